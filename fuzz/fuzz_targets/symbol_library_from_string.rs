@@ -0,0 +1,13 @@
+#![no_main]
+
+use kicad_generator::schematic::symbol_library::SymbolLibrary;
+use libfuzzer_sys::fuzz_target;
+
+// Run with `cargo +nightly fuzz run symbol_library_from_string`
+fuzz_target!(|src: &str| {
+    if let Ok(library) = SymbolLibrary::from_string(src, "Fuzz".to_string()) {
+        let written = library.to_string();
+        SymbolLibrary::from_string(&written, "Fuzz".to_string())
+            .expect("a library that was read should read back once written");
+    }
+});
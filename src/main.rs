@@ -1,9 +1,7 @@
 use std::{fs::OpenOptions, io::Write, path::Path, sync::OnceLock};
 
 use clap::Parser;
-use kicad_generator::{schematic::{symbol_library::SymbolLibraries, KicadSch, Position},
-                      PAGE_HEIGHT,
-                      PAGE_WIDTH};
+use kicad_generator::schematic::{symbol_library::SymbolLibraries, KicadSch, Position};
 use lazy_static::lazy_static;
 
 lazy_static! {
@@ -54,7 +52,9 @@ fn main() {
     let mut symbol_libraries = SymbolLibraries::get_statics();
     // let mut symbol_libraries =
     //     SymbolLibraries::all_from_dir(KICAD_3RD_PARTY_PATH.get().unwrap()).unwrap();
-    symbol_libraries.add_dir(KICAD_3RD_PARTY_PATH.get().unwrap()).unwrap();
+    symbol_libraries
+        .add_dir(KICAD_3RD_PARTY_PATH.get().unwrap())
+        .unwrap_or_else(|e| panic!("Unable to load symbol libraries: {e}"));
 
     for lib in symbol_libraries.iter() {
         let path = format!("static/included_libs/{}", lib.name);
@@ -69,12 +69,9 @@ fn main() {
     for symbol in &symbols_555s {
         println!("Found symbol: {}", symbol.name);
     }
+    let (x, y) = schematic.page().center();
     schematic
-        .place(symbols_555s[0], Position {
-            x: PAGE_WIDTH as f32 / 2.,
-            y: PAGE_HEIGHT as f32 / 2.,
-            rotation: None,
-        })
+        .place(symbols_555s[0], Position { x, y, rotation: None })
         .expect("Failed to place symbol");
 
     if let Some(output_path) = args.sheet_output {
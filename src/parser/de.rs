@@ -0,0 +1,372 @@
+use std::slice;
+
+use serde::de::{self,
+                value::{BorrowedStrDeserializer, SeqDeserializer},
+                DeserializeSeed,
+                EnumAccess,
+                IntoDeserializer,
+                MapAccess,
+                SeqAccess,
+                VariantAccess,
+                Visitor};
+
+use crate::parser::{self, ParseError, ParseErrorKind, SExpr, UNKNOWN_FIELD};
+
+/// Deserializes a value from a KiCad S-expression node, see the [module](crate::parser) for the
+/// mapping between serde and S-expressions.
+pub fn from_sexpr<'de, T: de::Deserialize<'de>>(node: &'de SExpr) -> Result<T, ParseError> {
+    T::deserialize(Deserializer::value(node))
+}
+
+/// Reads a value out of the arguments of a list, or out of a single atom/string.
+struct Deserializer<'de> {
+    args: &'de [SExpr],
+    node: &'de SExpr, // Used to locate errors
+}
+
+impl<'de> Deserializer<'de> {
+    fn value(node: &'de SExpr) -> Self {
+        match node {
+            SExpr::List(..) => Self { args: node.args(), node },
+            _ => Self { args: slice::from_ref(node), node },
+        }
+    }
+
+    fn first(&self) -> Result<&'de SExpr, ParseError> {
+        self.args.first().ok_or_else(|| self.node.missing("value".to_string()))
+    }
+
+    fn located<T>(&self, result: Result<T, ParseError>) -> Result<T, ParseError> {
+        result.map_err(|mut e| {
+            if let ParseErrorKind::MissingField { parent, .. } = &mut e.kind
+                && parent.is_empty()
+            {
+                *parent = self.node.name().unwrap_or_default().to_string();
+            }
+            e.or_span(self.node.span())
+        })
+    }
+}
+
+macro_rules! deserialize_number {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+                let value = self.first()?.expect_number()?;
+                self.located(visitor.$visit(value))
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = ParseError;
+
+    deserialize_number! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        match self.args {
+            [] => self.located(visitor.visit_unit()),
+            [SExpr::Atom(value, _) | SExpr::Str(value, _)] => {
+                self.located(visitor.visit_borrowed_str(value))
+            },
+            args if args.iter().any(|arg| arg.as_list().is_some()) => {
+                self.located(visitor.visit_map(StructMap::any(args)))
+            },
+            args => self.located(visitor.visit_seq(Seq(args.iter()))),
+        }
+    }
+
+    /// `(name yes)`, `(name no)`, or just `(name)`/`name` which mean `yes`.
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        let value = match self.args.first() {
+            Some(value) => value.expect_bool()?,
+            None => true,
+        };
+        self.located(visitor.visit_bool(value))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        let value = self.first()?.expect_string()?;
+        self.located(visitor.visit_borrowed_str(value))
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ParseError> {
+        Err(self.node.unexpected("a value that can be written as text".to_string()))
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        self.deserialize_bytes(visitor)
+    }
+
+    /// Absent values are handled by the struct they belong to, anything we are asked for exists.
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        self.located(visitor.visit_unit())
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ParseError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ParseError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        self.located(visitor.visit_seq(Seq(self.args.iter())))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ParseError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ParseError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        self.located(visitor.visit_map(StructMap::any(self.args)))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ParseError> {
+        self.located(visitor.visit_map(StructMap::new(self.args, name, fields)))
+    }
+
+    /// Unit variants are atoms, other variants are lists named after the variant.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ParseError> {
+        let value = self.first()?;
+        let variant = match value {
+            SExpr::List(..) => value.name().ok_or_else(|| value.missing("name".to_string()))?,
+            _ => value.expect_atom()?,
+        };
+        if !variants.contains(&variant) {
+            return Err(value.unknown_value(name, variant));
+        }
+        self.located(visitor.visit_enum(Enum { variant, value }))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParseError> {
+        visitor.visit_unit()
+    }
+}
+
+/// Consecutive values, each one being read on its own.
+struct Seq<'de>(slice::Iter<'de, SExpr>);
+
+impl<'de> SeqAccess<'de> for Seq<'de> {
+    type Error = ParseError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, ParseError> {
+        self.0.next().map(|value| seed.deserialize(Deserializer::value(value))).transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> { Some(self.0.len()) }
+}
+
+enum Entry<'de> {
+    Value(Deserializer<'de>),
+    Unknown(Vec<&'de SExpr>),
+}
+
+/// Fields of a struct, matched against the positional arguments and child lists of a node.
+struct StructMap<'de> {
+    entries: std::vec::IntoIter<(&'de str, Entry<'de>)>,
+    value:   Option<Entry<'de>>,
+}
+
+impl<'de> StructMap<'de> {
+    fn new(args: &'de [SExpr], name: &'static str, fields: &'static [&'static str]) -> Self {
+        let mut entries = vec![];
+        let mut unknown = vec![];
+
+        let mut positional = parser::struct_layout(name).1.peekable();
+        let mut args = args.iter().peekable();
+        while let (Some(field), Some(arg)) = (positional.peek(), args.peek()) {
+            if arg.as_list().is_some() {
+                break;
+            }
+            entries.push((*field, Entry::Value(Deserializer::value(arg))));
+            positional.next();
+            args.next();
+        }
+
+        for arg in args {
+            let name = match arg {
+                SExpr::List(..) => arg.name(),
+                SExpr::Atom(atom, _) => Some(atom.as_str()), // Bare flags like `hide`
+                SExpr::Str(..) => None,
+            };
+            let known = name.filter(|name| {
+                fields.contains(name)
+                    && *name != UNKNOWN_FIELD
+                    && !entries.iter().any(|(field, _)| field == name)
+            });
+            match (known, arg) {
+                (Some(name), SExpr::List(..)) => {
+                    entries.push((name, Entry::Value(Deserializer { args: arg.args(), node: arg })))
+                },
+                (Some(name), _) => {
+                    entries.push((name, Entry::Value(Deserializer { args: &[], node: arg })))
+                },
+                (None, _) => unknown.push(arg),
+            }
+        }
+
+        if fields.contains(&UNKNOWN_FIELD) {
+            entries.push((UNKNOWN_FIELD, Entry::Unknown(unknown)));
+        }
+
+        Self { entries: entries.into_iter(), value: None }
+    }
+
+    /// Map of every child list, keyed by their name, used when the expected fields are not known.
+    fn any(args: &'de [SExpr]) -> Self {
+        let entries = args
+            .iter()
+            .filter_map(|arg| {
+                Some((arg.name()?, Entry::Value(Deserializer { args: arg.args(), node: arg })))
+            })
+            .collect::<Vec<_>>();
+        Self { entries: entries.into_iter(), value: None }
+    }
+}
+
+impl<'de> MapAccess<'de> for StructMap<'de> {
+    type Error = ParseError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ParseError> {
+        let Some((key, value)) = self.entries.next() else { return Ok(None) };
+        self.value = Some(value);
+        seed.deserialize(BorrowedStrDeserializer::new(key)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ParseError> {
+        match self.value.take().expect("next_value_seed called before next_key_seed") {
+            Entry::Value(deserializer) => seed.deserialize(deserializer),
+            Entry::Unknown(nodes) => {
+                // Unknown nodes are kept as `SExpr`, which deserialize from their text form
+                let nodes = nodes.into_iter().map(|node| node.to_string().into_deserializer());
+                seed.deserialize(SeqDeserializer::new(nodes))
+            },
+        }
+    }
+}
+
+struct Enum<'de> {
+    variant: &'de str,
+    value:   &'de SExpr,
+}
+
+impl<'de> EnumAccess<'de> for Enum<'de> {
+    type Error = ParseError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), ParseError> {
+        let variant = seed.deserialize(BorrowedStrDeserializer::new(self.variant))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Enum<'de> {
+    type Error = ParseError;
+
+    fn unit_variant(self) -> Result<(), ParseError> { Ok(()) }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, ParseError> {
+        seed.deserialize(Deserializer { args: self.value.args(), node: self.value })
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ParseError> {
+        de::Deserializer::deserialize_tuple(
+            Deserializer { args: self.value.args(), node: self.value },
+            len,
+            visitor,
+        )
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ParseError> {
+        de::Deserializer::deserialize_struct(
+            Deserializer { args: self.value.args(), node: self.value },
+            "",
+            fields,
+            visitor,
+        )
+    }
+}
@@ -0,0 +1,152 @@
+use std::{error::Error,
+          fmt::{Display, Formatter},
+          path::{Path, PathBuf}};
+
+use serde::{de, ser};
+
+use crate::parser::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedToken { expected: String, found: String },
+    MissingField { field: String, parent: String },
+    InvalidNumber(String),
+    UnknownEnumValue { ty: &'static str, value: String },
+    Eof { expected: String },
+    UnsupportedVersion(u32), // Older than KiCad 6
+    TooDeep(usize),          // Lists nested deeper than the limit
+    Io(String),
+    Custom(String), // Reported by serde
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseErrorKind::UnexpectedToken { expected, found } => {
+                f.write_fmt(format_args!("expected {expected}, but got {found}"))
+            },
+            ParseErrorKind::MissingField { field, parent } => {
+                f.write_fmt(format_args!("missing {field} in ({parent} ...)"))
+            },
+            ParseErrorKind::InvalidNumber(value) => {
+                f.write_fmt(format_args!("`{value}` is not a valid number"))
+            },
+            ParseErrorKind::UnknownEnumValue { ty, value } => {
+                f.write_fmt(format_args!("unknown {ty} `{value}`"))
+            },
+            ParseErrorKind::Eof { expected } => {
+                f.write_fmt(format_args!("unexpected end of input, expected {expected}"))
+            },
+            ParseErrorKind::UnsupportedVersion(version) => f.write_fmt(format_args!(
+                "unsupported file format version {version}, only files saved by KiCad 6 or newer \
+                 can be read"
+            )),
+            ParseErrorKind::TooDeep(limit) => {
+                f.write_fmt(format_args!("lists are nested more than {limit} levels deep"))
+            },
+            ParseErrorKind::Io(error) | ParseErrorKind::Custom(error) => f.write_str(error),
+        }
+    }
+}
+
+/// Line, column (both 1-based) and source line of an error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub line:    usize,
+    pub column:  usize,
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind:     ParseErrorKind,
+    pub span:     Span,
+    pub location: Option<Box<Location>>, // Filled once the error reaches the source it comes from
+    pub path:     Option<PathBuf>,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Span) -> Self {
+        Self { kind, span, location: None, path: None }
+    }
+
+    pub fn io(error: impl Display, path: impl AsRef<Path>) -> Self {
+        Self::new(ParseErrorKind::Io(error.to_string()), Span::default())
+            .with_path(path.as_ref().to_path_buf())
+    }
+
+    /// Resolves the span of the error into a line and column of `src`.
+    pub fn locate(mut self, src: &str) -> Self {
+        if matches!(self.kind, ParseErrorKind::Io(_)) || self.location.is_some() {
+            return self;
+        }
+        let offset = (0..=self.span.start.min(src.len()))
+            .rev()
+            .find(|&i| src.is_char_boundary(i))
+            .unwrap_or(0);
+        let line_start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[offset..].find('\n').map_or(src.len(), |i| offset + i);
+        self.location = Some(Box::new(Location {
+            line:    src[..offset].matches('\n').count() + 1,
+            column:  src[line_start..offset].chars().count() + 1,
+            snippet: src[line_start..line_end].trim_end().to_string(),
+        }));
+        self
+    }
+
+    /// Sets the span of errors that were raised without one, like the ones coming from serde.
+    pub fn or_span(mut self, span: Span) -> Self {
+        if self.span == Span::default() {
+            self.span = span;
+        }
+        self
+    }
+
+    pub fn with_path(mut self, path: PathBuf) -> Self {
+        self.path = Some(path);
+        self
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(path) = &self.path {
+            f.write_fmt(format_args!("{}:", path.to_string_lossy()))?;
+        }
+        match self.location.as_deref() {
+            Some(Location { line, column, snippet }) => {
+                f.write_fmt(format_args!("{line}:{column}: {}\n", self.kind))?;
+                let marker = snippet
+                    .chars()
+                    .take(column - 1)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect::<String>();
+                f.write_fmt(format_args!("    {snippet}\n    {marker}^"))
+            },
+            None if self.path.is_some() => f.write_fmt(format_args!(" {}", self.kind)),
+            None => f.write_fmt(format_args!("{}", self.kind)),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl de::Error for ParseError {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self::new(ParseErrorKind::Custom(msg.to_string()), Span::default())
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Self::new(
+            ParseErrorKind::MissingField {
+                field:  format!("({field} ...)"),
+                parent: String::new(),
+            },
+            Span::default(),
+        )
+    }
+}
+
+impl ser::Error for ParseError {
+    fn custom<T: Display>(msg: T) -> Self { <Self as de::Error>::custom(msg) }
+}
@@ -0,0 +1,164 @@
+use std::fmt::{Display, Formatter};
+
+use crate::parser::{ParseError, ParseErrorKind, Span};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token<'a> {
+    Open,
+    Close,
+    Atom(&'a str),
+    Str(&'a str), // Content between the quotes, escape sequences are kept as-is, see `unescape`
+}
+
+impl Display for Token<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Open => f.write_str("`(`"),
+            Token::Close => f.write_str("`)`"),
+            Token::Atom(atom) => f.write_fmt(format_args!("`{atom}`")),
+            Token::Str(str) => f.write_fmt(format_args!("\"{str}\"")),
+        }
+    }
+}
+
+/// Splits a KiCad S-expression source into tokens, keeping track of their position in the source.
+pub struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self { Self { src, pos: 0 } }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.src[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn is_delimiter(c: u8) -> bool { c.is_ascii_whitespace() || matches!(c, b'(' | b')' | b'"') }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<(Token<'a>, Span), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.skip_whitespace();
+        let bytes = self.src.as_bytes();
+        let start = self.pos;
+
+        let token = match bytes.get(start)? {
+            b'(' => {
+                self.pos += 1;
+                Token::Open
+            },
+            b')' => {
+                self.pos += 1;
+                Token::Close
+            },
+            b'"' => {
+                let mut end = start + 1;
+                loop {
+                    match bytes.get(end) {
+                        None => {
+                            self.pos = bytes.len();
+                            return Some(Err(ParseError::new(
+                                ParseErrorKind::Eof { expected: "closing `\"`".to_string() },
+                                Span { start, end: bytes.len() },
+                            )));
+                        },
+                        Some(b'\\') => end += 2,
+                        Some(b'"') => break,
+                        Some(_) => end += 1,
+                    }
+                }
+                self.pos = end + 1;
+                Token::Str(&self.src[start + 1..end])
+            },
+            _ => {
+                let len = bytes[start..].iter().position(|&c| Self::is_delimiter(c));
+                self.pos = len.map_or(bytes.len(), |len| start + len);
+                Token::Atom(&self.src[start..self.pos])
+            },
+        };
+
+        Some(Ok((token, Span { start, end: self.pos })))
+    }
+}
+
+/// Resolves the escape sequences of a quoted string, the way KiCad's lexer does. Unknown
+/// sequences are kept as-is.
+pub fn unescape(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let Some(escaped) = chars.next() else {
+            out.push('\\');
+            break;
+        };
+        match escaped {
+            '"' | '\\' => out.push(escaped),
+            'a' => out.push('\x07'),
+            'b' => out.push('\x08'),
+            'f' => out.push('\x0c'),
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            'v' => out.push('\x0b'),
+            'x' => {
+                let mut value = 0;
+                let mut digits = 0;
+                while digits < 2
+                    && let Some(digit) = chars.peek().and_then(|c| c.to_digit(16))
+                {
+                    value = value * 16 + digit;
+                    digits += 1;
+                    chars.next();
+                }
+                match char::from_u32(value).filter(|_| digits > 0) {
+                    Some(c) => out.push(c),
+                    None => out.push_str("\\x"),
+                }
+            },
+            '0'..='7' => {
+                let mut value = escaped.to_digit(8).unwrap_or_default();
+                for _ in 0..2 {
+                    let Some(digit) = chars.peek().and_then(|c| c.to_digit(8)) else { break };
+                    value = value * 8 + digit;
+                    chars.next();
+                }
+                out.extend(char::from_u32(value));
+            },
+            _ => {
+                out.push('\\');
+                out.push(escaped);
+            },
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_keep_their_place_in_the_source() {
+        let src = "(pin \"~\"\n\t(at -1.27 0))";
+        let tokens: Vec<_> = Lexer::new(src).map(Result::unwrap).collect();
+        let texts: Vec<_> = tokens.iter().map(|(_, span)| &src[span.start..span.end]).collect();
+        assert_eq!(texts, ["(", "pin", "\"~\"", "(", "at", "-1.27", "0", ")", ")"]);
+        assert_eq!(tokens[2].0, Token::Str("~"));
+        assert_eq!(tokens[5].0, Token::Atom("-1.27"));
+    }
+
+    #[test]
+    fn unterminated_strings_are_rejected() {
+        let error = Lexer::new("(name \"VCC").nth(2).unwrap().unwrap_err();
+        assert!(matches!(error.kind, ParseErrorKind::Eof { .. }));
+        assert_eq!(error.span, Span { start: 6, end: 10 });
+    }
+}
@@ -1,25 +1,454 @@
-use regex::Regex;
+//! Reading and writing of KiCad S-expressions.
+//!
+//! Files are tokenized into an [`SExpr`] tree, which is then either walked by hand by the
+//! `extract_from` functions or mapped onto types through serde with [`from_sexpr`] and
+//! [`to_sexpr`]. The serde mapping follows the shape of KiCad files:
+//!
+//! - A struct is a list named after the struct (`#[serde(rename = "stroke")]`) and each field is a
+//!   child list named after the field: `(stroke (width 0.254) (type default))`. When a struct is
+//!   the value of a field, the list is named after the field instead.
+//! - Fields listed after the name of the struct are positional, they are written as bare values
+//!   right after the name of the list, in that order: `#[serde(rename = "alternate name
+//!   electrical_type pin_graphic_style")]` for `(alternate "TRIG" input inverted)`. Keeping this
+//!   out of the field names leaves the JSON dumps of the bundled libraries with plain keys.
+//! - A field named [`UNKNOWN_FIELD`] of type `Vec<SExpr>` receives every child that matches no
+//!   other field, and writes them back as-is.
+//! - `bool`s are `yes`/`no`. When reading, a list without value (`(do_not_autoplace)`) or a bare
+//!   atom named after the field (`hide`) both mean `yes`.
+//! - `None` is not written at all, and a missing child reads as `None`.
+//! - Unit enum variants are atoms (`#[serde(rename_all = "snake_case")]`), other variants are
+//!   lists named after the variant. Tuples and sequences are written inline: `(size 1.27 1.27)`.
 
-pub fn expect_str<'a>(content: &'a str, pattern: &'static str) -> Result<&'a str, String> {
-    if let Some(stripped) = content.strip_prefix(pattern) {
-        Ok(stripped.trim())
-    } else {
-        Err(format!("Expected {pattern}, but got {}", &content[..20.max(pattern.len() + 10)]))
+mod de;
+mod error;
+mod lexer;
+mod pretty;
+mod ser;
+
+use std::{fmt::{Display, Formatter, Write},
+          str::FromStr};
+
+pub use de::from_sexpr;
+pub use error::{Location, ParseError, ParseErrorKind};
+use lexer::{Lexer, Token};
+pub use pretty::{prettify, write_pretty};
+pub use ser::to_sexpr;
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Name of the field collecting the children a type does not know about, see the module docs.
+pub const UNKNOWN_FIELD: &str = "unknown";
+
+/// Splits the serde name of a struct into the name of its list and its positional fields, see the
+/// module docs.
+fn struct_layout(name: &'static str) -> (&'static str, impl Iterator<Item = &'static str>) {
+    let mut words = name.split(' ');
+    (words.next().unwrap_or_default(), words)
+}
+
+/// Byte range of a node in the parsed source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end:   usize,
+}
+
+/// A node of a KiCad S-expression tree. Spans are ignored when comparing nodes, and nodes are
+/// serialized as their text.
+#[derive(Debug, Clone)]
+pub enum SExpr {
+    Atom(String, Span),     // Bare token, e.g. `yes`, `passive` or `1.27`
+    Str(String, Span),      // Quoted string, without the quotes
+    List(Vec<SExpr>, Span), // `( ... )`
+}
+
+/// Deepest nesting of lists accepted by [`parse`]. KiCad files stay below 10 levels, the limit
+/// keeps the recursive walks over the tree (writing, comparing, dropping) from overflowing the
+/// stack on malformed input.
+pub const MAX_DEPTH: usize = 256;
+
+/// Parses a source containing exactly one top-level S-expression.
+pub fn parse(src: &str) -> Result<SExpr, ParseError> {
+    // Lists being built, with the position of their opening parenthesis
+    let mut stack: Vec<(Vec<SExpr>, usize)> = vec![];
+    let mut root = None;
+
+    for token in Lexer::new(src) {
+        let (token, span) = token?;
+        if root.is_some() {
+            return Err(ParseError::new(
+                ParseErrorKind::UnexpectedToken {
+                    expected: "end of input".to_string(),
+                    found:    token.to_string(),
+                },
+                span,
+            ));
+        }
+        let node = match token {
+            Token::Open => {
+                if stack.len() == MAX_DEPTH {
+                    return Err(ParseError::new(ParseErrorKind::TooDeep(MAX_DEPTH), span));
+                }
+                stack.push((vec![], span.start));
+                continue;
+            },
+            Token::Close => {
+                let (items, start) = stack.pop().ok_or_else(|| {
+                    ParseError::new(
+                        ParseErrorKind::UnexpectedToken {
+                            expected: "`(`".to_string(),
+                            found:    token.to_string(),
+                        },
+                        span,
+                    )
+                })?;
+                SExpr::List(items, Span { start, end: span.end })
+            },
+            Token::Atom(atom) => SExpr::Atom(atom.to_string(), span),
+            Token::Str(str) => SExpr::Str(lexer::unescape(str), span),
+        };
+        match stack.last_mut() {
+            Some((items, _)) => items.push(node),
+            None => root = Some(node),
+        }
     }
+
+    if let Some((_, start)) = stack.last() {
+        return Err(ParseError::new(
+            ParseErrorKind::Eof { expected: "`)`".to_string() },
+            Span { start: *start, end: src.len() },
+        ));
+    }
+    root.ok_or_else(|| {
+        ParseError::new(
+            ParseErrorKind::Eof { expected: "an S-expression".to_string() },
+            Span { start: src.len(), end: src.len() },
+        )
+    })
 }
 
-pub fn expect_regex<'a>(
-    content: &'a str,
-    pattern: &'static str,
-) -> Result<(&'a str, &'a str), String> {
-    let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
-    if let Some(found) = regex.find(content) {
-        if found.start() != 0 {
-            Err(format!("Expected {regex}, but got {}", &content[..20.max(pattern.len() + 10)]))
+impl SExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            SExpr::Atom(_, span) | SExpr::Str(_, span) | SExpr::List(_, span) => *span,
+        }
+    }
+
+    pub fn as_atom(&self) -> Option<&str> {
+        match self {
+            SExpr::Atom(atom, _) => Some(atom),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[SExpr]> {
+        match self {
+            SExpr::List(items, _) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// The leading atom of a list, e.g. `pin` for `(pin passive line ...)`.
+    pub fn name(&self) -> Option<&str> { self.as_list()?.first()?.as_atom() }
+
+    pub fn is_list(&self, name: &str) -> bool { self.name() == Some(name) }
+
+    /// Items following the name of a list, or an empty slice for anything else.
+    pub fn args(&self) -> &[SExpr] {
+        match self.as_list() {
+            Some([_, args @ ..]) => args,
+            _ => &[],
+        }
+    }
+
+    /// First child list named `name`.
+    pub fn child(&self, name: &str) -> Option<&SExpr> {
+        self.args().iter().find(|arg| arg.is_list(name))
+    }
+
+    /// Children not in `known`, to be written back as-is: child lists by their name, and bare
+    /// values such as flags of newer versions once the first `positional` ones are skipped.
+    pub fn unknown_children(&self, positional: usize, known: &[&str]) -> Vec<SExpr> {
+        let mut positional = positional;
+        self.args()
+            .iter()
+            .filter(|arg| match arg {
+                SExpr::List(..) => arg.name().is_some_and(|name| !known.contains(&name)),
+                SExpr::Atom(atom, _) if known.contains(&atom.as_str()) => false,
+                _ if positional > 0 => {
+                    positional -= 1;
+                    false
+                },
+                _ => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Checks that this node is a list named `name` and returns its arguments.
+    pub fn expect_list(&self, name: &str) -> Result<&[SExpr], ParseError> {
+        if self.is_list(name) {
+            Ok(self.args())
         } else {
-            Ok((&content[..found.end()], content[found.end()..].trim()))
+            Err(self.unexpected(format!("({name} ...)")))
+        }
+    }
+
+    /// Mandatory child list named `name`.
+    pub fn expect_child(&self, name: &str) -> Result<&SExpr, ParseError> {
+        self.child(name).ok_or_else(|| self.missing(format!("({name} ...)")))
+    }
+
+    /// Mandatory argument at `index`, not counting the name of the list.
+    pub fn expect_arg(&self, index: usize) -> Result<&SExpr, ParseError> {
+        self.args().get(index).ok_or_else(|| self.missing(format!("argument {}", index + 1)))
+    }
+
+    pub fn expect_atom(&self) -> Result<&str, ParseError> {
+        self.as_atom().ok_or_else(|| self.unexpected("an atom".to_string()))
+    }
+
+    /// Content of a quoted string. Bare atoms are accepted too as KiCad is lenient about it.
+    pub fn expect_string(&self) -> Result<&str, ParseError> {
+        match self {
+            SExpr::Str(str, _) | SExpr::Atom(str, _) => Ok(str),
+            SExpr::List(..) => Err(self.unexpected("a string".to_string())),
         }
-    } else {
-        Err(format!("Expected {regex}, but got {}", &content[..20.max(pattern.len() + 10)]))
+    }
+
+    pub fn expect_number<T: FromStr>(&self) -> Result<T, ParseError> {
+        let value = self.expect_string()?;
+        value.parse().map_err(|_| {
+            ParseError::new(ParseErrorKind::InvalidNumber(value.to_string()), self.span())
+        })
+    }
+
+    /// `yes`/`no` value.
+    pub fn expect_bool(&self) -> Result<bool, ParseError> {
+        match self.expect_atom()? {
+            "yes" => Ok(true),
+            "no" => Ok(false),
+            other => Err(self.unknown_value("boolean", other)),
+        }
+    }
+
+    pub fn unexpected(&self, expected: String) -> ParseError {
+        ParseError::new(
+            ParseErrorKind::UnexpectedToken { expected, found: self.describe() },
+            self.span(),
+        )
+    }
+
+    pub fn missing(&self, field: String) -> ParseError {
+        ParseError::new(
+            ParseErrorKind::MissingField {
+                field,
+                parent: self.name().unwrap_or_default().to_string(),
+            },
+            self.span(),
+        )
+    }
+
+    pub fn unknown_value(&self, ty: &'static str, value: &str) -> ParseError {
+        ParseError::new(
+            ParseErrorKind::UnknownEnumValue { ty, value: value.to_string() },
+            self.span(),
+        )
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            SExpr::Atom(atom, _) => format!("`{atom}`"),
+            SExpr::Str(str, _) => Quoted(str).to_string(),
+            SExpr::List(..) => format!("({} ...)", self.name().unwrap_or("")),
+        }
+    }
+}
+
+impl PartialEq for SExpr {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (SExpr::Atom(a, _), SExpr::Atom(b, _)) | (SExpr::Str(a, _), SExpr::Str(b, _)) => a == b,
+            (SExpr::List(a, _), SExpr::List(b, _)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Serialize for SExpr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for SExpr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        parse(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+impl Display for SExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SExpr::Atom(atom, _) => f.write_str(atom),
+            SExpr::Str(str, _) => f.write_fmt(format_args!("{}", Quoted(str))),
+            SExpr::List(items, _) => {
+                f.write_str("(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    f.write_fmt(format_args!("{item}"))?;
+                }
+                f.write_str(")")
+            },
+        }
+    }
+}
+
+/// Writes a string between quotes, escaping it the way KiCad does.
+pub struct Quoted<'a>(pub &'a str);
+
+impl Display for Quoted<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                _ => f.write_char(c)?,
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+/// Writes each node on its own line, used to emit back nodes that were not understood.
+pub fn write_unknown(f: &mut Formatter<'_>, nodes: &[SExpr]) -> std::fmt::Result {
+    for node in nodes {
+        f.write_fmt(format_args!("\n{node}"))?;
+    }
+    Ok(())
+}
+
+/// Writes `value` through the serde mapping, used by the `Display` impls of serde-driven types.
+pub fn write_sexpr<T: Serialize + ?Sized>(f: &mut Formatter<'_>, value: &T) -> std::fmt::Result {
+    let node = to_sexpr(value).map_err(|_| std::fmt::Error)?;
+    f.write_fmt(format_args!("{node}"))
+}
+
+/// Used with `skip_serializing_if` for flags that KiCad only writes when set.
+pub fn is_false(value: &bool) -> bool { !value }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+
+    fn list(items: Vec<SExpr>) -> SExpr { SExpr::List(items, Span::default()) }
+
+    fn atom() -> impl Strategy<Value = SExpr> {
+        "[a-z0-9_.+-]{1,8}".prop_map(|atom| SExpr::Atom(atom, Span::default()))
+    }
+
+    /// Any node, strings holding any character.
+    pub fn sexpr() -> impl Strategy<Value = SExpr> {
+        let string = any::<String>().prop_map(|str| SExpr::Str(str, Span::default()));
+        prop_oneof![atom(), string]
+            .prop_recursive(4, 32, 6, |inner| vec(inner, 0..6).prop_map(list))
+    }
+
+    /// Lists no type knows about, as kept in the `unknown` fields.
+    pub fn unknown_nodes() -> impl Strategy<Value = Vec<SExpr>> {
+        let node = ("x_[a-z_]{1,8}", vec(sexpr(), 0..4)).prop_map(|(name, args)| {
+            list([SExpr::Atom(name, Span::default())].into_iter().chain(args).collect())
+        });
+        vec(node, 0..3)
+    }
+
+    proptest! {
+        #[test]
+        fn written_nodes_parse_back(node in sexpr()) {
+            prop_assert_eq!(parse(&node.to_string())?, node);
+        }
+
+        #[test]
+        fn prettify_only_changes_whitespace(node in sexpr()) {
+            let pretty = prettify(&node.to_string());
+            prop_assert_eq!(parse(&pretty)?, node);
+            prop_assert_eq!(prettify(&pretty), pretty);
+        }
+    }
+
+    #[test]
+    fn nodes_span_their_source() {
+        let src = "(property \"Value\" R (at 0 0 90))";
+        let node = parse(src).unwrap();
+        assert_eq!(node.span(), Span { start: 0, end: src.len() });
+        assert_eq!(node.name(), Some("property"));
+        assert_eq!(node.expect_arg(0).unwrap().expect_string().unwrap(), "Value");
+        let at = node.expect_child("at").unwrap();
+        assert_eq!(&src[at.span().start..at.span().end], "(at 0 0 90)");
+        assert_eq!(at.expect_arg(2).unwrap().expect_number::<f32>().unwrap(), 90.);
+        assert!(node.child("effects").is_none());
+    }
+
+    #[test]
+    fn unbalanced_sources_are_rejected() {
+        for src in ["", "(at 0 0", "(at 0 0))", "(at) (at)"] {
+            assert!(parse(src).is_err(), "{src}");
+        }
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Style {
+        Solid,
+        DashDot,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    #[serde(rename = "line name")]
+    struct Line {
+        name:    String,
+        size:    (f32, f32),
+        style:   Style,
+        #[serde(default, skip_serializing_if = "is_false")]
+        hide:    bool,
+        offset:  Option<f32>,
+        #[serde(default)]
+        unknown: Vec<SExpr>,
+    }
+
+    #[test]
+    fn types_map_onto_lists() {
+        let src = "(line \"A\" (size 1.27 2.54) hide (style dash_dot) (x_future 1))";
+        let line: Line = from_sexpr(&parse(src).unwrap()).unwrap();
+        let unknown = vec![parse("(x_future 1)").unwrap()];
+        let expected = Line {
+            name: "A".to_string(),
+            size: (1.27, 2.54),
+            style: Style::DashDot,
+            hide: true,
+            offset: None,
+            unknown,
+        };
+        assert_eq!(line, expected);
+
+        let written = "(line \"A\" (size 1.27 2.54) (style dash_dot) (hide yes) (x_future 1))";
+        assert_eq!(to_sexpr(&line).unwrap(), parse(written).unwrap());
+        let line = Line { style: Style::Solid, hide: false, offset: Some(0.5), ..line };
+        let written = "(line \"A\" (size 1.27 2.54) (style solid) (offset 0.5) (x_future 1))";
+        assert_eq!(to_sexpr(&line).unwrap(), parse(written).unwrap());
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let src = format!("{}{}", "(".repeat(MAX_DEPTH + 1), ")".repeat(MAX_DEPTH + 1));
+        assert_eq!(parse(&src).unwrap_err().kind, ParseErrorKind::TooDeep(MAX_DEPTH));
+        let src = format!("{}{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(parse(&src).is_ok());
     }
 }
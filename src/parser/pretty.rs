@@ -0,0 +1,147 @@
+use std::fmt::{Display, Formatter};
+
+/// Lists of points are kept on a single line until this column, like KiCad does.
+const XY_COLUMN_LIMIT: usize = 99;
+/// Past this column, the tokens of a list are wrapped on their own lines.
+const TOKEN_WRAP_COLUMN: usize = 72;
+const XY_TOKEN: &[u8] = b"(xy ";
+
+/// Re-indents an S-expression the way KiCad 9 saves its files, so that a file written by this
+/// crate and saved again by KiCad stays the same.
+///
+/// This follows `KICAD_FORMAT::Prettify`: every list starts on a new line indented with one tab
+/// per level, a list closes on its own line when it contains other lists, and runs of whitespace
+/// are collapsed into a single space. Only the whitespace of `src` is changed.
+pub fn prettify(src: &str) -> String {
+    let bytes = src.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+
+    let mut depth = 0;
+    let mut column = 0;
+    let mut last_non_whitespace = 0;
+    let mut in_quote = false;
+    let mut inserted_space = false;
+    let mut in_multi_line_list = false;
+    let mut in_xy = false;
+    let mut backslashes = 0; // Consecutive backslashes, to know if a quote is escaped
+
+    let newline = |out: &mut Vec<u8>, depth: usize| {
+        out.push(b'\n');
+        out.extend(std::iter::repeat_n(b'\t', depth));
+    };
+
+    for (i, &c) in bytes.iter().enumerate() {
+        if is_whitespace(c) && !in_quote {
+            let next = bytes[i..].iter().find(|&&c| !is_whitespace(c)).copied();
+            if !inserted_space
+                && depth > 0
+                && last_non_whitespace != b'('
+                && !matches!(next, Some(b'(' | b')'))
+            {
+                if in_xy || column < TOKEN_WRAP_COLUMN {
+                    out.push(b' ');
+                    column += 1;
+                } else {
+                    newline(&mut out, depth);
+                    column = depth;
+                    in_multi_line_list = true;
+                }
+                inserted_space = true;
+            }
+            continue;
+        }
+
+        inserted_space = false;
+        match c {
+            b'(' if !in_quote => {
+                let is_xy = bytes[i..].starts_with(XY_TOKEN);
+                if out.is_empty() {
+                    out.push(b'(');
+                    column += 1;
+                } else if in_xy && is_xy && column < XY_COLUMN_LIMIT {
+                    out.extend(b" (");
+                    column += 2;
+                } else {
+                    newline(&mut out, depth);
+                    out.push(b'(');
+                    column = depth + 1;
+                }
+                in_xy = is_xy;
+                depth += 1;
+            },
+            b')' if !in_quote => {
+                depth = depth.saturating_sub(1);
+                if last_non_whitespace == b')' || in_multi_line_list {
+                    newline(&mut out, depth);
+                    out.push(b')');
+                    column = depth + 1;
+                    in_multi_line_list = false;
+                } else {
+                    out.push(b')');
+                    column += 1;
+                }
+            },
+            _ => {
+                if c == b'\\' {
+                    backslashes += 1;
+                } else {
+                    if c == b'"' && backslashes % 2 == 0 {
+                        in_quote = !in_quote;
+                    }
+                    backslashes = 0;
+                }
+                out.push(c);
+                column += 1;
+            },
+        }
+        last_non_whitespace = c;
+    }
+
+    out.push(b'\n');
+    // Only ASCII bytes were inserted or removed, and never inside a character
+    String::from_utf8(out).expect("prettify keeps the source valid UTF-8")
+}
+
+fn is_whitespace(c: u8) -> bool { matches!(c, b' ' | b'\t' | b'\n' | b'\r') }
+
+/// Writes the output of `value`'s `Display` through [`prettify`].
+pub fn write_pretty(f: &mut Formatter<'_>, value: impl Display) -> std::fmt::Result {
+    f.write_str(&prettify(&value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_are_indented_with_tabs() {
+        let src = "(symbol (at 0 0 90) (property \"Value\"  \"a  \\\" (b)\" (effects (hide yes))))";
+        let expected = "(symbol\n\t(at 0 0 90)\n\t(property \"Value\" \"a  \\\" \
+                        (b)\"\n\t\t(effects\n\t\t\t(hide yes)\n\t\t)\n\t)\n)\n";
+        assert_eq!(prettify(src), expected);
+    }
+
+    #[test]
+    fn points_share_lines() {
+        let points: String = (0..12).map(|i| format!(" (xy {}.27 -{i}.54)", 10 * i)).collect();
+        let pretty = prettify(&format!("(polyline (pts{points}))"));
+        let lines: Vec<_> = pretty.lines().collect();
+        // Each line of points goes past the column where tokens wrap
+        let expected = [
+            "\t\t(xy 0.27 -0.54) (xy 10.27 -1.54) (xy 20.27 -2.54) (xy 30.27 -3.54) (xy 40.27 \
+             -4.54) (xy 50.27 -5.54)",
+            "\t\t(xy 60.27 -6.54) (xy 70.27 -7.54) (xy 80.27 -8.54) (xy 90.27 -9.54) (xy 100.27 \
+             -10.54) (xy 110.27 -11.54)",
+        ];
+        assert_eq!(lines, [&["(polyline", "\t(pts"], &expected[..], &["\t)", ")"]].concat());
+    }
+
+    #[test]
+    fn long_lists_wrap() {
+        let words = "word ".repeat(20);
+        let pretty = prettify(&format!("(tokens {words})"));
+        let (first, rest) = pretty.split_once('\n').unwrap();
+        assert_eq!(first.len(), "(tokens".len() + 13 * " word".len());
+        assert_eq!(rest, format!("\t{}\n)\n", ["word"; 7].join(" ")));
+    }
+}
@@ -0,0 +1,309 @@
+use serde::{ser::{self, Impossible},
+            Serialize};
+
+use crate::parser::{self, ParseError, SExpr, Span, UNKNOWN_FIELD};
+
+/// Serializes a value into a KiCad S-expression node, see the [module](crate::parser) for the
+/// mapping between serde and S-expressions.
+pub fn to_sexpr<T: Serialize + ?Sized>(value: &T) -> Result<SExpr, ParseError> {
+    match value.serialize(Serializer)? {
+        Fragment::Value(value) => Ok(value),
+        Fragment::List(name, args) => Ok(list(name, args)),
+        Fragment::Absent => Err(ser::Error::custom("Nothing to serialize")),
+    }
+}
+
+/// What a value turns into, before knowing where it is placed.
+enum Fragment {
+    Absent,                            // `None`, not written at all
+    Value(SExpr),                      // Atom or string
+    List(Option<String>, Vec<SExpr>), // Arguments of a list, with the name of the list if known
+}
+
+impl Fragment {
+    /// Arguments this fragment adds to a list named after the field holding it.
+    fn into_args(self) -> Option<Vec<SExpr>> {
+        match self {
+            Fragment::Absent => None,
+            Fragment::Value(value) => Some(vec![value]),
+            Fragment::List(_, args) => Some(args),
+        }
+    }
+
+    /// The fragment as a standalone node, when it is an element of a sequence.
+    fn into_node(self) -> Option<SExpr> {
+        match self {
+            Fragment::Absent => None,
+            Fragment::Value(value) => Some(value),
+            Fragment::List(name, args) => Some(list(name, args)),
+        }
+    }
+}
+
+fn list(name: Option<String>, args: Vec<SExpr>) -> SExpr {
+    let name = name.map(|name| SExpr::Atom(name, Span::default()));
+    SExpr::List(name.into_iter().chain(args).collect(), Span::default())
+}
+
+fn atom(value: impl ToString) -> Fragment {
+    Fragment::Value(SExpr::Atom(value.to_string(), Span::default()))
+}
+
+struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Error = ParseError;
+    type Ok = Fragment;
+    type SerializeMap = Impossible<Fragment, ParseError>;
+    type SerializeSeq = Seq;
+    type SerializeStruct = Struct;
+    type SerializeStructVariant = Struct;
+    type SerializeTuple = Seq;
+    type SerializeTupleStruct = Seq;
+    type SerializeTupleVariant = Seq;
+
+    fn serialize_bool(self, v: bool) -> Result<Fragment, ParseError> {
+        Ok(atom(if v { "yes" } else { "no" }))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Fragment, ParseError> { Ok(atom(v)) }
+
+    fn serialize_i16(self, v: i16) -> Result<Fragment, ParseError> { Ok(atom(v)) }
+
+    fn serialize_i32(self, v: i32) -> Result<Fragment, ParseError> { Ok(atom(v)) }
+
+    fn serialize_i64(self, v: i64) -> Result<Fragment, ParseError> { Ok(atom(v)) }
+
+    fn serialize_u8(self, v: u8) -> Result<Fragment, ParseError> { Ok(atom(v)) }
+
+    fn serialize_u16(self, v: u16) -> Result<Fragment, ParseError> { Ok(atom(v)) }
+
+    fn serialize_u32(self, v: u32) -> Result<Fragment, ParseError> { Ok(atom(v)) }
+
+    fn serialize_u64(self, v: u64) -> Result<Fragment, ParseError> { Ok(atom(v)) }
+
+    fn serialize_f32(self, v: f32) -> Result<Fragment, ParseError> { Ok(atom(v)) }
+
+    fn serialize_f64(self, v: f64) -> Result<Fragment, ParseError> { Ok(atom(v)) }
+
+    fn serialize_char(self, v: char) -> Result<Fragment, ParseError> {
+        self.serialize_str(&v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Fragment, ParseError> {
+        Ok(Fragment::Value(SExpr::Str(v.to_string(), Span::default())))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Fragment, ParseError> {
+        Err(ser::Error::custom("Bytes can't be written in an S-expression"))
+    }
+
+    fn serialize_none(self) -> Result<Fragment, ParseError> { Ok(Fragment::Absent) }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Fragment, ParseError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Fragment, ParseError> { Ok(Fragment::List(None, vec![])) }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Fragment, ParseError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Fragment, ParseError> {
+        Ok(atom(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Fragment, ParseError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Fragment, ParseError> {
+        let args = value.serialize(Serializer)?.into_args().unwrap_or_default();
+        Ok(Fragment::List(None, vec![list(Some(variant.to_string()), args)]))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Seq, ParseError> {
+        Ok(Seq { name: None, items: vec![] })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Seq, ParseError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Seq, ParseError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Seq, ParseError> {
+        Ok(Seq { name: Some(variant), items: vec![] })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, ParseError> {
+        Err(ser::Error::custom("Maps can't be written in an S-expression, use a struct"))
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Struct, ParseError> {
+        let (name, positional_fields) = parser::struct_layout(name);
+        let positional_fields = positional_fields.collect();
+        Ok(Struct { name, variant: false, positional_fields, positional: vec![], children: vec![] })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Struct, ParseError> {
+        Ok(Struct {
+            name: variant,
+            variant: true,
+            positional_fields: vec![],
+            positional: vec![],
+            children: vec![],
+        })
+    }
+}
+
+struct Seq {
+    name:  Option<&'static str>, // Set for tuple variants
+    items: Vec<SExpr>,
+}
+
+impl Seq {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ParseError> {
+        self.items.extend(value.serialize(Serializer)?.into_node());
+        Ok(())
+    }
+
+    fn finish(self) -> Fragment {
+        match self.name {
+            Some(variant) => {
+                Fragment::List(None, vec![list(Some(variant.to_string()), self.items)])
+            },
+            None => Fragment::List(None, self.items),
+        }
+    }
+}
+
+impl ser::SerializeSeq for Seq {
+    type Error = ParseError;
+    type Ok = Fragment;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ParseError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Fragment, ParseError> { Ok(self.finish()) }
+}
+
+impl ser::SerializeTuple for Seq {
+    type Error = ParseError;
+    type Ok = Fragment;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ParseError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Fragment, ParseError> { Ok(self.finish()) }
+}
+
+impl ser::SerializeTupleStruct for Seq {
+    type Error = ParseError;
+    type Ok = Fragment;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ParseError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Fragment, ParseError> { Ok(self.finish()) }
+}
+
+impl ser::SerializeTupleVariant for Seq {
+    type Error = ParseError;
+    type Ok = Fragment;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ParseError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Fragment, ParseError> { Ok(self.finish()) }
+}
+
+struct Struct {
+    name:              &'static str,
+    variant:           bool, // Struct variants are nested in a list named after the variant
+    positional_fields: Vec<&'static str>,
+    positional:        Vec<SExpr>,
+    children:          Vec<SExpr>,
+}
+
+impl ser::SerializeStruct for Struct {
+    type Error = ParseError;
+    type Ok = Fragment;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ParseError> {
+        let Some(args) = value.serialize(Serializer)?.into_args() else { return Ok(()) };
+        if key == UNKNOWN_FIELD {
+            // Unknown nodes serialize to their text form, see `SExpr`'s `Serialize`
+            for node in args {
+                self.children.push(parser::parse(node.expect_string()?)?);
+            }
+        } else if self.positional_fields.contains(&key) {
+            self.positional.extend(args);
+        } else {
+            self.children.push(list(Some(key.to_string()), args));
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<Fragment, ParseError> {
+        let args = self.positional.into_iter().chain(self.children).collect();
+        if self.variant {
+            Ok(Fragment::List(None, vec![list(Some(self.name.to_string()), args)]))
+        } else {
+            Ok(Fragment::List(Some(self.name.to_string()), args))
+        }
+    }
+}
+
+impl ser::SerializeStructVariant for Struct {
+    type Error = ParseError;
+    type Ok = Fragment;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ParseError> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Fragment, ParseError> { ser::SerializeStruct::end(self) }
+}
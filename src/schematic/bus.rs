@@ -0,0 +1,207 @@
+use std::{fmt::{Display, Formatter},
+          str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{parser::{self, ParseError, SExpr},
+            schematic::{coord::{Coord, Length},
+                        graphic::Stroke,
+                        router::GRID,
+                        Position}};
+
+/// Width and height of the bus entries placed by [`super::KicadSch::fan_out_bus`], KiCad's default.
+pub const BUS_ENTRY_SIZE: Length = Coord::from_nm(2 * GRID.nm());
+
+/// The name of a bus, given by the label on it, which tells the nets it carries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusName {
+    /// `PREFIX[START..END]`, carrying the nets `PREFIX<START>` to `PREFIX<END>`, like `D[0..7]`.
+    Vector { prefix: String, start: u32, end: u32 },
+    /// `NAME{MEMBER MEMBER ...}`, or without a name like `{SDA SCL}`. Members may be nets,
+    /// vectors or aliases, and their nets are prefixed by `NAME.` when the group is named.
+    Group { name: String, members: Vec<String> },
+}
+
+impl BusName {
+    /// The nets of the bus, in order, expanding vectors and the `aliases` of the sheet.
+    pub fn nets(&self, aliases: &[BusAlias]) -> Vec<String> {
+        match self {
+            Self::Vector { prefix, start, end } => {
+                let indices: Vec<u32> = if start <= end {
+                    (*start..=*end).collect()
+                } else {
+                    (*end..=*start).rev().collect()
+                };
+                indices.into_iter().map(|index| format!("{prefix}{index}")).collect()
+            },
+            Self::Group { name, members } => {
+                let nets = members.iter().flat_map(|member| member_nets(member, aliases));
+                if name.is_empty() {
+                    nets.collect()
+                } else {
+                    nets.map(|net| format!("{name}.{net}")).collect()
+                }
+            },
+        }
+    }
+}
+
+/// The nets of a group member, or the member itself when it is a single net.
+fn member_nets(member: &str, aliases: &[BusAlias]) -> Vec<String> {
+    if let Some(alias) = aliases.iter().find(|alias| alias.name == member) {
+        alias.members.iter().flat_map(|member| member_nets(member, aliases)).collect()
+    } else if let Ok(bus) = member.parse::<BusName>() {
+        bus.nets(aliases)
+    } else {
+        vec![member.to_string()]
+    }
+}
+
+impl FromStr for BusName {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let not_a_bus = || format!("{name} is not a bus, like D[0..7] or {{SDA SCL}}");
+        if let Some((group, members)) = name.strip_suffix('}').and_then(|s| s.split_once('{')) {
+            let members: Vec<String> = members.split_whitespace().map(str::to_string).collect();
+            if members.is_empty() || group.contains(char::is_whitespace) {
+                return Err(not_a_bus());
+            }
+            Ok(Self::Group { name: group.to_string(), members })
+        } else if let Some((prefix, range)) = name.strip_suffix(']').and_then(|s| s.split_once('['))
+        {
+            let (start, end) = range.split_once("..").ok_or_else(not_a_bus)?;
+            if prefix.is_empty() || prefix.contains(char::is_whitespace) {
+                return Err(not_a_bus());
+            }
+            Ok(Self::Vector {
+                prefix: prefix.to_string(),
+                start:  start.parse().map_err(|_| not_a_bus())?,
+                end:    end.parse().map_err(|_| not_a_bus())?,
+            })
+        } else {
+            Err(not_a_bus())
+        }
+    }
+}
+
+impl Display for BusName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Vector { prefix, start, end } => write!(f, "{prefix}[{start}..{end}]"),
+            Self::Group { name, members } => write!(f, "{name}{{{}}}", members.join(" ")),
+        }
+    }
+}
+
+/// `(bus_alias "NAME" (members "MEMBER" ...))`, a name standing for several members in the buses
+/// of a sheet.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[serde(rename = "bus_alias name")]
+pub struct BusAlias {
+    pub name:    String,
+    pub members: Vec<String>,
+}
+
+impl BusAlias {
+    pub fn new(name: &str, members: &[&str]) -> Self {
+        Self { name: name.to_string(), members: members.iter().map(|m| m.to_string()).collect() }
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("bus_alias")?;
+        parser::from_sexpr(node)
+    }
+}
+
+impl Display for BusAlias {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
+}
+
+/// `(bus_entry (at X Y) (size DX DY) ...)`, the slanted line joining a wire to a bus, from
+/// `(X, Y)` to `(X + DX, Y + DY)`.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "bus_entry")]
+pub struct BusEntry {
+    #[serde(rename = "at")]
+    pub position: Position,
+    pub size:     (Length, Length),
+    pub stroke:   Stroke,
+    pub uuid:     Uuid,
+}
+
+impl BusEntry {
+    pub fn new((x, y): (Coord, Coord), size: (Length, Length)) -> Self {
+        Self {
+            position: Position { x, y, rotation: None },
+            size,
+            stroke: Stroke::default(),
+            uuid: Uuid::new_v4(),
+        }
+    }
+
+    /// The two ends of the entry, where it connects.
+    pub fn ends(&self) -> [(Coord, Coord); 2] {
+        let Position { x, y, .. } = self.position;
+        [(x, y), (x + self.size.0, y + self.size.1)]
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("bus_entry")?;
+        parser::from_sexpr(node)
+    }
+}
+
+impl Display for BusEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::schematic::coord::tests::coord;
+
+    #[test]
+    fn buses_carry_their_nets() {
+        let aliases = [BusAlias::new("I2C", &["SDA", "SCL"])];
+        let nets = |name: &str| name.parse::<BusName>().unwrap().nets(&aliases);
+        assert_eq!(nets("D[0..3]"), ["D0", "D1", "D2", "D3"]);
+        assert_eq!(nets("A[2..0]"), ["A2", "A1", "A0"]);
+        assert_eq!(nets("{SDA SCL}"), ["SDA", "SCL"]);
+        assert_eq!(nets("MEM{D[0..1] WE I2C}"), [
+            "MEM.D0", "MEM.D1", "MEM.WE", "MEM.SDA", "MEM.SCL"
+        ]);
+        for name in ["D0", "D[0..]", "[0..7]", "{}", "D[0..7", "MY BUS{A B}"] {
+            assert!(name.parse::<BusName>().is_err(), "{name}");
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn bus_names_round_trip(prefix in "[A-Z_]{1,5}", start in 0..64u32, end in 0..64u32) {
+            let vector = BusName::Vector { prefix: prefix.clone(), start, end };
+            prop_assert_eq!(vector.to_string().parse::<BusName>(), Ok(vector.clone()));
+            let members = vec![vector.to_string(), prefix];
+            let group = BusName::Group { name: "BUS".to_string(), members };
+            prop_assert_eq!(group.to_string().parse::<BusName>(), Ok(group));
+        }
+
+        #[test]
+        fn bus_aliases_round_trip(
+            name in "[A-Z]{1,5}",
+            members in prop::collection::vec("[A-Z0-9]{1,5}", 1..5),
+        ) {
+            let alias = BusAlias { name, members };
+            prop_assert_eq!(BusAlias::extract_from(&parser::parse(&alias.to_string())?)?, alias);
+        }
+
+        #[test]
+        fn bus_entries_round_trip(at in (coord(), coord()), size in (coord(), coord())) {
+            let entry = BusEntry::new(at, size);
+            prop_assert_eq!(BusEntry::extract_from(&parser::parse(&entry.to_string())?)?, entry);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::{error::Error,
+          fmt::{Display, Formatter},
+          ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+          str::FromStr};
+
+use serde::{de::{self, Visitor},
+            Deserialize,
+            Deserializer,
+            Serialize,
+            Serializer};
+
+const NM_PER_MM: i64 = 1_000_000;
+const NM_PER_MIL: i64 = 25_400;
+
+/// A coordinate, stored as an integer number of nanometres like KiCad does internally, so that
+/// coordinates compare exactly. Written and read in millimetres, the unit of KiCad files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Coord(i64);
+
+/// Widths, radii and other distances, which share the representation of coordinates.
+pub type Length = Coord;
+
+impl Coord {
+    pub const ZERO: Self = Self(0);
+
+    pub const fn from_nm(nm: i64) -> Self { Self(nm) }
+
+    /// Rounded to the nearest nanometre.
+    pub fn from_mm(mm: f64) -> Self { Self((mm * NM_PER_MM as f64).round() as i64) }
+
+    /// Rounded to the nearest nanometre.
+    pub fn from_mil(mil: f64) -> Self { Self((mil * NM_PER_MIL as f64).round() as i64) }
+
+    pub const fn nm(self) -> i64 { self.0 }
+
+    pub fn mm(self) -> f64 { self.0 as f64 / NM_PER_MM as f64 }
+
+    pub fn mil(self) -> f64 { self.0 as f64 / NM_PER_MIL as f64 }
+
+    pub const fn abs(self) -> Self { Self(self.0.abs()) }
+}
+
+impl Add for Coord {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self { Self(self.0 + rhs.0) }
+}
+
+impl AddAssign for Coord {
+    fn add_assign(&mut self, rhs: Self) { self.0 += rhs.0 }
+}
+
+impl Sub for Coord {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self { Self(self.0 - rhs.0) }
+}
+
+impl SubAssign for Coord {
+    fn sub_assign(&mut self, rhs: Self) { self.0 -= rhs.0 }
+}
+
+impl Neg for Coord {
+    type Output = Self;
+
+    fn neg(self) -> Self { Self(-self.0) }
+}
+
+impl Mul<i64> for Coord {
+    type Output = Self;
+
+    fn mul(self, rhs: i64) -> Self { Self(self.0 * rhs) }
+}
+
+/// Rounds towards zero, to the nanometre.
+impl Div<i64> for Coord {
+    type Output = Self;
+
+    fn div(self, rhs: i64) -> Self { Self(self.0 / rhs) }
+}
+
+/// Millimetres with as few digits as needed, e.g. `1.27`, `-0.0005` or `0`.
+impl Display for Coord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let nm = self.0.unsigned_abs();
+        let (integer, fraction) = (nm / NM_PER_MM as u64, nm % NM_PER_MM as u64);
+        if fraction == 0 {
+            return f.write_fmt(format_args!("{sign}{integer}"));
+        }
+        let fraction = format!("{fraction:06}");
+        f.write_fmt(format_args!("{sign}{integer}.{}", fraction.trim_end_matches('0')))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseCoordError(String);
+
+impl Display for ParseCoordError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("`{}` is not a valid coordinate", self.0))
+    }
+}
+
+impl Error for ParseCoordError {}
+
+/// Parses millimetres, like `1.27`, `-.5` or `2.54e-1`, without going through floating point.
+/// Digits below the nanometre are rounded.
+impl FromStr for Coord {
+    type Err = ParseCoordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseCoordError(s.to_string());
+
+        let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, exponent.parse().map_err(|_| error())?),
+            None => (s, 0i32),
+        };
+        let (negative, mantissa) = match mantissa.as_bytes().first() {
+            Some(b'-') => (true, &mantissa[1..]),
+            Some(b'+') => (false, &mantissa[1..]),
+            _ => (false, mantissa),
+        };
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let is_digits = |part: &str| part.bytes().all(|c| c.is_ascii_digit());
+        if integer.len() + fraction.len() == 0 || !is_digits(integer) || !is_digits(fraction) {
+            return Err(error());
+        }
+
+        // The value is `digits * 10^scale` nanometres
+        let digits = format!("{integer}{fraction}");
+        let digits = digits.trim_start_matches('0').as_bytes();
+        let scale = (exponent as i64) - fraction.len() as i64 + 6;
+        let dropped = usize::try_from(-scale).unwrap_or(0); // Digits below the nanometre
+        let kept = digits.len().saturating_sub(dropped);
+
+        let mut nm: i64 = 0;
+        for &digit in &digits[..kept] {
+            let next = nm.checked_mul(10).and_then(|nm| nm.checked_add((digit - b'0') as i64));
+            nm = next.ok_or_else(error)?;
+        }
+        if scale > 0 && nm != 0 {
+            let power = u32::try_from(scale).ok().and_then(|scale| 10i64.checked_pow(scale));
+            nm = power.and_then(|power| nm.checked_mul(power)).ok_or_else(error)?;
+        }
+        // Rounds half away from zero, looking at the first dropped digit
+        if dropped > 0 && digits.len() >= dropped && digits[kept] >= b'5' {
+            nm = nm.checked_add(1).ok_or_else(error)?;
+        }
+
+        Ok(Self(if negative { -nm } else { nm }))
+    }
+}
+
+impl Serialize for Coord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Nanometre counts have few enough digits for the shortest form of the f64 to be exact
+        serializer.serialize_f64(self.mm())
+    }
+}
+
+impl<'de> Deserialize<'de> for Coord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(CoordVisitor)
+    }
+}
+
+struct CoordVisitor;
+
+impl Visitor<'_> for CoordVisitor {
+    type Value = Coord;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("a coordinate in millimetres")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Coord, E> { v.parse().map_err(E::custom) }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Coord, E> { Ok(Coord::from_mm(v)) }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Coord, E> {
+        v.checked_mul(NM_PER_MM).map(Coord).ok_or_else(|| E::custom("coordinate out of range"))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Coord, E> {
+        let v = i64::try_from(v).map_err(|_| E::custom("coordinate out of range"))?;
+        self.visit_i64(v)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Coordinates within a metre of the origin.
+    pub fn coord() -> impl Strategy<Value = Coord> {
+        (-1_000_000_000..=1_000_000_000i64).prop_map(Coord::from_nm)
+    }
+
+    pub fn length() -> impl Strategy<Value = Length> {
+        (0..=1_000_000_000i64).prop_map(Coord::from_nm)
+    }
+
+    proptest! {
+        #[test]
+        fn written_coords_parse_back(coord in coord()) {
+            prop_assert_eq!(coord.to_string().parse::<Coord>()?, coord);
+        }
+
+        #[test]
+        fn exponents_are_exact(coord in coord(), exponent in 0..6u32) {
+            let shifted = Coord::from_nm(coord.nm() * 10i64.pow(exponent));
+            prop_assert_eq!(format!("{shifted}e-{exponent}").parse::<Coord>()?, coord);
+        }
+
+        #[test]
+        fn parsing_agrees_with_floats(coord in coord()) {
+            prop_assert_eq!(Coord::from_mm(coord.to_string().parse()?), coord);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt::{Display, Formatter};
+
+use uuid::Uuid;
+
+use crate::{parser::{self, ParseError, SExpr},
+            schematic::{extract_uuid, graphic::{Fill, Graphic}}};
+
+/// A shape drawn on the sheet to annotate it: a polyline, rectangle, circle, arc or Bézier curve.
+/// Unlike in symbols, KiCad gives shapes of schematics a UUID.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drawing {
+    pub graphic: Graphic,
+    pub uuid:    Uuid,
+}
+
+impl Drawing {
+    pub fn new(graphic: Graphic) -> Result<Self, String> {
+        match graphic {
+            Graphic::Text { .. } | Graphic::TextBox { .. } | Graphic::Pin { .. } => {
+                Err("Only shapes can be drawn, see `Text` and `TextBox` for texts".to_string())
+            },
+            graphic => Ok(Self { graphic, uuid: Uuid::new_v4() }),
+        }
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        let mut graphic = match node.name() {
+            Some("polyline" | "rectangle" | "circle" | "arc" | "bezier") => {
+                Graphic::extract_from(node)?
+            },
+            _ => return Err(node.unexpected("a shape".to_string())),
+        };
+        // The graphic keeps the UUID with the children it does not know about
+        let uuid = extract_uuid(node)?;
+        unknown_children(&mut graphic).retain(|child| !child.is_list("uuid"));
+        Ok(Self { graphic, uuid })
+    }
+}
+
+/// The children a shape keeps as-is.
+fn unknown_children(graphic: &mut Graphic) -> &mut Vec<SExpr> {
+    match graphic {
+        Graphic::Arc { unknown, .. }
+        | Graphic::Circle { unknown, .. }
+        | Graphic::Bezier { unknown, .. }
+        | Graphic::Polyline { unknown, .. }
+        | Graphic::Rectangle { unknown, .. }
+        | Graphic::Text { unknown, .. }
+        | Graphic::TextBox { unknown, .. }
+        | Graphic::Pin { unknown, .. } => unknown,
+    }
+}
+
+impl Display for Drawing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // KiCad reads polylines of two points as lines, which it writes without fill
+        if let Graphic::Polyline { points, stroke, fill, unknown } = &self.graphic
+            && let [(sx, sy), (ex, ey)] = points[..]
+            && *fill == Fill::default()
+        {
+            f.write_fmt(format_args!("(polyline\n(pts (xy {sx} {sy}) (xy {ex} {ey}))\n{stroke}"))?;
+            parser::write_unknown(f, unknown)?;
+            return f.write_fmt(format_args!("\n(uuid \"{}\"))", self.uuid));
+        }
+        let mut graphic = self.graphic.clone();
+        let uuid =
+            parser::parse(&format!("(uuid \"{}\")", self.uuid)).map_err(|_| std::fmt::Error)?;
+        unknown_children(&mut graphic).push(uuid);
+        graphic.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::schematic::graphic::tests::shape;
+
+    #[test]
+    fn unknown_atoms_are_written_back() {
+        let node = parser::parse(
+            "(rectangle locked (start 0 0) (end 2.54 2.54) (stroke (width 0) (type default))
+                (fill (type none)) (uuid \"0b0c65f9-0c27-4c53-a3f0-3b5ad6c4b2f5\"))",
+        )
+        .unwrap();
+        let drawing = Drawing::extract_from(&node).unwrap();
+        let Graphic::Rectangle { unknown, .. } = &drawing.graphic else { panic!("{drawing:?}") };
+        assert_eq!(unknown.iter().filter_map(SExpr::as_atom).collect::<Vec<_>>(), ["locked"]);
+
+        let reread = Drawing::extract_from(&parser::parse(&drawing.to_string()).unwrap()).unwrap();
+        assert_eq!(reread, drawing);
+    }
+
+    proptest! {
+        #[test]
+        fn drawings_round_trip(graphic in shape()) {
+            if let Ok(drawing) = Drawing::new(graphic) {
+                let node = parser::parse(&drawing.to_string())?;
+                prop_assert_eq!(Drawing::extract_from(&node)?, drawing);
+            }
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{parser,
+            schematic::coord::{Coord, Length}};
+
+/// An axis-aligned rectangle, given by its smallest and its largest corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BoundingBox {
+    pub min: (Coord, Coord),
+    pub max: (Coord, Coord),
+}
+
+impl BoundingBox {
+    /// The box between two opposite corners, in any order.
+    pub fn new(a: (Coord, Coord), b: (Coord, Coord)) -> Self {
+        Self { min: (a.0.min(b.0), a.1.min(b.1)), max: (a.0.max(b.0), a.1.max(b.1)) }
+    }
+
+    /// The smallest box containing all `points`, or `None` without points.
+    pub fn from_points(points: impl IntoIterator<Item = (Coord, Coord)>) -> Option<Self> {
+        points.into_iter().map(|point| Self::new(point, point)).reduce(Self::union)
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min: (self.min.0.min(other.min.0), self.min.1.min(other.min.1)),
+            max: (self.max.0.max(other.max.0), self.max.1.max(other.max.1)),
+        }
+    }
+
+    pub fn width(&self) -> Length { self.max.0 - self.min.0 }
+
+    pub fn height(&self) -> Length { self.max.1 - self.min.1 }
+
+    pub fn center(&self) -> (Coord, Coord) {
+        ((self.min.0 + self.max.0) / 2, (self.min.1 + self.max.1) / 2)
+    }
+
+    pub fn corners(&self) -> [(Coord, Coord); 4] {
+        [self.min, (self.max.0, self.min.1), self.max, (self.min.0, self.max.1)]
+    }
+
+    /// Points on the border are inside.
+    pub fn contains(&self, (x, y): (Coord, Coord)) -> bool {
+        (self.min.0..=self.max.0).contains(&x) && (self.min.1..=self.max.1).contains(&y)
+    }
+
+    /// Boxes only touching by their border intersect.
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.0 <= other.max.0
+            && other.min.0 <= self.max.0
+            && self.min.1 <= other.max.1
+            && other.min.1 <= self.max.1
+    }
+
+    /// Grows the box by `margin` on every side.
+    pub fn inflate(self, margin: Length) -> Self {
+        Self {
+            min: (self.min.0 - margin, self.min.1 - margin),
+            max: (self.max.0 + margin, self.max.1 + margin),
+        }
+    }
+
+    pub fn translate(self, (dx, dy): (Coord, Coord)) -> Self {
+        Self { min: (self.min.0 + dx, self.min.1 + dy), max: (self.max.0 + dx, self.max.1 + dy) }
+    }
+}
+
+/// The rotations KiCad allows for symbols, pins and their texts, counter-clockwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Rotation {
+    /// Rounded to the nearest quarter turn.
+    pub fn from_degrees(degrees: f32) -> Self {
+        match ((degrees / 90.).round() as i64).rem_euclid(4) {
+            0 => Self::Deg0,
+            1 => Self::Deg90,
+            2 => Self::Deg180,
+            _ => Self::Deg270,
+        }
+    }
+
+    pub fn degrees(self) -> f32 {
+        match self {
+            Self::Deg0 => 0.,
+            Self::Deg90 => 90.,
+            Self::Deg180 => 180.,
+            Self::Deg270 => 270.,
+        }
+    }
+
+    /// The rotation turning the X axis towards `(dx, dy)`, Y growing upwards.
+    fn of_direction((dx, dy): (Coord, Coord)) -> Self {
+        if dx.abs() >= dy.abs() {
+            if dx >= Coord::ZERO { Self::Deg0 } else { Self::Deg180 }
+        } else if dy > Coord::ZERO {
+            Self::Deg90
+        } else {
+            Self::Deg270
+        }
+    }
+}
+
+/// Mirroring of a placed symbol, `(mirror x)` or `(mirror y)`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Mirror {
+    /// Upside down, across the X axis.
+    X,
+    /// Left to right, across the Y axis.
+    Y,
+}
+
+impl Display for Mirror {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
+}
+
+/// How a placed symbol is turned: rotated first, then mirrored, as seen on the sheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    pub mirror:   Option<Mirror>,
+}
+
+/// A quarter turn or mirror followed by a translation, like KiCad's `TRANSFORM`. The matrix only
+/// holds 0 and ±1, so coordinates stay exact: `x' = x1·x + y1·y + dx` and `y' = x2·x + y2·y + dy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Transform {
+    x1:     i64,
+    y1:     i64,
+    x2:     i64,
+    y2:     i64,
+    offset: (Coord, Coord),
+}
+
+impl Transform {
+    pub const IDENTITY: Self =
+        Self { x1: 1, y1: 0, x2: 0, y2: 1, offset: (Coord::ZERO, Coord::ZERO) };
+    /// Turns symbol coordinates, Y growing upwards, into schematic coordinates, Y growing
+    /// downwards, and back.
+    pub const FLIP_Y: Self = Self { y2: -1, ..Self::IDENTITY };
+
+    pub const fn translation(offset: (Coord, Coord)) -> Self { Self { offset, ..Self::IDENTITY } }
+
+    /// Counter-clockwise around the origin, Y growing upwards.
+    pub const fn rotation(rotation: Rotation) -> Self {
+        let (x1, y1, x2, y2) = match rotation {
+            Rotation::Deg0 => (1, 0, 0, 1),
+            Rotation::Deg90 => (0, -1, 1, 0),
+            Rotation::Deg180 => (-1, 0, 0, -1),
+            Rotation::Deg270 => (0, 1, -1, 0),
+        };
+        Self { x1, y1, x2, y2, ..Self::IDENTITY }
+    }
+
+    pub const fn mirror(mirror: Mirror) -> Self {
+        match mirror {
+            Mirror::X => Self::FLIP_Y,
+            Mirror::Y => Self { x1: -1, ..Self::IDENTITY },
+        }
+    }
+
+    /// Moves the points of a symbol to where they land on the sheet when it is placed at
+    /// `position` with `orientation`.
+    pub fn placement(position: (Coord, Coord), orientation: Orientation) -> Self {
+        // Turning before flipping Y makes the rotation counter-clockwise on the sheet too
+        let mut transform = Self::rotation(orientation.rotation).then(Self::FLIP_Y);
+        if let Some(mirror) = orientation.mirror {
+            transform = transform.then(Self::mirror(mirror));
+        }
+        transform.then(Self::translation(position))
+    }
+
+    /// `self`, then `next`.
+    pub fn then(self, next: Self) -> Self {
+        Self {
+            x1:     next.x1 * self.x1 + next.y1 * self.x2,
+            y1:     next.x1 * self.y1 + next.y1 * self.y2,
+            x2:     next.x2 * self.x1 + next.y2 * self.x2,
+            y2:     next.x2 * self.y1 + next.y2 * self.y2,
+            offset: next.apply(self.offset),
+        }
+    }
+
+    pub fn apply(&self, (x, y): (Coord, Coord)) -> (Coord, Coord) {
+        (x * self.x1 + y * self.y1 + self.offset.0, x * self.x2 + y * self.y2 + self.offset.1)
+    }
+
+    /// The transform moving points back, quarter turns and mirrors being undone by their
+    /// transpose.
+    pub fn inverse(&self) -> Self {
+        let linear = Self { x1: self.x1, y1: self.x2, x2: self.y1, y2: self.y2, ..Self::IDENTITY };
+        let (dx, dy) = linear.apply(self.offset);
+        Self { offset: (-dx, -dy), ..linear }
+    }
+
+    /// Boxes stay aligned with the axes, as the transform only turns by quarter turns.
+    pub fn apply_to_box(&self, bounding_box: BoundingBox) -> BoundingBox {
+        BoundingBox::new(self.apply(bounding_box.min), self.apply(bounding_box.max))
+    }
+
+    /// Turns a direction, like the one a pin points to, ignoring the translation.
+    pub fn apply_to_rotation(&self, rotation: Rotation) -> Rotation {
+        let linear = Self { offset: (Coord::ZERO, Coord::ZERO), ..*self };
+        let direction = Self::rotation(rotation).apply((Coord::from_nm(1), Coord::ZERO));
+        Rotation::of_direction(linear.apply(direction))
+    }
+
+    /// Whether horizontal lines become vertical.
+    pub fn swaps_axes(&self) -> bool { self.x1 == 0 }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use proptest::{option, prelude::*, sample::select};
+
+    use super::*;
+    use crate::schematic::coord::tests::coord;
+
+    pub fn orientation() -> impl Strategy<Value = Orientation> {
+        let rotations = vec![Rotation::Deg0, Rotation::Deg90, Rotation::Deg180, Rotation::Deg270];
+        let mirror = option::of(select(vec![Mirror::X, Mirror::Y]));
+        (select(rotations), mirror).prop_map(|(rotation, mirror)| Orientation { rotation, mirror })
+    }
+
+    fn bounding_box() -> impl Strategy<Value = BoundingBox> {
+        ((coord(), coord()), (coord(), coord())).prop_map(|(a, b)| BoundingBox::new(a, b))
+    }
+
+    proptest! {
+        #[test]
+        fn unions_contain_both_boxes(a in bounding_box(), b in bounding_box()) {
+            let union = a.union(b);
+            for corner in a.corners().into_iter().chain(b.corners()) {
+                prop_assert!(union.contains(corner));
+            }
+            prop_assert!(union.intersects(&a) && union.intersects(&b));
+        }
+
+        #[test]
+        fn placements_turn_like_kicad(x in coord(), y in coord()) {
+            // KiCad's matrices for `(at X Y ANGLE)` and `(mirror x)`, on symbol coordinates
+            let kicad = |(x1, y1, x2, y2): (i64, i64, i64, i64)| (x * x1 - y * y1, x * x2 - y * y2);
+            let place = |rotation, mirror| {
+                Transform::placement((Coord::ZERO, Coord::ZERO), Orientation { rotation, mirror })
+                    .apply((x, y))
+            };
+            prop_assert_eq!(place(Rotation::Deg0, None), kicad((1, 0, 0, 1)));
+            prop_assert_eq!(place(Rotation::Deg90, None), kicad((0, 1, -1, 0)));
+            prop_assert_eq!(place(Rotation::Deg180, None), kicad((-1, 0, 0, -1)));
+            prop_assert_eq!(place(Rotation::Deg270, None), kicad((0, -1, 1, 0)));
+            prop_assert_eq!(place(Rotation::Deg0, Some(Mirror::X)), kicad((1, 0, 0, -1)));
+            prop_assert_eq!(place(Rotation::Deg0, Some(Mirror::Y)), kicad((-1, 0, 0, 1)));
+            prop_assert_eq!(place(Rotation::Deg90, Some(Mirror::X)), kicad((0, 1, 1, 0)));
+        }
+
+        #[test]
+        fn inverses_undo_placements(
+            point in (coord(), coord()),
+            position in (coord(), coord()),
+            orientation in orientation(),
+        ) {
+            let placement = Transform::placement(position, orientation);
+            prop_assert_eq!(placement.inverse().apply(placement.apply(point)), point);
+        }
+    }
+}
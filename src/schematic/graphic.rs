@@ -1,239 +1,468 @@
-use std::{fmt::{Display, Formatter},
-          str::FromStr};
+use std::{cmp::Ordering,
+          f64::consts::{FRAC_PI_2, TAU},
+          fmt::{Display, Formatter}};
 
-use log::warn;
 use serde::{Deserialize, Serialize};
 
-use crate::{parser, schematic::Position};
+use crate::{parser::{self, ParseError, Quoted, SExpr},
+            schematic::{coord::{Coord, Length},
+                        geometry::{BoundingBox, Rotation, Transform},
+                        Position}};
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[allow(clippy::large_enum_variant)] // Pins are the most common graphic, boxing them would not help
 pub enum Graphic {
     Arc {
-        start:  (f32, f32),
-        mid:    (f32, f32),
-        end:    (f32, f32),
-        stroke: Stroke,
-        fill:   Fill,
+        start:   (Coord, Coord),
+        mid:     (Coord, Coord),
+        end:     (Coord, Coord),
+        stroke:  Stroke,
+        fill:    Fill,
+        #[serde(default)]
+        unknown: Vec<SExpr>,
     },
     Circle {
-        center: (f32, f32),
-        radius: f32,
-        stroke: Stroke,
-        fill:   Fill,
+        center:  (Coord, Coord),
+        radius:  Length,
+        stroke:  Stroke,
+        fill:    Fill,
+        #[serde(default)]
+        unknown: Vec<SExpr>,
     },
     Bezier {
-        points: Vec<(f32, f32)>,
-        stroke: Stroke,
-        fill:   Fill,
+        points:  Vec<(Coord, Coord)>,
+        stroke:  Stroke,
+        fill:    Fill,
+        #[serde(default)]
+        unknown: Vec<SExpr>,
     },
     Polyline {
-        points: Vec<(f32, f32)>,
-        stroke: Stroke,
-        fill:   Fill,
+        points:  Vec<(Coord, Coord)>,
+        stroke:  Stroke,
+        fill:    Fill,
+        #[serde(default)]
+        unknown: Vec<SExpr>,
     },
     Rectangle {
-        start:  (f32, f32),
-        end:    (f32, f32),
-        stroke: Stroke,
-        fill:   Fill,
+        start:   (Coord, Coord),
+        end:     (Coord, Coord),
+        stroke:  Stroke,
+        fill:    Fill,
+        #[serde(default)]
+        unknown: Vec<SExpr>,
     },
     Text {
-        text:     String,
-        position: Position, // (at x y rotation)
+        text:        String,
+        #[serde(default, skip_serializing_if = "parser::is_false")]
+        private:     bool,
+        position:    Position, // (at x y rotation)
+        text_effect: TextEffect,
+        #[serde(default)]
+        unknown:     Vec<SExpr>,
+    },
+    TextBox {
+        text:        String,
+        #[serde(default, skip_serializing_if = "parser::is_false")]
+        private:     bool,
+        position:    Position,         // Top left corner
+        size:        (Length, Length), // (size WIDTH HEIGHT)
+        stroke:      Stroke,
+        fill:        Fill,
+        text_effect: TextEffect,
+        #[serde(default)]
+        unknown:     Vec<SExpr>,
     },
     Pin {
         electrical_type: ElectricalType,
         pin_graphic_style: PinGraphicStyle,
         position: Position,
-        length: f32,
+        length: Length,
         hide: bool,
         name: String,
         name_text_effect: TextEffect,
-        number: usize,
+        number: PinNumber,
         number_text_effect: TextEffect,
         alternates: Vec<PinAlternate>,
+        #[serde(default)]
+        unknown: Vec<SExpr>,
     },
 }
 
 impl Graphic {
-    pub fn extract_arc_from(content: &str) -> Result<(Self, &str), String> {
-        let content = parser::expect_str(content, "(arc")?;
-
-        let (start, content) =
-            parser::expect_regex(content, r#"\(start -?\d+(\.\d+)? -?\d+(\.\d+)?\)"#)?;
-        let start = start.replace("(start ", "").replace(")", "");
-        let start = start.split_once(' ').unwrap();
-        let start = (f32::from_str(start.0).unwrap(), f32::from_str(start.1).unwrap());
-
-        let (mid, content) =
-            parser::expect_regex(content, r#"\(mid -?\d+(\.\d+)? -?\d+(\.\d+)?\)"#)?;
-        let mid = mid.replace("(mid ", "").replace(")", "");
-        let mid = mid.split_once(' ').unwrap();
-        let mid = (f32::from_str(mid.0).unwrap(), f32::from_str(mid.1).unwrap());
-
-        let (end, content) =
-            parser::expect_regex(content, r#"\(end -?\d+(\.\d+)? -?\d+(\.\d+)?\)"#)?;
-        let end = end.replace("(end ", "").replace(")", "");
-        let end = end.split_once(' ').unwrap();
-        let end = (f32::from_str(end.0).unwrap(), f32::from_str(end.1).unwrap());
-
-        let (stroke, content) = Stroke::extract_from(content)?;
-        let (fill, content) = Fill::extract_from(content)?;
-
-        Ok((Self::Arc { start, mid, end, stroke, fill }, content))
-    }
-
-    pub fn extract_polyline_from(content: &str) -> Result<(Self, &str), String> {
-        let content = parser::expect_str(content, "(polyline")?;
-        let mut content = parser::expect_str(content, "(pts")?;
-
-        let mut points = vec![];
-
-        while content.starts_with("(xy") {
-            let (point, left) =
-                parser::expect_regex(content, r#"\(xy -?\d+(\.\d+)? -?\d+(\.\d+)?\)"#)?;
-            let point = point.replace("(xy ", "").replace(")", "");
-            let point = point.split_once(' ').unwrap();
-            let point = (f32::from_str(point.0).unwrap(), f32::from_str(point.1).unwrap());
-            points.push(point);
-            content = left;
+    /// Reads any graphic, picking the kind from the name of the list.
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        match node.name() {
+            Some("arc") => Self::extract_arc_from(node),
+            Some("bezier") => Self::extract_bezier_from(node),
+            Some("circle") => Self::extract_circle_from(node),
+            Some("polyline") => Self::extract_polyline_from(node),
+            Some("rectangle") => Self::extract_rectangle_from(node),
+            Some("text") => Self::extract_text_from(node),
+            Some("text_box") => Self::extract_text_box_from(node),
+            Some("pin") => Self::extract_pin_from(node),
+            _ => Err(node.unexpected("a graphic".to_string())),
         }
+    }
 
-        let content = parser::expect_str(content, ")")?;
-        let (stroke, content) = Stroke::extract_from(content)?;
-        let (fill, content) = Fill::extract_from(content)?;
-
-        Ok((Self::Polyline { points, stroke, fill }, content))
-    }
-
-    pub fn extract_rectangle_from(content: &str) -> Result<(Self, &str), String> {
-        let content = parser::expect_str(content, "(rectangle")?;
-        let (start, content) =
-            parser::expect_regex(content, r#"\(start -?\d+(\.\d+)? -?\d+(\.\d+)?\)"#)?;
-        let start = start.replace("(start ", "").replace(")", "");
-        let start = start.split_once(' ').unwrap();
-        let start = (f32::from_str(start.0).unwrap(), f32::from_str(start.1).unwrap());
-
-        let (end, content) =
-            parser::expect_regex(content, r#"\(end -?\d+(\.\d+)? -?\d+(\.\d+)?\)"#)?;
-        let end = end.replace("(end ", "").replace(")", "");
-        let end = end.split_once(' ').unwrap();
-        let end = (f32::from_str(end.0).unwrap(), f32::from_str(end.1).unwrap());
-
-        let (stroke, content) = Stroke::extract_from(content)?;
-        let (fill, content) = Fill::extract_from(content)?;
-        let content = parser::expect_str(content, ")")?;
-
-        Ok((Self::Rectangle { start, end, stroke, fill }, content))
-    }
-
-    pub fn extract_circle_from(content: &str) -> Result<(Self, &str), String> {
-        let content = parser::expect_str(content, "(circle")?;
-        let (center, content) =
-            parser::expect_regex(content, r#"\(center -?\d+(\.\d+)? -?\d+(\.\d+)?\)"#)?;
-        let center = center.replace("(center ", "").replace(")", "");
-        let center = center.split_once(' ').unwrap();
-        let center = (f32::from_str(center.0).unwrap(), f32::from_str(center.1).unwrap());
-
-        let (radius, content) = parser::expect_regex(content, r#"\(radius \d+(\.\d+)?\)"#)?;
-        let radius = radius.replace("(radius ", "").replace(")", "").parse::<f32>().unwrap();
-        let (stroke, content) = Stroke::extract_from(content)?;
-        let (fill, content) = Fill::extract_from(content)?;
-        let content = parser::expect_str(content, ")")?;
-        Ok((Self::Circle { center, radius, stroke, fill }, content))
-    }
-
-    pub fn extract_pin_from(content: &str) -> Result<(Self, &str), String> {
-        let content = parser::expect_str(content, "(pin")?;
-        let (electrical_type, content) = parser::expect_regex(
-            content,
-            r#"input|output|bidirectional|tri_state|passive|free|unspecified|power_in|power_out|open_collector|open_emitter|no_connect"#,
-        )?;
-        let electrical_type = ElectricalType::from(electrical_type);
-        let (pin_graphic_style, content) = parser::expect_regex(
-            content,
-            r#"line|inverted|clock|inverted_clock|input_low|clock_low|output_low|edge_clock_high|non_logic"#,
-        )?;
-        let pin_graphic_style = PinGraphicStyle::from(pin_graphic_style);
-        let (position, content) = Position::extract_from(content)?;
-        let (length, mut content) = parser::expect_regex(content, r#"\(length \d+(\.\d+)?\)"#)?;
-        let length = length.replace("(length ", "").replace(")", "").parse::<f32>().unwrap();
-
-        let hide = content.starts_with("hide");
-        if hide {
-            content = parser::expect_str(content, "hide")?;
-        }
+    pub fn extract_arc_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("arc")?;
+        Ok(Self::Arc {
+            start:   extract_point(node, "start")?,
+            mid:     extract_point(node, "mid")?,
+            end:     extract_point(node, "end")?,
+            stroke:  Stroke::extract_from(node.expect_child("stroke")?)?,
+            fill:    Fill::extract_from(node.expect_child("fill")?)?,
+            unknown: node.unknown_children(0, &["start", "mid", "end", "stroke", "fill"]),
+        })
+    }
 
-        let (name, content) = parser::expect_regex(content, r#"\(name "[^"]*""#)?;
-        let name = name[7..name.len() - 1].to_string();
-        let (name_text_effect, content) = TextEffect::extract_from(content)?;
-        let content = parser::expect_str(content, ")")?;
-        let (number, content) = parser::expect_regex(content, r#"\(number "[^"]+""#)?;
-        let number = number[9..number.len() - 1].parse::<usize>().unwrap();
-        let (number_text_effect, content) = TextEffect::extract_from(content)?;
-        let mut content = parser::expect_str(content, ")")?;
-        let mut alternates = vec![];
-        while content.starts_with("(alternate") {
-            let (alternate, left) = PinAlternate::extract_from(content)?;
-            alternates.push(alternate);
-            content = left;
-        }
-        let content = parser::expect_str(content, ")")?;
+    /// Lines drawn on schematics are polylines of two points without fill.
+    pub fn extract_polyline_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("polyline")?;
+        let fill = match node.child("fill") {
+            Some(fill) => Fill::extract_from(fill)?,
+            None => Fill::default(),
+        };
+        Ok(Self::Polyline {
+            points: extract_points(node)?,
+            stroke: Stroke::extract_from(node.expect_child("stroke")?)?,
+            fill,
+            unknown: node.unknown_children(0, &["pts", "stroke", "fill"]),
+        })
+    }
 
-        Ok((
-            Self::Pin {
-                electrical_type,
-                pin_graphic_style,
-                position,
-                length,
-                hide,
-                name,
-                name_text_effect,
-                number,
-                number_text_effect,
-                alternates,
+    pub fn extract_bezier_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("bezier")?;
+        Ok(Self::Bezier {
+            points:  extract_points(node)?,
+            stroke:  Stroke::extract_from(node.expect_child("stroke")?)?,
+            fill:    Fill::extract_from(node.expect_child("fill")?)?,
+            unknown: node.unknown_children(0, &["pts", "stroke", "fill"]),
+        })
+    }
+
+    /// Unlike everywhere else, KiCad writes the angle of texts in symbols in tenths of a degree.
+    pub fn extract_text_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("text")?;
+        let position = Position::extract_from(node.expect_child("at")?)?;
+        let rotation = position.rotation.map(|tenths| tenths / 10.);
+        Ok(Self::Text {
+            text:        extract_text(node)?,
+            private:     is_private(node),
+            position:    Position { rotation, ..position },
+            text_effect: TextEffect::extract_from(node.expect_child("effects")?)?,
+            unknown:     node.unknown_children(1, &["private", "at", "effects"]),
+        })
+    }
+
+    pub fn extract_text_box_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("text_box")?;
+        Ok(Self::TextBox {
+            text:        extract_text(node)?,
+            private:     is_private(node),
+            position:    Position::extract_from(node.expect_child("at")?)?,
+            size:        extract_point(node, "size")?,
+            stroke:      Stroke::extract_from(node.expect_child("stroke")?)?,
+            fill:        Fill::extract_from(node.expect_child("fill")?)?,
+            text_effect: TextEffect::extract_from(node.expect_child("effects")?)?,
+            unknown:     node
+                .unknown_children(1, &["private", "at", "size", "stroke", "fill", "effects"]),
+        })
+    }
+
+    pub fn extract_rectangle_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("rectangle")?;
+        Ok(Self::Rectangle {
+            start:   extract_point(node, "start")?,
+            end:     extract_point(node, "end")?,
+            stroke:  Stroke::extract_from(node.expect_child("stroke")?)?,
+            fill:    Fill::extract_from(node.expect_child("fill")?)?,
+            unknown: node.unknown_children(0, &["start", "end", "stroke", "fill"]),
+        })
+    }
+
+    pub fn extract_circle_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("circle")?;
+        Ok(Self::Circle {
+            center:  extract_point(node, "center")?,
+            radius:  node.expect_child("radius")?.expect_arg(0)?.expect_number()?,
+            stroke:  Stroke::extract_from(node.expect_child("stroke")?)?,
+            fill:    Fill::extract_from(node.expect_child("fill")?)?,
+            unknown: node.unknown_children(0, &["center", "radius", "stroke", "fill"]),
+        })
+    }
+
+    pub fn extract_pin_from(node: &SExpr) -> Result<Self, ParseError> {
+        let args = node.expect_list("pin")?;
+        let electrical_type = ElectricalType::extract_from(node.expect_arg(0)?)?;
+        let pin_graphic_style = PinGraphicStyle::extract_from(node.expect_arg(1)?)?;
+        let position = Position::extract_from(node.expect_child("at")?)?;
+        let length = node.expect_child("length")?.expect_arg(0)?.expect_number()?;
+        let hide = match node.child("hide") {
+            Some(hide) => hide.expect_arg(0)?.expect_bool()?,
+            None => args.iter().any(|arg| arg.as_atom() == Some("hide")),
+        };
+
+        let name = node.expect_child("name")?;
+        let name_text_effect = TextEffect::extract_from(name.expect_child("effects")?)?;
+        let name = name.expect_arg(0)?.expect_string()?.to_string();
+
+        let number = node.expect_child("number")?;
+        let number_text_effect = TextEffect::extract_from(number.expect_child("effects")?)?;
+        let number = PinNumber::from(number.expect_arg(0)?.expect_string()?);
+
+        let alternates = args
+            .iter()
+            .filter(|arg| arg.is_list("alternate"))
+            .map(PinAlternate::extract_from)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self::Pin {
+            electrical_type,
+            pin_graphic_style,
+            position,
+            length,
+            hide,
+            name,
+            name_text_effect,
+            number,
+            number_text_effect,
+            alternates,
+            unknown: node
+                .unknown_children(2, &["at", "length", "hide", "name", "number", "alternate"]),
+        })
+    }
+}
+
+/// Reads the quoted text of a `(text ...)` or `(text_box ...)`, which follows the `private` flag.
+fn extract_text(node: &SExpr) -> Result<String, ParseError> {
+    let text = node.args().iter().find_map(|arg| match arg {
+        SExpr::Str(text, _) => Some(text.to_string()),
+        _ => None,
+    });
+    text.ok_or_else(|| node.missing("text".to_string()))
+}
+
+/// `private` texts are only shown in the symbol editor.
+fn is_private(node: &SExpr) -> bool {
+    node.args().iter().any(|arg| arg.as_atom() == Some("private"))
+}
+
+/// Reads the `(pts (xy X Y) ...)` child of `node`.
+pub(crate) fn extract_points(node: &SExpr) -> Result<Vec<(Coord, Coord)>, ParseError> {
+    node.expect_child("pts")?.args().iter().map(|xy| point_from(xy, "xy")).collect()
+}
+
+/// Reads the `(NAME X Y)` child of `node`.
+fn extract_point(node: &SExpr, name: &str) -> Result<(Coord, Coord), ParseError> {
+    point_from(node.expect_child(name)?, name)
+}
+
+fn point_from(node: &SExpr, name: &str) -> Result<(Coord, Coord), ParseError> {
+    node.expect_list(name)?;
+    Ok((node.expect_arg(0)?.expect_number()?, node.expect_arg(1)?.expect_number()?))
+}
+
+impl Graphic {
+    /// The area covered by the graphic in symbol coordinates, Y growing upwards, including half
+    /// the width of its stroke. Pins cover their line from their connection point. `None` for
+    /// lines without points.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let (points, stroke) = match self {
+            Graphic::Arc { start, mid, end, stroke, .. } => {
+                (arc_extremes(*start, *mid, *end), stroke)
+            },
+            Graphic::Circle { center: (x, y), radius, stroke, .. } => {
+                (vec![(*x - *radius, *y - *radius), (*x + *radius, *y + *radius)], stroke)
+            },
+            // A Bézier curve stays within its control points
+            Graphic::Bezier { points, stroke, .. } => (points.clone(), stroke),
+            Graphic::Polyline { points, stroke, .. } => (points.clone(), stroke),
+            Graphic::Rectangle { start, end, stroke, .. } => (vec![*start, *end], stroke),
+            Graphic::Text { text, position, text_effect, .. } => {
+                return Some(text_effect.bounding_box(text, position));
+            },
+            Graphic::TextBox { position, size: (width, height), stroke, .. } => {
+                let top_left = (position.x, position.y);
+                (vec![top_left, (position.x + *width, position.y - *height)], stroke)
+            },
+            Graphic::Pin { position, length, .. } => {
+                let (x, y) = (position.x, position.y);
+                let direction = Transform::rotation(position.angle());
+                let (dx, dy) = direction.apply((*length, Coord::ZERO));
+                return Some(BoundingBox::new((x, y), (x + dx, y + dy)));
+            },
+        };
+        Some(BoundingBox::from_points(points)?.inflate(stroke.width / 2))
+    }
+
+    /// The graphic moved by `transform`. Pins keep pointing away from the body, and texts stay
+    /// horizontal or vertical, keeping their justification.
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let point = |point: &(Coord, Coord)| transform.apply(*point);
+        let mut graphic = self.clone();
+        match &mut graphic {
+            Graphic::Arc { start, mid, end, .. } => {
+                (*start, *mid, *end) = (point(start), point(mid), point(end));
+            },
+            Graphic::Circle { center, .. } => *center = point(center),
+            Graphic::Bezier { points, .. } | Graphic::Polyline { points, .. } => {
+                points.iter_mut().for_each(|p| *p = point(p));
+            },
+            Graphic::Rectangle { start, end, .. } => (*start, *end) = (point(start), point(end)),
+            Graphic::Text { position, .. } => *position = text_position(position, transform),
+            Graphic::TextBox { position, size, .. } => {
+                let corner = (position.x + size.0, position.y - size.1);
+                let moved = BoundingBox::new(point(&(position.x, position.y)), point(&corner));
+                *position = Position {
+                    x: moved.min.0,
+                    y: moved.max.1,
+                    ..text_position(position, transform)
+                };
+                *size = (moved.width(), moved.height());
+            },
+            Graphic::Pin { position, .. } => {
+                let (x, y) = point(&(position.x, position.y));
+                let rotation = transform.apply_to_rotation(position.angle());
+                *position = Position { x, y, rotation: Some(rotation.degrees()) };
             },
-            content,
-        ))
+        }
+        graphic
     }
 }
 
+/// The position of a text moved by `transform`, KiCad only writing texts left to right or bottom
+/// to top.
+fn text_position(position: &Position, transform: &Transform) -> Position {
+    let (x, y) = transform.apply((position.x, position.y));
+    let rotation = match (transform.swaps_axes(), position.angle()) {
+        (false, _) => position.rotation,
+        (true, Rotation::Deg0 | Rotation::Deg180) => Some(90.),
+        (true, Rotation::Deg90 | Rotation::Deg270) => Some(0.),
+    };
+    Position { x, y, rotation }
+}
+
+/// The ends of an arc, and the points where it crosses the horizontal and vertical lines through
+/// its centre, which bound it together.
+fn arc_extremes(
+    start: (Coord, Coord),
+    mid: (Coord, Coord),
+    end: (Coord, Coord),
+) -> Vec<(Coord, Coord)> {
+    let mut points = vec![start, mid, end];
+    let offset = |(x, y): (Coord, Coord)| ((x - start.0).nm() as i128, (y - start.1).nm() as i128);
+    let ((mx, my), (ex, ey)) = (offset(mid), offset(end));
+    if mx * ey == my * ex {
+        return points; // A straight line
+    }
+
+    let [s, m, e] = [start, mid, end].map(|(x, y)| (x.mm(), y.mm()));
+    let squared = |(x, y): (f64, f64)| x * x + y * y;
+    let d = 2. * (s.0 * (m.1 - e.1) + m.0 * (e.1 - s.1) + e.0 * (s.1 - m.1));
+    let cx = (squared(s) * (m.1 - e.1) + squared(m) * (e.1 - s.1) + squared(e) * (s.1 - m.1)) / d;
+    let cy = (squared(s) * (e.0 - m.0) + squared(m) * (s.0 - e.0) + squared(e) * (m.0 - s.0)) / d;
+    let radius = (s.0 - cx).hypot(s.1 - cy);
+
+    // The arc goes counter-clockwise from `from`, over `sweep` radians, through `mid`
+    let angle = |(x, y): (f64, f64)| (y - cy).atan2(x - cx);
+    let counter_clockwise = |from: f64, to: f64| (to - from).rem_euclid(TAU);
+    let (start, mid, end) = (angle(s), angle(m), angle(e));
+    let (from, sweep) = if counter_clockwise(start, mid) <= counter_clockwise(start, end) {
+        (start, counter_clockwise(start, end))
+    } else {
+        (end, counter_clockwise(end, start))
+    };
+    let axes = [(1., 0.), (0., 1.), (-1., 0.), (0., -1.)];
+    for (quarter, (dx, dy)) in axes.into_iter().enumerate() {
+        if counter_clockwise(from, quarter as f64 * FRAC_PI_2) <= sweep {
+            points.push((Coord::from_mm(cx + radius * dx), Coord::from_mm(cy + radius * dy)));
+        }
+    }
+    points
+}
+
 impl Display for Graphic {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Graphic::Arc { start: (sx, sy), mid: (mx, my), end: (ex, ey), stroke, fill } => {
+            Graphic::Arc {
+                start: (sx, sy),
+                mid: (mx, my),
+                end: (ex, ey),
+                stroke,
+                fill,
+                unknown,
+            } => {
                 f.write_fmt(format_args!(
                     "(arc\n(start {sx} {sy})\n(mid {mx} {my})\n(end {ex} \
-                     {ey})\n{stroke}\n{fill}\n)"
-                ))
+                     {ey})\n{stroke}\n{fill}"
+                ))?;
+                parser::write_unknown(f, unknown)?;
+                f.write_str(")")
             },
-            Graphic::Circle { center: (cx, cy), radius, stroke, fill } => {
+            Graphic::Circle { center: (cx, cy), radius, stroke, fill, unknown } => {
                 f.write_fmt(format_args!(
-                    "(circle\n(center {cx} {cy})\n(radius {radius})\n{stroke}\n{fill})"
-                ))
+                    "(circle\n(center {cx} {cy})\n(radius {radius})\n{stroke}\n{fill}"
+                ))?;
+                parser::write_unknown(f, unknown)?;
+                f.write_str(")")
             },
-            Graphic::Bezier { points, stroke, fill } => {
+            Graphic::Bezier { points, stroke, fill, unknown } => {
                 f.write_str("(bezier\n(pts")?;
                 for point in points {
                     f.write_fmt(format_args!("\n(xy {x} {y})", x = point.0, y = point.1))?;
                 }
-                f.write_fmt(format_args!(")\n{stroke}\n{fill}"))
+                f.write_fmt(format_args!(")\n{stroke}\n{fill}"))?;
+                parser::write_unknown(f, unknown)?;
+                f.write_str(")")
             },
-            Graphic::Polyline { points, stroke, fill } => {
+            Graphic::Polyline { points, stroke, fill, unknown } => {
                 f.write_str("(polyline\n(pts")?;
                 for point in points {
                     f.write_fmt(format_args!("\n(xy {x} {y})", x = point.0, y = point.1))?;
                 }
-                f.write_fmt(format_args!(")\n{stroke}\n{fill}"))
+                f.write_fmt(format_args!(")\n{stroke}\n{fill}"))?;
+                parser::write_unknown(f, unknown)?;
+                f.write_str(")")
             },
-            Graphic::Rectangle { start: (sx, sy), end: (ex, ey), stroke, fill } => {
+            Graphic::Rectangle { start: (sx, sy), end: (ex, ey), stroke, fill, unknown } => {
                 f.write_fmt(format_args!(
-                    "\
-            (rectangle\n(start {sx} {sy})\n(end {ex} {ey})\n{stroke}\n{fill})"
-                ))
+                    "(rectangle\n(start {sx} {sy})\n(end {ex} {ey})\n{stroke}\n{fill}"
+                ))?;
+                parser::write_unknown(f, unknown)?;
+                f.write_str(")")
+            },
+            Graphic::Text { text, private, position, text_effect, unknown } => {
+                let private = if *private { "private " } else { "" };
+                let position = Position {
+                    rotation: position.rotation.map(|degrees| degrees * 10.),
+                    ..position.clone()
+                };
+                f.write_fmt(format_args!(
+                    "(text {private}{}\n{position}\n{text_effect}",
+                    Quoted(text)
+                ))?;
+                parser::write_unknown(f, unknown)?;
+                f.write_str(")")
             },
-            Graphic::Text { text, position } => {
-                f.write_fmt(format_args!("(text\n(text \"{text}\")\n{position}\n)"))
+            Graphic::TextBox {
+                text,
+                private,
+                position,
+                size: (width, height),
+                stroke,
+                fill,
+                text_effect,
+                unknown,
+            } => {
+                let private = if *private { "private " } else { "" };
+                f.write_fmt(format_args!(
+                    "(text_box {private}{}\n{position}\n(size {width} {height})\n{stroke}\n{fill}\n\
+                     {text_effect}",
+                    Quoted(text)
+                ))?;
+                parser::write_unknown(f, unknown)?;
+                f.write_str(")")
             },
             Graphic::Pin {
                 electrical_type,
@@ -246,279 +475,301 @@ impl Display for Graphic {
                 number,
                 number_text_effect,
                 alternates,
+                unknown,
             } => {
                 f.write_fmt(format_args!(
                     "(pin\n{electrical_type}\n{pin_graphic_style}\n{position}\n(length \
-                     {length})\n(hide {})\n(name \"{name}\" {name_text_effect})\n(number \
-                     \"{number}\" {number_text_effect})\n",
-                    if *hide { "yes" } else { "no" }
+                     {length})"
+                ))?;
+                if *hide {
+                    f.write_str("\n(hide yes)")?;
+                }
+                f.write_fmt(format_args!(
+                    "\n(name {name} {name_text_effect})\n(number {number} {number_text_effect})",
+                    name = Quoted(name),
+                    number = Quoted(number.as_str())
                 ))?;
-                // {alternates}\n))
                 for alternate in alternates {
-                    f.write_fmt(format_args!("{alternate}\n"))?;
+                    f.write_fmt(format_args!("\n{alternate}"))?;
                 }
+                parser::write_unknown(f, unknown)?;
                 f.write_str(")")
             },
         }
     }
 }
 
+/// The number of a pin, which KiCad stores as a string: BGA balls like `A1` or `AA12`, connector
+/// contacts like `A5`/`B5` on USB-C, shields like `SH` and exposed pads like `EP` are all valid.
+///
+/// Pin numbers are ordered naturally, so `2` comes before `10` and `A9` before `A10`.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, Clone)]
+#[serde(transparent)]
+pub struct PinNumber(String);
+
+impl PinNumber {
+    pub fn as_str(&self) -> &str { &self.0 }
+}
+
+impl From<&str> for PinNumber {
+    fn from(number: &str) -> Self { Self(number.to_string()) }
+}
+
+impl From<String> for PinNumber {
+    fn from(number: String) -> Self { Self(number) }
+}
+
+impl Display for PinNumber {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { f.write_str(&self.0) }
+}
+
+impl Ord for PinNumber {
+    fn cmp(&self, other: &Self) -> Ordering {
+        natural_cmp(&self.0, &other.0).then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+impl PartialOrd for PinNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+/// Compares strings the way people sort pin numbers and references: runs of digits compare by
+/// value, so `U2 < U10`, and everything else compares case-insensitively. Strings differing only
+/// by leading zeros or case compare equal.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (a_chunk, a_rest) = split_chunk(a);
+        let (b_chunk, b_rest) = split_chunk(b);
+        let ordering = match (a_chunk, b_chunk) {
+            ("", "") => return Ordering::Equal,
+            (a_chunk, b_chunk) if is_number(a_chunk) && is_number(b_chunk) => {
+                let (a_digits, b_digits) =
+                    (a_chunk.trim_start_matches('0'), b_chunk.trim_start_matches('0'));
+                a_digits.len().cmp(&b_digits.len()).then_with(|| a_digits.cmp(b_digits))
+            },
+            (a_chunk, b_chunk) => a_chunk
+                .chars()
+                .flat_map(char::to_lowercase)
+                .cmp(b_chunk.chars().flat_map(char::to_lowercase)),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        (a, b) = (a_rest, b_rest);
+    }
+}
+
+/// Splits the leading run of digits, or of anything else, off `s`.
+fn split_chunk(s: &str) -> (&str, &str) {
+    let digits = s.starts_with(|c: char| c.is_ascii_digit());
+    let end = s.find(|c: char| c.is_ascii_digit() != digits).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+fn is_number(chunk: &str) -> bool { chunk.starts_with(|c: char| c.is_ascii_digit()) }
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "alternate name electrical_type pin_graphic_style")]
 pub struct PinAlternate {
-    name: String,
-    electrical_type: ElectricalType,
+    name:              String,
+    electrical_type:   ElectricalType,
     pin_graphic_style: PinGraphicStyle,
 }
 
 impl Display for PinAlternate {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!(
-            "(alternate \"{name}\" {electrical_type} {pin_graphic_style}))",
-            name = self.name,
-            electrical_type = self.electrical_type,
-            pin_graphic_style = self.pin_graphic_style,
-        ))
-    }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
 impl PinAlternate {
-    pub fn extract_from(content: &str) -> Result<(Self, &str), String> {
-        let content = parser::expect_str(content, "(alternate")?;
-        let (name, content) = parser::expect_regex(content, r#""[^"]*""#)?;
-        let name = name[1..name.len() - 1].to_string();
-        let (electrical_type, content) = parser::expect_regex(
-            content,
-            r#"input|output|bidirectional|tri_state|passive|free|unspecified|power_in|power_out|open_collector|open_emitter|no_connect"#,
-        )?;
-        let electrical_type = ElectricalType::from(electrical_type);
-        let (pin_graphic_style, content) = parser::expect_regex(
-            content,
-            r#"line|inverted|clock|inverted_clock|input_low|clock_low|output_low|edge_clock_high|non_logic"#,
-        )?;
-        let pin_graphic_style = PinGraphicStyle::from(pin_graphic_style);
-        let content = parser::expect_str(content, ")")?;
-        Ok((Self { name, electrical_type, pin_graphic_style }, content))
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("alternate")?;
+        parser::from_sexpr(node)
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+/// Width 0 and type `default` let KiCad draw lines with the width and style of the schematic.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+#[serde(rename = "stroke")]
 pub struct Stroke {
-    width: f32,
+    width: Length,
+    #[serde(rename = "type")]
     ty:    StrokeType,
     color: Option<(f32, f32, f32, f32)>, // RGBA
 }
 
 impl Display for Stroke {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let Stroke { width, ty, color } = self;
-        f.write_fmt(format_args!("(stroke\n(width {width})\n(type {ty})\n"))?;
-        if let Some((r, g, b, a)) = color {
-            f.write_fmt(format_args!("(color {r} {g} {b} {a})\n"))?;
-        }
-        f.write_str(")")
-    }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
 impl Stroke {
-    fn extract_from(content: &str) -> Result<(Self, &str), String> {
-        let content = parser::expect_str(content, "(stroke")?;
-        let (width, content) = parser::expect_regex(content, r#"\(width \d+(\.\d+)?\)"#)?;
-        let width = width.replace("(width ", "").replace(")", "").parse::<f32>().unwrap();
-        let (ty, content) = StrokeType::extract_from(content)?;
-        let (color, content) = if content.starts_with("(color") {
-            let (color, content) = parser::expect_regex(
-                content,
-                r#"\(color \d+(\.\d+)? \d+(\.\d+)? \d+(\.\d+)? \d+(\.\d+)?\)"#,
-            )?;
-            let color = color.replace("(color ", "").replace(")", "");
-            let color = color.split(' ').map(|x| x.parse::<f32>().unwrap()).collect::<Vec<_>>();
-            let color = Some((color[0], color[1], color[2], color[3]));
-            (color, content)
-        } else {
-            (None, content)
-        };
-        let content = parser::expect_str(content, ")")?;
-        Ok((Self { width, ty, color }, content))
+    /// A line `width` wide, 0 for the default width of the schematic.
+    pub fn new(width: Length, ty: StrokeType) -> Self { Self { width, ty, color: None } }
+
+    pub fn with_color(self, rgba: (f32, f32, f32, f32)) -> Self {
+        Self { color: Some(rgba), ..self }
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("stroke")?;
+        parser::from_sexpr(node)
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "snake_case")]
 pub enum StrokeType {
     Dash,
     DashDot,
     DashDotDot,
     Dot,
+    #[default]
     Default,
     Solid,
 }
 
 impl Display for StrokeType {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            StrokeType::Dash => f.write_str("dash"),
-            StrokeType::DashDot => f.write_str("dash_dot"),
-            StrokeType::DashDotDot => f.write_str("dash_dot_dot"),
-            StrokeType::Dot => f.write_str("dot"),
-            StrokeType::Default => f.write_str("default"),
-            StrokeType::Solid => f.write_str("solid"),
-        }
-    }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
-impl StrokeType {
-    fn extract_from(content: &str) -> Result<(Self, &str), String> {
-        let (ty, content) = parser::expect_regex(
-            content,
-            r#"\(type (dash|dashdot|dashdotdot|dot|default|solid)\)"#,
-        )?;
-        Ok((
-            match ty {
-                "(type dash)" => Self::Dash,
-                "(type dashdot)" => Self::DashDot,
-                "(type dashdotdot)" => Self::DashDotDot,
-                "(type dot)" => Self::Dot,
-                "(type default)" => Self::Default,
-                "(type solid)" => Self::Solid,
-                _ => unreachable!(),
-            },
-            content,
-        ))
-    }
-}
-
-#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
-pub enum Fill {
-    None,
-    Outline,
-    Background,
+/// Type `none` by default, drawing only the outline.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+#[serde(rename = "fill")]
+pub struct Fill {
+    #[serde(rename = "type")]
+    ty:    FillType,
+    color: Option<(f32, f32, f32, f32)>, // RGBA, with `(type color)`
 }
 
 impl Display for Fill {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Fill::None => f.write_str("(fill\n(type none)\n)"),
-            Fill::Outline => f.write_str("(fill\n(type outline)\n)"),
-            Fill::Background => f.write_str("(fill\n(type background)\n)"),
-        }
-    }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
 impl Fill {
-    fn extract_from(content: &str) -> Result<(Self, &str), String> {
-        let content = parser::expect_str(content, "(fill")?;
-
-        let (ty, content) = parser::expect_regex(content, r#"\(type (none|background|outline)\)"#)?;
-        let ty = match ty {
-            "(type none)" => Self::None,
-            "(type background)" => Self::Background,
-            "(type outline)" => Self::Outline,
-            _ => unreachable!(),
-        };
+    pub fn new(ty: FillType) -> Self { Self { ty, color: None } }
 
-        let content = parser::expect_str(content, ")")?;
+    /// Filled with `rgba`, whatever the colours of the schematic.
+    pub fn color(rgba: (f32, f32, f32, f32)) -> Self {
+        Self { ty: FillType::Color, color: Some(rgba) }
+    }
 
-        Ok((ty, content))
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("fill")?;
+        parser::from_sexpr(node)
     }
 }
 
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FillType {
+    #[default]
+    None,
+    Outline,
+    Background,
+    Color,
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "effects")]
 pub struct TextEffect {
     font:    Font,
-    justify: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    justify: Vec<Justify>,
+    #[serde(default, skip_serializing_if = "parser::is_false")]
     hide:    bool,
+    #[serde(default)]
+    unknown: Vec<SExpr>,
 }
 
 impl TextEffect {
-    pub fn extract_from(content: &str) -> Result<(Self, &str), String> {
-        let content = parser::expect_str(content, "(effects")?;
-        let (font, mut content) = Font::extract_from(content)?;
-
-        let mut it = Self { font, hide: false, justify: None };
-
-        loop {
-            if content.starts_with(")") {
-                content = parser::expect_str(content, ")")?;
-                break;
-            } else if content.starts_with("(hide") {
-                let (hide, left) = parser::expect_regex(content, r#"\(hide (yes|no)\)"#)?;
-                let hide = &hide[6..hide.len() - 1];
-                it.hide = hide == "yes";
-                content = left;
-            } else if content.starts_with("(justify") {
-                let (justify, left) = parser::expect_regex(content, r#"\(justify [^\)]+\)"#)?;
-                let justify = &justify[9..justify.len() - 1];
-                it.justify = Some(justify.to_string());
-                content = left;
-            } else {
-                let (skipped, left) = parser::expect_regex(content, r#"\([^\)]*\)"#)?;
-                warn!("Skipped: {}", skipped);
-                content = left;
-            }
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("effects")?;
+        parser::from_sexpr(node)
+    }
+
+    pub fn with_justify(self, justify: Vec<Justify>) -> Self { Self { justify, ..self } }
+}
+
+/// KiCad's default text: 1.27 mm high, centred.
+impl Default for TextEffect {
+    fn default() -> Self {
+        Self {
+            font:    Font {
+                size:    (Length::from_mm(1.27), Length::from_mm(1.27)),
+                bold:    false,
+                italic:  false,
+                unknown: vec![],
+            },
+            justify: vec![],
+            hide:    false,
+            unknown: vec![],
         }
+    }
+}
 
-        Ok((it, content))
+/// Distance between the baselines of two lines of text, relative to the font height, like KiCad's
+/// stroke font.
+const LINE_SPACING: f64 = 1.62;
+
+impl TextEffect {
+    /// Estimates the area covered by `text` written at `position`, in symbol coordinates, Y growing
+    /// upwards. Every character is counted as wide as the font, which slightly overestimates the
+    /// width of most texts.
+    pub fn bounding_box(&self, text: &str, position: &Position) -> BoundingBox {
+        let (height, width) = self.font.size;
+        let lines = text.lines().count().max(1);
+        let columns = text.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+        let width = width * columns as i64;
+        let height = Coord::from_mm(height.mm() * (1. + LINE_SPACING * (lines - 1) as f64));
+
+        let x = if self.justify.contains(&Justify::Left) {
+            (Coord::ZERO, width)
+        } else if self.justify.contains(&Justify::Right) {
+            (-width, Coord::ZERO)
+        } else {
+            (-width / 2, width - width / 2)
+        };
+        let y = if self.justify.contains(&Justify::Top) {
+            (-height, Coord::ZERO)
+        } else if self.justify.contains(&Justify::Bottom) {
+            (Coord::ZERO, height)
+        } else {
+            (-height / 2, height - height / 2)
+        };
+        let rotation = Transform::rotation(position.angle());
+        let bounding_box = BoundingBox::new(rotation.apply((x.0, y.0)), rotation.apply((x.1, y.1)));
+        bounding_box.translate((position.x, position.y))
     }
 }
 
 impl Display for TextEffect {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let TextEffect { font, justify, hide } = self;
-        f.write_fmt(format_args!("(effects\n{font}\n"))?;
-        if let Some(justify) = justify {
-            f.write_fmt(format_args!("(justify {justify})\n"))?;
-        }
-        if *hide {
-            f.write_str("hide\n")?;
-        }
-        f.write_str(")")
-    }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
-struct Font {
-    size:   (f32, f32), //(size HEIGHT WIDTH)
-    italic: bool,
+#[serde(rename_all = "snake_case")]
+pub enum Justify {
+    Left,
+    Right,
+    Top,
+    Bottom,
+    Mirror,
 }
 
-impl Display for Font {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("(font\n(size {} {})", self.size.0, self.size.1))?;
-        if self.italic {
-            f.write_str(" (italic yes)")?;
-        }
-        f.write_str("\n)")
-    }
-}
-
-impl Font {
-    fn extract_from(content: &str) -> Result<(Self, &str), String> {
-        let content = parser::expect_str(content, "(font")?;
-        let (size, mut content) =
-            parser::expect_regex(content, r#"\(size \d+(\.\d+)? \d+(\.\d+)?\)"#)?;
-        let size = size.replace("(size ", "").replace(")", "");
-        let size = size.split_once(' ').unwrap();
-        let size = (f32::from_str(size.0).unwrap(), f32::from_str(size.1).unwrap());
-
-        let mut italic = false;
-
-        while !content.starts_with(")") {
-            if content.starts_with("(italic yes)") {
-                let left = parser::expect_str(content, "(italic yes)")?;
-                italic = true;
-                content = left;
-            } else {
-                let (skipped, left) = parser::expect_regex(content, r#"\([^\)]*\)"#)?;
-                warn!("Skipped: {}", skipped);
-                content = left;
-            }
-        }
-        let content = parser::expect_str(content, ")")?;
-
-        Ok((Self { size, italic }, content))
-    }
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "font")]
+struct Font {
+    size:    (Length, Length), //(size HEIGHT WIDTH)
+    #[serde(default, skip_serializing_if = "parser::is_false")]
+    bold:    bool,
+    #[serde(default, skip_serializing_if = "parser::is_false")]
+    italic:  bool,
+    #[serde(default)]
+    unknown: Vec<SExpr>,
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
 pub enum ElectricalType {
     Input,
     Output,
@@ -534,46 +785,16 @@ pub enum ElectricalType {
     NoConnect,
 }
 
-impl From<&str> for ElectricalType {
-    fn from(value: &str) -> Self {
-        match value {
-            "input" => Self::Input,
-            "output" => Self::Output,
-            "bidirectional" => Self::Bidirectional,
-            "tri_state" => Self::TriState,
-            "passive" => Self::Passive,
-            "free" => Self::Free,
-            "unspecified" => Self::Unspecified,
-            "power_in" => Self::PowerIn,
-            "power_out" => Self::PowerOut,
-            "open_collector" => Self::OpenCollector,
-            "open_emitter" => Self::OpenEmitter,
-            "no_connect" => Self::NoConnect,
-            _ => unreachable!("Unknown ElectricalType"),
-        }
-    }
+impl ElectricalType {
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> { parser::from_sexpr(node) }
 }
 
 impl Display for ElectricalType {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str(match self {
-            ElectricalType::Input => "input",
-            ElectricalType::Output => "output",
-            ElectricalType::Bidirectional => "bidirectional",
-            ElectricalType::TriState => "tri_state",
-            ElectricalType::Passive => "passive",
-            ElectricalType::Free => "free",
-            ElectricalType::Unspecified => "unspecified",
-            ElectricalType::PowerIn => "power_in",
-            ElectricalType::PowerOut => "power_out",
-            ElectricalType::OpenCollector => "open_collector",
-            ElectricalType::OpenEmitter => "open_emitter",
-            ElectricalType::NoConnect => "no_connect",
-        })
-    }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
 pub enum PinGraphicStyle {
     Line,
     Inverted,
@@ -586,35 +807,331 @@ pub enum PinGraphicStyle {
     NonLogic,
 }
 
-impl From<&str> for PinGraphicStyle {
-    fn from(value: &str) -> Self {
-        match value {
-            "line" => Self::Line,
-            "inverted" => Self::Inverted,
-            "clock" => Self::Clock,
-            "inverted_clock" => Self::InvertedClock,
-            "input_low" => Self::InputLow,
-            "clock_low" => Self::ClockLow,
-            "output_low" => Self::OutputLow,
-            "edge_clock_high" => Self::EdgeClockHigh,
-            "non_logic" => Self::NonLogic,
-            _ => unreachable!("Unknown pin graphic style"),
-        }
-    }
+impl PinGraphicStyle {
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> { parser::from_sexpr(node) }
 }
 
 impl Display for PinGraphicStyle {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str(match self {
-            PinGraphicStyle::Line => "line",
-            PinGraphicStyle::Inverted => "inverted",
-            PinGraphicStyle::Clock => "clock",
-            PinGraphicStyle::InvertedClock => "inverted_clock",
-            PinGraphicStyle::InputLow => "input_low",
-            PinGraphicStyle::ClockLow => "clock_low",
-            PinGraphicStyle::OutputLow => "output_low",
-            PinGraphicStyle::EdgeClockHigh => "edge_clock_high",
-            PinGraphicStyle::NonLogic => "non_logic",
-        })
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use proptest::{collection::vec, option, prelude::*, sample::select};
+
+    use super::*;
+    use crate::{parser::tests::unknown_nodes,
+                schematic::{coord::tests::{coord, length},
+                            geometry::tests::orientation,
+                            tests::position}};
+
+    fn point() -> impl Strategy<Value = (Coord, Coord)> { (coord(), coord()) }
+
+    fn color() -> impl Strategy<Value = Option<(f32, f32, f32, f32)>> {
+        option::of((0..=255u8, 0..=255u8, 0..=255u8, 0.0..=1.0f32))
+            .prop_map(|color| color.map(|(r, g, b, a)| (r as f32, g as f32, b as f32, a)))
+    }
+
+    pub fn stroke() -> impl Strategy<Value = Stroke> {
+        let ty = select(vec![
+            StrokeType::Dash,
+            StrokeType::DashDot,
+            StrokeType::DashDotDot,
+            StrokeType::Dot,
+            StrokeType::Default,
+            StrokeType::Solid,
+        ]);
+        (length(), ty, color()).prop_map(|(width, ty, color)| Stroke { width, ty, color })
+    }
+
+    pub fn fill() -> impl Strategy<Value = Fill> {
+        let ty = select(vec![FillType::None, FillType::Outline, FillType::Background]);
+        let color_fill = color().prop_map(|color| Fill { ty: FillType::Color, color });
+        prop_oneof![ty.prop_map(|ty| Fill { ty, color: None }), color_fill]
+    }
+
+    pub fn text_effect() -> impl Strategy<Value = TextEffect> {
+        let font = ((length(), length()), any::<bool>(), any::<bool>(), unknown_nodes())
+            .prop_map(|(size, bold, italic, unknown)| Font { size, bold, italic, unknown });
+        let justify = select(vec![
+            Justify::Left,
+            Justify::Right,
+            Justify::Top,
+            Justify::Bottom,
+            Justify::Mirror,
+        ]);
+        (font, vec(justify, 0..3), any::<bool>(), unknown_nodes())
+            .prop_map(|(font, justify, hide, unknown)| TextEffect { font, justify, hide, unknown })
+    }
+
+    fn electrical_type() -> impl Strategy<Value = ElectricalType> {
+        select(vec![
+            ElectricalType::Input,
+            ElectricalType::Output,
+            ElectricalType::Bidirectional,
+            ElectricalType::TriState,
+            ElectricalType::Passive,
+            ElectricalType::Free,
+            ElectricalType::Unspecified,
+            ElectricalType::PowerIn,
+            ElectricalType::PowerOut,
+            ElectricalType::OpenCollector,
+            ElectricalType::OpenEmitter,
+            ElectricalType::NoConnect,
+        ])
+    }
+
+    fn pin_graphic_style() -> impl Strategy<Value = PinGraphicStyle> {
+        select(vec![
+            PinGraphicStyle::Line,
+            PinGraphicStyle::Inverted,
+            PinGraphicStyle::Clock,
+            PinGraphicStyle::InvertedClock,
+            PinGraphicStyle::InputLow,
+            PinGraphicStyle::ClockLow,
+            PinGraphicStyle::OutputLow,
+            PinGraphicStyle::EdgeClockHigh,
+            PinGraphicStyle::NonLogic,
+        ])
+    }
+
+    pub fn pin() -> impl Strategy<Value = Graphic> {
+        let alternate = (any::<String>(), electrical_type(), pin_graphic_style()).prop_map(
+            |(name, electrical_type, pin_graphic_style)| PinAlternate {
+                name,
+                electrical_type,
+                pin_graphic_style,
+            },
+        );
+        let kind = (electrical_type(), pin_graphic_style(), position(), length(), any::<bool>());
+        let number = any::<String>().prop_map(PinNumber::from);
+        let name = (any::<String>(), text_effect(), number, text_effect());
+        (kind, name, vec(alternate, 0..3), unknown_nodes()).prop_map(
+            |(
+                (electrical_type, pin_graphic_style, position, length, hide),
+                (name, name_text_effect, number, number_text_effect),
+                alternates,
+                unknown,
+            )| Graphic::Pin {
+                electrical_type,
+                pin_graphic_style,
+                position,
+                length,
+                hide,
+                name,
+                name_text_effect,
+                number,
+                number_text_effect,
+                alternates,
+                unknown,
+            },
+        )
+    }
+
+    /// Any graphic but pins.
+    pub fn shape() -> impl Strategy<Value = Graphic> {
+        prop_oneof![
+            (point(), point(), point(), stroke(), fill(), unknown_nodes()).prop_map(
+                |(start, mid, end, stroke, fill, unknown)| Graphic::Arc {
+                    start,
+                    mid,
+                    end,
+                    stroke,
+                    fill,
+                    unknown
+                }
+            ),
+            (point(), length(), stroke(), fill(), unknown_nodes()).prop_map(
+                |(center, radius, stroke, fill, unknown)| Graphic::Circle {
+                    center,
+                    radius,
+                    stroke,
+                    fill,
+                    unknown
+                }
+            ),
+            (vec(point(), 0..8), stroke(), fill(), unknown_nodes()).prop_map(
+                |(points, stroke, fill, unknown)| Graphic::Polyline {
+                    points,
+                    stroke,
+                    fill,
+                    unknown
+                }
+            ),
+            (point(), point(), stroke(), fill(), unknown_nodes()).prop_map(
+                |(start, end, stroke, fill, unknown)| Graphic::Rectangle {
+                    start,
+                    end,
+                    stroke,
+                    fill,
+                    unknown
+                }
+            ),
+            (vec(point(), 0..8), stroke(), fill(), unknown_nodes()).prop_map(
+                |(points, stroke, fill, unknown)| Graphic::Bezier { points, stroke, fill, unknown }
+            ),
+            (any::<String>(), any::<bool>(), position(), text_effect(), unknown_nodes()).prop_map(
+                |(text, private, position, text_effect, unknown)| Graphic::Text {
+                    text,
+                    private,
+                    // Written in tenths of a degree
+                    position: Position { rotation: position.rotation.map(f32::round), ..position },
+                    text_effect,
+                    unknown
+                }
+            ),
+            (any::<String>(), position(), (length(), length()), stroke(), fill(), text_effect())
+                .prop_map(|(text, position, size, stroke, fill, text_effect)| Graphic::TextBox {
+                    text,
+                    private: false,
+                    position,
+                    size,
+                    stroke,
+                    fill,
+                    text_effect,
+                    unknown: vec![],
+                }),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn graphics_round_trip(graphic in prop_oneof![shape(), pin()]) {
+            let node = parser::parse(&graphic.to_string())?;
+            prop_assert_eq!(Graphic::extract_from(&node)?, graphic);
+        }
+
+        #[test]
+        fn text_effects_round_trip(text_effect in text_effect()) {
+            let node = parser::parse(&text_effect.to_string())?;
+            prop_assert_eq!(TextEffect::extract_from(&node)?, text_effect);
+        }
+
+        #[test]
+        fn pins_move_with_their_box(
+            pin in pin(),
+            orientation in orientation(),
+            offset in point(),
+        ) {
+            let transform = Transform::placement(offset, orientation);
+            let moved = pin.transformed(&transform).bounding_box();
+            prop_assert_eq!(moved, pin.bounding_box().map(|b| transform.apply_to_box(b)));
+        }
+
+        #[test]
+        fn natural_order_is_total(a in "[a-cA-C0-2]{0,4}", b in "[a-cA-C0-2]{0,4}") {
+            let (a, b) = (PinNumber::from(a), PinNumber::from(b));
+            prop_assert_eq!(a.cmp(&b), b.cmp(&a).reverse());
+            prop_assert_eq!(a.cmp(&b) == Ordering::Equal, a == b);
+        }
+    }
+
+    #[test]
+    fn stroke_types_are_named_like_kicad() {
+        let names = ["dash", "dash_dot", "dash_dot_dot", "dot", "default", "solid"];
+        for name in names {
+            let stroke = format!("(stroke (width 0.254) (type {name}))");
+            let stroke = Stroke::extract_from(&parser::parse(&stroke).unwrap()).unwrap();
+            assert_eq!(stroke.ty.to_string(), name);
+            assert!(stroke.to_string().contains(&format!("(type {name})")), "{stroke}");
+        }
+        assert!(parser::from_sexpr::<StrokeType>(&parser::parse("dashdot").unwrap()).is_err());
+    }
+
+    #[test]
+    fn arcs_are_bounded_by_their_circle() {
+        let arc = |start, mid, end| {
+            let arc = format!(
+                "(arc (start {start}) (mid {mid}) (end {end}) (stroke (width 0.2) (type default)) \
+                 (fill (type none)))"
+            );
+            Graphic::extract_from(&parser::parse(&arc).unwrap()).unwrap().bounding_box().unwrap()
+        };
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+
+        // Half a circle of radius 2 above the X axis, both ways round
+        let expected = BoundingBox::new(mm(-2.1, -0.1), mm(2.1, 2.1));
+        assert_eq!(arc("2 0", "0 2", "-2 0"), expected);
+        assert_eq!(arc("-2 0", "0 2", "2 0"), expected);
+        // Three quarters of the same circle
+        assert_eq!(arc("0 -2", "2 0", "-2 0"), BoundingBox::new(mm(-2.1, -2.1), mm(2.1, 2.1)));
+    }
+
+    #[test]
+    fn pins_are_bounded_by_their_line() {
+        let pin = "(pin input line (at 5.08 2.54 90) (length 3.81) \
+                   (name \"A\" (effects (font (size 1.27 1.27)))) \
+                   (number \"1\" (effects (font (size 1.27 1.27)))))";
+        let pin = Graphic::extract_from(&parser::parse(pin).unwrap()).unwrap();
+        let expected = (Coord::from_mm(5.08), Coord::from_mm(2.54), Coord::from_mm(6.35));
+        assert_eq!(
+            pin.bounding_box(),
+            Some(BoundingBox::new((expected.0, expected.1), (expected.0, expected.2)))
+        );
+    }
+
+    #[test]
+    fn pin_numbers_sort_naturally() {
+        let numbers = ["B10", "10", "EP", "A2", "2", "b1", "A10", "1", "SH", "A1"];
+        let mut numbers: Vec<PinNumber> = numbers.map(PinNumber::from).into();
+        numbers.sort();
+        let numbers: Vec<&str> = numbers.iter().map(PinNumber::as_str).collect();
+        assert_eq!(numbers, ["1", "2", "10", "A1", "A2", "A10", "b1", "B10", "EP", "SH"]);
+    }
+
+    #[test]
+    fn beziers_texts_and_text_boxes_are_read() {
+        let graphics = [
+            "(bezier (pts (xy 0 0) (xy 1.27 2.54) (xy 3.81 2.54) (xy 5.08 0)) (stroke (width 0) \
+             (type default)) (fill (type none)))",
+            "(text \"Vout\" (at 1.27 -2.54 900) (effects (font (size 1.27 1.27))))",
+            "(text_box \"Adjust R2\" (exclude_from_sim no) (at -2.54 5.08 0) (size 7.62 2.54) \
+             (margins 0.9525 0.9525 0.9525 0.9525) (stroke (width 0) (type default)) (fill (type \
+             none)) (effects (font (size 1.27 1.27)) (justify left top)))",
+        ];
+        let read = |graphic: &str| Graphic::extract_from(&parser::parse(graphic).unwrap()).unwrap();
+        let [bezier, text, text_box] = graphics.map(read);
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+
+        let Graphic::Bezier { points, .. } = &bezier else { panic!("Should be a bezier") };
+        assert_eq!(points, &[mm(0., 0.), mm(1.27, 2.54), mm(3.81, 2.54), mm(5.08, 0.)]);
+        let Graphic::Text { text: content, position, .. } = &text else {
+            panic!("Should be a text")
+        };
+        assert_eq!((content.as_str(), position.angle()), ("Vout", Rotation::Deg90));
+        let Graphic::TextBox { text: content, size, unknown, .. } = &text_box else {
+            panic!("Should be a text box")
+        };
+        assert_eq!(content, "Adjust R2");
+        assert_eq!(*size, (Length::from_mm(7.62), Length::from_mm(2.54)));
+        // The margins and the simulation flag are kept as they are
+        assert_eq!(unknown.len(), 2);
+
+        for graphic in [bezier, text, text_box] {
+            assert_eq!(read(&graphic.to_string()), graphic);
+        }
+    }
+
+    #[test]
+    fn private_texts_are_written_back() {
+        for (kind, src) in [
+            ("text", "(text private \"Note\" (at 0 0 0) (effects (font (size 1.27 1.27))))"),
+            (
+                "text_box",
+                "(text_box private \"Note\" (at 0 0 0) (size 7.62 2.54) (stroke (width 0) (type \
+                 default)) (fill (type none)) (effects (font (size 1.27 1.27))))",
+            ),
+        ] {
+            let graphic = Graphic::extract_from(&parser::parse(src).unwrap()).unwrap();
+            let (Graphic::Text { text, private, unknown, .. }
+            | Graphic::TextBox { text, private, unknown, .. }) = &graphic
+            else {
+                panic!("Should be a text")
+            };
+            assert_eq!((text.as_str(), *private, unknown.len()), ("Note", true, 0));
+            let written = graphic.to_string();
+            assert!(written.starts_with(&format!("({kind} private \"Note\"")), "{written}");
+            assert_eq!(Graphic::extract_from(&parser::parse(&written).unwrap()).unwrap(), graphic);
+        }
     }
 }
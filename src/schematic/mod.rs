@@ -1,76 +1,633 @@
+pub mod bus;
+pub mod coord;
+pub mod drawing;
+pub mod geometry;
 pub mod graphic;
+pub mod page;
+pub mod project;
+pub mod router;
+pub mod sheet;
 pub mod symbol;
 pub mod symbol_library;
+pub mod text;
 
 use std::{fmt::{Display, Formatter},
-          str::FromStr};
+          fs::read_to_string,
+          path::Path};
 
+use bus::{BusAlias, BusEntry, BusName, BUS_ENTRY_SIZE};
+use drawing::Drawing;
+use log::{debug, warn};
+use page::{Page, TitleBlock};
 use serde::{Deserialize, Serialize};
+use sheet::HierarchicalSheet;
 use symbol::{Symbol, SymbolInstance};
+use text::{Image, Table, Text, TextBox};
 use uuid::Uuid;
 
-use crate::{parser, schematic::graphic::Graphic};
+use crate::{parser::{self, ParseError, ParseErrorKind, Quoted, SExpr},
+            schematic::{coord::{Coord, Length},
+                        geometry::{BoundingBox, Orientation, Rotation},
+                        graphic::{ElectricalType,
+                                  Fill,
+                                  Graphic,
+                                  Justify,
+                                  PinNumber,
+                                  Stroke,
+                                  StrokeType,
+                                  TextEffect},
+                        router::{PinEnd, GRID}}};
+
+/// `(version ...)` of the schematics saved by KiCad 7, the first version placing the instances in
+/// the symbols.
+pub const OLDEST_VERSION: u32 = 20230121;
+/// `(version ...)` of the schematics saved by KiCad 9, whose syntax is used when writing.
+pub const VERSION: u32 = 20250114;
+/// Length of the wire between a pin and the label [`KicadSch::label_pin`] attaches to it.
+pub const LABEL_STUB: Length = Coord::from_nm(2 * GRID.nm());
+/// Length of the wires between the pins and the bus entries placed by [`KicadSch::fan_out_bus`],
+/// leaving room for the labels of the nets.
+pub const FAN_OUT_STUB: Length = Coord::from_nm(8 * GRID.nm());
+/// Space between the symbols and the frame [`KicadSch::frame_symbols`] draws around them.
+pub const FRAME_MARGIN: Length = Coord::from_nm(4 * GRID.nm());
 
 #[derive(Debug)]
 pub struct KicadSch {
-    version: &'static str,
-    generator: &'static str,
-    generator_version: &'static str,
+    version: u32, // As read, the schematic is always written with the syntax of `VERSION`
+    generator: String,
+    generator_version: Option<String>, // Since KiCad 8
     uuid: Uuid,
-    paper: &'static str,
+    page: Page,
+    title_block: Option<TitleBlock>,
     lib_symbols: Vec<Symbol>, // Will be written even if empty
+    bus_aliases: Vec<BusAlias>,
     junctions: Vec<Junction>,
     no_connects: Vec<NoConnect>,
     bus_entries: Vec<BusEntry>,
     wires_and_buses: Vec<WireOrBus>,
     images: Vec<Image>,
-    polylines: Vec<Graphic>, // Graphic::Polyline
+    drawings: Vec<Drawing>,
     texts: Vec<Text>,
+    text_boxes: Vec<TextBox>,
+    tables: Vec<Table>,
     labels: Vec<Label>,
     local_labels: Vec<LocalLabel>,
     hierarchical_labels: Vec<HierarchicalLabel>,
     symbols: Vec<SymbolInstance>,
     hierarchical_sheets: Vec<HierarchicalSheet>,
-    hierarchical_pins: Vec<HierarchicalPin>,
-    page: Page,
+    sheet_instance: Option<SheetInstance>, // Only written in the root schematic of a project
+    unknown: Vec<SExpr>, // Written back as-is
     pub project_name: String, // Will not be written
 }
 
 impl KicadSch {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ParseError> {
+        let path = path.as_ref();
+        let src = read_to_string(path).map_err(|e| ParseError::io(e, path))?;
+        Self::from_string(src).map_err(|e| e.with_path(path.to_path_buf()))
+    }
+
+    /// Parses a schematic, errors are located by line and column in `content`. Writing it back
+    /// gives the same elements, unknown ones included.
+    pub fn from_string(content: impl AsRef<str>) -> Result<Self, ParseError> {
+        let content = content.as_ref();
+        Self::extract_from(&parser::parse(content).map_err(|e| e.locate(content))?)
+            .map_err(|e| e.locate(content))
+    }
+
+    fn extract_from(root: &SExpr) -> Result<Self, ParseError> {
+        root.expect_list("kicad_sch")?;
+
+        let version_node = root.expect_child("version")?.expect_arg(0)?;
+        let version = version_node.expect_number()?;
+        if version < OLDEST_VERSION {
+            return Err(ParseError::new(
+                ParseErrorKind::UnsupportedVersion(version),
+                version_node.span(),
+            ));
+        }
+        if version > VERSION {
+            warn!("Schematic has version {version}, newer than {VERSION}, reading it anyway");
+        }
+        let generator =
+            root.expect_child("generator")?.expect_arg(0)?.expect_string()?.to_string();
+        let generator_version = match root.child("generator_version") {
+            Some(node) => Some(node.expect_arg(0)?.expect_string()?.to_string()),
+            None => None,
+        };
+        let title_block = match root.child("title_block") {
+            Some(node) => Some(TitleBlock::extract_from(node)?),
+            None => None,
+        };
+        let lib_symbols = match root.child("lib_symbols") {
+            Some(node) => node.args().iter().map(Symbol::extract_cached_from).collect(),
+            None => Ok(vec![]),
+        }?;
+
+        let mut sch = Self {
+            version,
+            generator,
+            generator_version,
+            uuid: extract_uuid(root)?,
+            page: Page::extract_from(root.expect_child("paper")?)?,
+            title_block,
+            lib_symbols,
+            sheet_instance: None,
+            ..Default::default()
+        };
+        for arg in root.args() {
+            match arg.name() {
+                Some(
+                    "version" | "generator" | "generator_version" | "uuid" | "paper"
+                    | "title_block" | "lib_symbols",
+                ) => {},
+                Some("bus_alias") => sch.bus_aliases.push(BusAlias::extract_from(arg)?),
+                Some("junction") => sch.junctions.push(Junction::extract_from(arg)?),
+                Some("no_connect") => sch.no_connects.push(NoConnect::extract_from(arg)?),
+                Some("bus_entry") => sch.bus_entries.push(BusEntry::extract_from(arg)?),
+                Some("wire" | "bus") => sch.wires_and_buses.push(WireOrBus::extract_from(arg)?),
+                Some("image") => sch.images.push(Image::extract_from(arg)?),
+                Some("polyline" | "rectangle" | "circle" | "arc" | "bezier") => {
+                    sch.drawings.push(Drawing::extract_from(arg)?)
+                },
+                Some("text") => sch.texts.push(Text::extract_from(arg)?),
+                Some("text_box") => sch.text_boxes.push(TextBox::extract_from(arg)?),
+                Some("table") => sch.tables.push(Table::extract_from(arg)?),
+                Some("label") => sch.local_labels.push(LocalLabel::extract_from(arg)?),
+                Some("global_label") => sch.labels.push(Label::extract_from(arg)?),
+                Some("hierarchical_label") => {
+                    sch.hierarchical_labels.push(HierarchicalLabel::extract_from(arg)?)
+                },
+                Some("symbol") => {
+                    sch.symbols.push(SymbolInstance::extract_from(arg, &sch.lib_symbols)?)
+                },
+                Some("sheet") => {
+                    sch.hierarchical_sheets.push(HierarchicalSheet::extract_from(arg)?)
+                },
+                Some("sheet_instances") => {
+                    sch.sheet_instance = Some(SheetInstance::extract_from(arg)?)
+                },
+                _ if is_default_embedded_fonts(arg) => {},
+                _ => {
+                    debug!("Keeping unknown schematic node: {arg}");
+                    sch.unknown.push(arg.clone());
+                },
+            }
+        }
+        // Symbols placed from now on belong to the same project
+        let projects = sch.symbols.iter().map(SymbolInstance::project_name);
+        let sheets = sch.hierarchical_sheets.iter().map(|sheet| sheet.project_name.as_str());
+        sch.project_name = projects.chain(sheets).next().unwrap_or_default().to_string();
+        Ok(sch)
+    }
+
+    /// Places `symbol` turned by the rotation of `position`, rounded to a quarter turn.
     pub fn place(&mut self, symbol: &Symbol, position: Position) -> Result<(), String> {
+        let orientation = Orientation { rotation: position.angle(), mirror: None };
+        self.place_oriented(symbol, (position.x, position.y), orientation)
+    }
+
+    pub fn place_oriented(
+        &mut self,
+        symbol: &Symbol,
+        position: (Coord, Coord),
+        orientation: Orientation,
+    ) -> Result<(), String> {
         if !self.lib_symbols.contains(symbol) {
             self.lib_symbols.push(symbol.clone())
         }
-        let unit = self.symbols.iter().filter(|s| s.name == symbol.name).count() + 1;
-        let symbol_instance = SymbolInstance::from(symbol, position, unit, self)?;
+        let symbol_instance = SymbolInstance::from(symbol, position, orientation, 1, self)?;
         self.symbols.push(symbol_instance);
         Ok(())
     }
+
+    pub fn page(&self) -> Page { self.page }
+
+    pub fn set_page(&mut self, page: Page) { self.page = page }
+
+    pub fn title_block(&self) -> Option<&TitleBlock> { self.title_block.as_ref() }
+
+    pub fn set_title_block(&mut self, title_block: TitleBlock) {
+        self.title_block = Some(title_block)
+    }
+
+    /// The placed symbol with reference `reference`, like `"U2"`.
+    pub fn symbol(&self, reference: &str) -> Option<&SymbolInstance> {
+        self.symbols.iter().find(|symbol| symbol.reference() == reference)
+    }
+
+    /// The placed symbols, a unit at a time.
+    pub fn symbols(&self) -> impl Iterator<Item = &SymbolInstance> { self.symbols.iter() }
+
+    pub fn symbol_by_uuid(&self, uuid: Uuid) -> Option<&SymbolInstance> {
+        self.symbols.iter().find(|symbol| symbol.uuid() == uuid)
+    }
+
+    /// The placed symbols of the library symbol `lib_id`, like `"Device:R"`.
+    pub fn symbols_by_lib_id(&self, lib_id: &str) -> impl Iterator<Item = &SymbolInstance> {
+        self.symbols.iter().filter(move |symbol| symbol.name == lib_id)
+    }
+
+    /// Moves the symbol `reference` so that its first unit lands at `position`, its other units
+    /// keeping their place relative to it. Wires stay where they are, see [`Self::connect`] to
+    /// wire its pins again.
+    pub fn move_symbol(&mut self, reference: &str, position: (Coord, Coord)) -> Result<(), String> {
+        let first = self.symbol(reference).ok_or(format!("No symbol {reference}"))?.position();
+        let (dx, dy) = (position.0 - first.0, position.1 - first.1);
+        for symbol in self.symbols.iter_mut().filter(|symbol| symbol.reference() == reference) {
+            let (x, y) = symbol.position();
+            symbol.set_position((x + dx, y + dy));
+        }
+        Ok(())
+    }
+
+    /// Removes every unit of the symbol `reference`, and the library symbols no longer placed.
+    pub fn remove_symbol(&mut self, reference: &str) -> Result<(), String> {
+        if self.symbol(reference).is_none() {
+            return Err(format!("No symbol {reference}"));
+        }
+        self.symbols.retain(|symbol| symbol.reference() != reference);
+        self.remove_unused_lib_symbols();
+        Ok(())
+    }
+
+    /// Draws every unit of the symbol `reference` like `symbol`, as when swapping a part for a
+    /// pin-compatible one. Wires, labels, junctions and no-connect flags on a pin follow the pin
+    /// with the same number of `symbol`, those on pins it lacks are left where they are. A wire
+    /// that would turn diagonal keeps its direction and gets an elbow to the moved pin.
+    pub fn replace_symbol(&mut self, reference: &str, symbol: &Symbol) -> Result<(), String> {
+        if self.symbol(reference).is_none() {
+            return Err(format!("No symbol {reference}"));
+        }
+        let mut moves = vec![];
+        for placed in self.symbols.iter_mut().filter(|placed| placed.reference() == reference) {
+            let before: Vec<_> =
+                placed.pins().map(|pin| (pin_number(pin), placed.pin_position_of(pin))).collect();
+            placed.set_lib_symbol(symbol);
+            for (number, from) in before {
+                if let Some(pin) = placed.pins().find(|pin| pin_number(pin) == number) {
+                    moves.push((from, placed.pin_position_of(pin)));
+                }
+            }
+        }
+        let moved = |point| moves.iter().find(|(from, _)| *from == point).map_or(point, |m| m.1);
+        let mut elbows = vec![];
+        for wire in self.wires_and_buses.iter_mut().filter(|wire| wire.kind == WireKind::Wire) {
+            let (start, end) = (moved(wire.start), moved(wire.end));
+            let horizontal = wire.start.1 == wire.end.1;
+            let orthogonal = horizontal || wire.start.0 == wire.end.0;
+            // The elbow goes on the moved end, the other one keeping its direction
+            let keeps_start = start == wire.start || end != wire.end;
+            (wire.start, wire.end) = (start, end);
+            if orthogonal && start.0 != end.0 && start.1 != end.1 {
+                let corner =
+                    if horizontal == keeps_start { (end.0, start.1) } else { (start.0, end.1) };
+                wire.end = corner;
+                elbows.push(WireOrBus::wire(corner, end));
+            }
+        }
+        self.wires_and_buses.extend(elbows);
+        let move_position = |position: &mut Position| {
+            let (x, y) = moved((position.x, position.y));
+            *position = Position { x, y, ..*position };
+        };
+        self.junctions.iter_mut().for_each(|junction| move_position(&mut junction.position));
+        self.no_connects.iter_mut().for_each(|flag| move_position(&mut flag.position));
+        self.labels.iter_mut().for_each(|label| move_position(&mut label.position));
+        self.local_labels.iter_mut().for_each(|label| move_position(&mut label.position));
+        self.hierarchical_labels.iter_mut().for_each(|label| move_position(&mut label.position));
+
+        if !self.lib_symbols.contains(symbol) {
+            self.lib_symbols.push(symbol.clone())
+        }
+        self.remove_unused_lib_symbols();
+        Ok(())
+    }
+
+    /// Drops the library symbols no placed symbol is drawn like.
+    fn remove_unused_lib_symbols(&mut self) {
+        let symbols = &self.symbols;
+        self.lib_symbols.retain(|lib_symbol| {
+            symbols.iter().any(|symbol| symbol.lib_symbol().name == lib_symbol.name)
+        });
+    }
+
+    /// Places `sheet`, see [`project::Project::add_sheet`] to place one with a pin for each
+    /// hierarchical label of its sub-sheet. Sheet names must be unique on a schematic.
+    pub fn add_sheet(&mut self, sheet: HierarchicalSheet) -> Result<(), String> {
+        if self.sheet(sheet.name()).is_some() {
+            return Err(format!("There already is a sheet named {}", sheet.name()));
+        }
+        self.hierarchical_sheets.push(sheet);
+        Ok(())
+    }
+
+    /// The placed sheet named `name`.
+    pub fn sheet(&self, name: &str) -> Option<&HierarchicalSheet> {
+        self.hierarchical_sheets.iter().find(|sheet| sheet.name() == name)
+    }
+
+    /// The areas wires must go around: the placed symbols and sheets.
+    pub(crate) fn bodies(&self) -> impl Iterator<Item = BoundingBox> {
+        let symbols = self.symbols.iter().filter_map(|symbol| symbol.bounding_box());
+        symbols.chain(self.hierarchical_sheets.iter().map(HierarchicalSheet::bounding_box))
+    }
+
+    pub fn add_wire(&mut self, wire: WireOrBus) { self.wires_and_buses.push(wire) }
+
+    pub fn add_bus_entry(&mut self, bus_entry: BusEntry) { self.bus_entries.push(bus_entry) }
+
+    /// Names members of buses on the sheet, see [`BusName`].
+    pub fn add_bus_alias(&mut self, bus_alias: BusAlias) { self.bus_aliases.push(bus_alias) }
+
+    pub fn add_text(&mut self, text: Text) { self.texts.push(text) }
+
+    pub fn add_text_box(&mut self, text_box: TextBox) { self.text_boxes.push(text_box) }
+
+    pub fn add_image(&mut self, image: Image) { self.images.push(image) }
+
+    pub fn add_table(&mut self, table: Table) { self.tables.push(table) }
+
+    /// Draws a shape on the sheet, see [`Drawing::new`] for the graphics allowed.
+    pub fn draw(&mut self, graphic: Graphic) -> Result<(), String> {
+        self.drawings.push(Drawing::new(graphic)?);
+        Ok(())
+    }
+
+    /// Draws lines through `points`, closing the shape when the last point is the first.
+    pub fn draw_polyline(&mut self, points: &[(Coord, Coord)], stroke: Stroke, fill: Fill) {
+        let points = points.to_vec();
+        self.add_shape(Graphic::Polyline { points, stroke, fill, unknown: vec![] })
+    }
+
+    /// Draws the rectangle between the opposite corners `start` and `end`.
+    pub fn draw_rectangle(
+        &mut self,
+        start: (Coord, Coord),
+        end: (Coord, Coord),
+        stroke: Stroke,
+        fill: Fill,
+    ) {
+        self.add_shape(Graphic::Rectangle { start, end, stroke, fill, unknown: vec![] })
+    }
+
+    pub fn draw_circle(
+        &mut self,
+        center: (Coord, Coord),
+        radius: Length,
+        stroke: Stroke,
+        fill: Fill,
+    ) {
+        self.add_shape(Graphic::Circle { center, radius, stroke, fill, unknown: vec![] })
+    }
+
+    /// Draws the arc from `start` to `end` passing through `mid`.
+    pub fn draw_arc(&mut self, [start, mid, end]: [(Coord, Coord); 3], stroke: Stroke, fill: Fill) {
+        self.add_shape(Graphic::Arc { start, mid, end, stroke, fill, unknown: vec![] })
+    }
+
+    /// Draws the cubic Bézier curve from the first point to the last, pulled by the two others.
+    pub fn draw_bezier(&mut self, points: [(Coord, Coord); 4], stroke: Stroke, fill: Fill) {
+        let points = points.to_vec();
+        self.add_shape(Graphic::Bezier { points, stroke, fill, unknown: vec![] })
+    }
+
+    /// Draws a graphic known to be a shape.
+    fn add_shape(&mut self, graphic: Graphic) {
+        self.drawings.push(Drawing { graphic, uuid: Uuid::new_v4() })
+    }
+
+    /// Draws a dashed frame around the placed symbols `references`, [`FRAME_MARGIN`] away from
+    /// their bodies and on the grid, with `title` in its top-left corner.
+    pub fn frame_symbols(&mut self, references: &[&str], title: &str) -> Result<(), String> {
+        let mut boxes = vec![];
+        for reference in references {
+            let symbol = self.symbol(reference).ok_or(format!("No symbol {reference}"))?;
+            boxes.extend(symbol.bounding_box());
+        }
+        let Some(bounds) = boxes.into_iter().reduce(BoundingBox::union) else {
+            return Err("No symbol to frame".to_string());
+        };
+        let bounds = bounds.inflate(FRAME_MARGIN);
+        let snap = |coord: Coord, up: bool| {
+            let cells =
+                coord.nm().div_euclid(GRID.nm()) + (up && coord.nm() % GRID.nm() != 0) as i64;
+            Coord::from_nm(cells * GRID.nm())
+        };
+        let start = (snap(bounds.min.0, false), snap(bounds.min.1, false));
+        let end = (snap(bounds.max.0, true), snap(bounds.max.1, true));
+        let stroke = Stroke::new(Length::ZERO, StrokeType::Dash);
+        self.draw_rectangle(start, end, stroke, Fill::default());
+        self.add_text(Text::new(title, (start.0 + GRID, start.1 + GRID * 2)));
+        Ok(())
+    }
+
+    pub fn add_label(&mut self, label: Label) { self.labels.push(label) }
+
+    pub fn add_local_label(&mut self, label: LocalLabel) { self.local_labels.push(label) }
+
+    pub fn add_hierarchical_label(&mut self, label: HierarchicalLabel) {
+        self.hierarchical_labels.push(label)
+    }
+
+    /// Attaches a label to a pin, given like for [`Self::connect`], at the end of a wire of
+    /// [`LABEL_STUB`] leaving the pin away from its symbol.
+    pub fn label_pin(
+        &mut self,
+        pin: (&str, &str),
+        text: &str,
+        kind: LabelKind,
+    ) -> Result<(), String> {
+        let PinEnd { point, direction: (dx, dy) } = self.pin_end(pin)?;
+        let end = (point.0 + LABEL_STUB * dx, point.1 + LABEL_STUB * dy);
+        self.add_wire(WireOrBus::wire(point, end));
+        self.add_any_label(text, kind, end, label_rotation((dx, dy)));
+        Ok(())
+    }
+
+    /// Connects the pins of symbol `reference` to the nets of bus `bus`, like `"D[0..7]"` or
+    /// `"{SDA SCL}"`, in order. Each pin gets a wire of [`FAN_OUT_STUB`] labelled with its net,
+    /// then a bus entry onto a bus running along the pins, which is labelled with `bus` at its
+    /// end. The pins must all leave their symbol in the same direction, like a side of an MCU.
+    pub fn fan_out_bus(
+        &mut self,
+        reference: &str,
+        pins: &[&str],
+        bus: &str,
+        kind: LabelKind,
+    ) -> Result<(), String> {
+        let nets = bus.parse::<BusName>()?.nets(&self.bus_aliases);
+        if nets.len() != pins.len() {
+            return Err(format!("{bus} carries {} nets, not {}", nets.len(), pins.len()));
+        }
+        let ends =
+            pins.iter().map(|pin| self.pin_end((reference, pin))).collect::<Result<Vec<_>, _>>()?;
+        let Some(&PinEnd { direction: (dx, dy), .. }) = ends.first() else {
+            return Err("No pin to fan out".to_string());
+        };
+        if ends.iter().any(|end| end.direction != (dx, dy)) {
+            return Err(format!("The pins of {reference} for {bus} are not on the same side"));
+        }
+
+        // Distance along the direction of the pins, and across it, the bus running across
+        let along = |(x, y): (Coord, Coord)| x * dx + y * dy;
+        let across = |(x, y): (Coord, Coord)| y * dx - x * dy;
+        let at = |along: Coord, across: Coord| (along * dx - across * dy, along * dy + across * dx);
+        let entries = ends.iter().map(|end| along(end.point)).max().unwrap() + FAN_OUT_STUB;
+        let bus_line = entries + BUS_ENTRY_SIZE;
+
+        let mut crossings = vec![];
+        for (end, net) in ends.iter().zip(&nets) {
+            let entry = at(entries, across(end.point));
+            self.add_wire(WireOrBus::wire(end.point, entry));
+            self.add_local_label(LocalLabel::new(net, end.point, label_rotation((dx, dy))));
+            // The entries slant towards increasing positions across the pins
+            let size = at(BUS_ENTRY_SIZE, BUS_ENTRY_SIZE);
+            self.add_bus_entry(BusEntry::new(entry, size));
+            crossings.push(across(end.point) + BUS_ENTRY_SIZE);
+        }
+        let first = *crossings.iter().min().unwrap();
+        let last = *crossings.iter().max().unwrap() + LABEL_STUB;
+        self.add_wire(WireOrBus::bus(at(bus_line, first), at(bus_line, last)));
+        // Read along the bus, away from its entries
+        self.add_any_label(bus, kind, at(bus_line, last), label_rotation((-dy, dx)));
+        Ok(())
+    }
+
+    fn add_any_label(
+        &mut self,
+        text: &str,
+        kind: LabelKind,
+        at: (Coord, Coord),
+        rotation: Rotation,
+    ) {
+        match kind {
+            LabelKind::Local => self.add_local_label(LocalLabel::new(text, at, rotation)),
+            LabelKind::Global(shape) => self.add_label(Label::new(text, shape, at, rotation)),
+            LabelKind::Hierarchical(shape) => {
+                self.add_hierarchical_label(HierarchicalLabel::new(text, shape, at, rotation))
+            },
+        }
+    }
+
+    /// Wires two pins together, each given as the reference of its symbol and its number or
+    /// name, like `("U1", "3")` or `("U1", "OUT")`, or as the name of a sheet and of its pin.
+    /// The wires go around the placed symbols and sheets, see [`router`], and nothing is drawn
+    /// when the pins are already connected.
+    pub fn connect(&mut self, a: (&str, &str), b: (&str, &str)) -> Result<(), String> {
+        let from = self.pin_end(a)?;
+        let to = self.pin_end(b)?;
+        let points = router::route(self, from, to)
+            .ok_or(format!("No route from {}:{} to {}:{}", a.0, a.1, b.0, b.1))?;
+        for segment in points.windows(2) {
+            self.add_wire(WireOrBus::wire(segment[0], segment[1]));
+        }
+        Ok(())
+    }
+
+    /// Adds the junctions KiCad expects where three or more wires or pins meet, or where a wire
+    /// ends in the middle of another one.
+    pub fn add_junctions(&mut self) {
+        let pins = self.pin_positions();
+        let wires: Vec<&WireOrBus> =
+            self.wires_and_buses.iter().filter(|wire| wire.kind == WireKind::Wire).collect();
+        let mut junctions: Vec<(Coord, Coord)> = vec![];
+        for point in wires.iter().flat_map(|wire| [wire.start, wire.end]) {
+            let ends = wires.iter().filter(|wire| wire.start == point || wire.end == point).count();
+            let middles = wires.iter().filter(|wire| wire.contains(point)).count() - ends;
+            let pins = pins.iter().filter(|pin| **pin == point).count();
+            let exists = junctions.contains(&point)
+                || self.junctions.iter().any(|j| (j.position.x, j.position.y) == point);
+            // A wire going through a point connects to it from both sides
+            if ends + 2 * middles + pins >= 3 && !exists {
+                junctions.push(point);
+            }
+        }
+        self.junctions.extend(junctions.into_iter().map(Junction::new));
+    }
+
+    /// Flags the pins connected to nothing, so that KiCad's ERC accepts them. Pins of type
+    /// `no_connect` do not need the flag, and hidden pins connect through their name. Buses
+    /// do not connect to pins, only wires, labels and the pins of symbols and sheets do.
+    pub fn add_no_connects(&mut self) {
+        let pins = self.pin_positions();
+        let labels = self.label_positions();
+        let wires: Vec<_> =
+            self.wires_and_buses.iter().filter(|wire| wire.kind == WireKind::Wire).collect();
+        let mut flags: Vec<(Coord, Coord)> = vec![];
+        for symbol in &self.symbols {
+            for pin in symbol.pins() {
+                let Graphic::Pin { electrical_type, hide, .. } = pin else { continue };
+                let point = symbol.pin_position_of(pin);
+                let is_connected = pins.iter().filter(|p| **p == point).count() > 1
+                    || wires.iter().any(|wire| wire.contains(point))
+                    || labels.contains(&point)
+                    || self.no_connects.iter().any(|n| (n.position.x, n.position.y) == point)
+                    || flags.contains(&point);
+                if !is_connected && !hide && *electrical_type != ElectricalType::NoConnect {
+                    flags.push(point);
+                }
+            }
+        }
+        self.no_connects.extend(flags.into_iter().map(NoConnect::new));
+    }
+
+    /// Where the global, local and hierarchical labels connect.
+    pub(crate) fn label_positions(&self) -> Vec<(Coord, Coord)> {
+        let labels = self.labels.iter().map(|label| &label.position);
+        let local_labels = self.local_labels.iter().map(|label| &label.position);
+        let hierarchical_labels = self.hierarchical_labels.iter().map(|label| &label.position);
+        labels.chain(local_labels).chain(hierarchical_labels).map(|at| (at.x, at.y)).collect()
+    }
+
+    /// Where the pins of all placed symbols and sheets connect.
+    pub(crate) fn pin_positions(&self) -> Vec<(Coord, Coord)> {
+        let pins = self.symbols.iter().map(|symbol| {
+            symbol.pins().map(|pin| symbol.pin_position_of(pin)).collect::<Vec<_>>()
+        });
+        let sheet_pins = self.hierarchical_sheets.iter().flat_map(|sheet| &sheet.pins);
+        pins.flatten().chain(sheet_pins.map(|pin| (pin.position.x, pin.position.y))).collect()
+    }
+
+    fn pin_end(&self, (reference, pin): (&str, &str)) -> Result<PinEnd, String> {
+        if let Some(sheet) = self.sheet(reference) {
+            let pin = sheet.pin(pin).ok_or(format!("Sheet {reference} has no pin {pin}"))?;
+            let point = (pin.position.x, pin.position.y);
+            return Ok(PinEnd { point, direction: pin.direction() });
+        }
+        let symbol = self.symbol(reference).ok_or(format!("No symbol {reference}"))?;
+        let pin = symbol.pin(pin).ok_or(format!("{reference} has no pin {pin}"))?;
+        Ok(PinEnd { point: symbol.pin_position_of(pin), direction: symbol.pin_direction_of(pin) })
+    }
 }
 
 impl Default for KicadSch {
     fn default() -> Self {
         Self {
-            version: "20250114",
-            generator: env!("CARGO_PKG_NAME"),
-            generator_version: env!("CARGO_PKG_VERSION"),
+            version: VERSION,
+            generator: env!("CARGO_PKG_NAME").to_string(),
+            generator_version: Some(env!("CARGO_PKG_VERSION").to_string()),
             uuid: Uuid::new_v4(),
-            paper: "A4",
+            page: Page::default(),
+            title_block: None,
             lib_symbols: vec![],
+            bus_aliases: vec![],
             junctions: vec![],
             no_connects: vec![],
             bus_entries: vec![],
             wires_and_buses: vec![],
             images: vec![],
-            polylines: vec![],
+            drawings: vec![],
             texts: vec![],
+            text_boxes: vec![],
+            tables: vec![],
             labels: vec![],
             local_labels: vec![],
             hierarchical_labels: vec![],
             symbols: vec![],
             hierarchical_sheets: vec![],
-            hierarchical_pins: vec![],
-            page: Page { path: "/".to_string(), page_number: 1 },
+            sheet_instance: Some(SheetInstance { path: "/".to_string(), page: "1".to_string() }),
+            unknown: vec![],
             project_name: "".to_string(),
         }
     }
@@ -78,170 +635,737 @@ impl Default for KicadSch {
 
 impl Display for KicadSch {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("(kicad_sch")?;
-        f.write_fmt(format_args!("(version {})", self.version))?;
-        f.write_fmt(format_args!("(generator {})", self.generator))?;
-        f.write_fmt(format_args!("(generator_version {})", self.generator_version))?;
-        f.write_fmt(format_args!("(uuid {})", self.uuid))?;
-        f.write_fmt(format_args!("(paper {})", self.paper))?;
+        parser::write_pretty(
+            f,
+            std::fmt::from_fn(|f| {
+                f.write_str("(kicad_sch")?;
+                f.write_fmt(format_args!("(version {})", self.version.max(VERSION)))?;
+                f.write_fmt(format_args!("(generator {})", Quoted(&self.generator)))?;
+                if let Some(generator_version) = &self.generator_version {
+                    f.write_fmt(format_args!("(generator_version {})", Quoted(generator_version)))?;
+                }
+                f.write_fmt(format_args!("(uuid \"{}\")", self.uuid))?;
+                f.write_fmt(format_args!("{}", self.page))?;
+                if let Some(title_block) = &self.title_block {
+                    f.write_fmt(format_args!("\n{}", title_block))?;
+                }
 
-        f.write_str("(lib_symbols")?;
-        for symbol in &self.lib_symbols {
-            f.write_fmt(format_args!("\n{}", symbol))?;
-        }
-        f.write_str(")")?;
+                f.write_str("(lib_symbols")?;
+                for symbol in &self.lib_symbols {
+                    f.write_fmt(format_args!("\n{}", symbol))?;
+                }
+                f.write_str(")")?;
 
-        for junction in &self.junctions {
-            f.write_fmt(format_args!("\n{}", junction))?;
-        }
-        for no_connect in &self.no_connects {
-            f.write_fmt(format_args!("\n{}", no_connect))?;
-        }
-        for bus_entry in &self.bus_entries {
-            f.write_fmt(format_args!("\n{}", bus_entry))?;
-        }
-        for wire_or_bus in &self.wires_and_buses {
-            f.write_fmt(format_args!("\n{}", wire_or_bus))?;
-        }
-        for image in &self.images {
-            f.write_fmt(format_args!("\n{}", image))?;
-        }
-        for polyline in &self.polylines {
-            f.write_fmt(format_args!("\n{}", polyline))?;
-        }
-        for text in &self.texts {
-            f.write_fmt(format_args!("\n{}", text))?;
-        }
-        for label in &self.labels {
-            f.write_fmt(format_args!("\n{}", label))?;
-        }
-        for local_label in &self.local_labels {
-            f.write_fmt(format_args!("\n{}", local_label))?;
-        }
-        for hierarchical_label in &self.hierarchical_labels {
-            f.write_fmt(format_args!("\n{}", hierarchical_label))?;
-        }
-        for symbol in &self.symbols {
-            f.write_fmt(format_args!("\n{}", symbol))?;
-        }
-        for hierarchical_sheet in &self.hierarchical_sheets {
-            f.write_fmt(format_args!("\n{}", hierarchical_sheet))?;
-        }
-        for hierarchical_pin in &self.hierarchical_pins {
-            f.write_fmt(format_args!("\n{}", hierarchical_pin))?;
-        }
-        f.write_fmt(format_args!(
-            "(sheet_instances\n(path \"{}\"\n(page \"{}\"\n)))",
-            self.page.path, self.page.page_number
-        ))
+                let sheet_instance = self.sheet_instance.as_ref().map(|instance| {
+                    std::fmt::from_fn(move |f| {
+                        f.write_fmt(format_args!(
+                            "(sheet_instances\n(path {}\n(page {})))",
+                            Quoted(&instance.path),
+                            Quoted(&instance.page)
+                        ))
+                    })
+                });
+                // KiCad sorts the items by kind, the nodes kept as-is included
+                let mut items = vec![];
+                push_items(&mut items, "bus_alias", &self.bus_aliases);
+                push_items(&mut items, "junction", &self.junctions);
+                push_items(&mut items, "no_connect", &self.no_connects);
+                push_items(&mut items, "bus_entry", &self.bus_entries);
+                push_items(&mut items, "wire", &self.wires_and_buses);
+                push_items(&mut items, "image", &self.images);
+                push_items(&mut items, "polyline", &self.drawings);
+                push_items(&mut items, "text", &self.texts);
+                push_items(&mut items, "text_box", &self.text_boxes);
+                push_items(&mut items, "table", &self.tables);
+                push_items(&mut items, "label", &self.local_labels);
+                push_items(&mut items, "global_label", &self.labels);
+                push_items(&mut items, "hierarchical_label", &self.hierarchical_labels);
+                push_items(&mut items, "symbol", &self.symbols);
+                push_items(&mut items, "sheet", &self.hierarchical_sheets);
+                push_items(&mut items, "sheet_instances", sheet_instance.as_slice());
+                for node in &self.unknown {
+                    items.push((save_rank(node.name().unwrap_or_default()), node));
+                }
+                items.sort_by_key(|(rank, _)| *rank);
+                for (_, item) in items {
+                    f.write_fmt(format_args!("\n{}", item))?;
+                }
+                if !self.unknown.iter().any(|node| node.is_list("embedded_fonts")) {
+                    f.write_str("\n(embedded_fonts no)")?;
+                }
+                f.write_str(")")
+            }),
+        )
     }
 }
 
-#[derive(Debug)]
-pub struct Junction {}
+/// Kinds of the top-level nodes, in the order KiCad saves them. Nodes of a same kind keep their
+/// order, and the nodes of unknown kinds go at the end.
+const SAVE_ORDER: &[&[&str]] = &[
+    &["bus_alias"],
+    &["junction"],
+    &["no_connect"],
+    &["bus_entry"],
+    &["wire", "bus"],
+    &["image"],
+    &["polyline", "rectangle", "circle", "arc", "bezier"],
+    &["text", "text_box"],
+    &["table"],
+    &["label"],
+    &["global_label"],
+    &["hierarchical_label"],
+    &["rule_area"],
+    &["netclass_flag"],
+    &["symbol"],
+    &["sheet"],
+    &["sheet_instances", "symbol_instances"],
+    &["embedded_fonts", "embedded_files"],
+];
+
+fn save_rank(kind: &str) -> usize {
+    SAVE_ORDER.iter().position(|kinds| kinds.contains(&kind)).unwrap_or(SAVE_ORDER.len() - 1)
+}
+
+fn push_items<'a, T: Display>(items: &mut Vec<(usize, &'a dyn Display)>, kind: &str, all: &'a [T]) {
+    let rank = save_rank(kind);
+    items.extend(all.iter().map(|item| (rank, item as &dyn Display)));
+}
+
+/// The dot joining wires which cross or end on another wire.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "junction")]
+pub struct Junction {
+    #[serde(rename = "at")]
+    pub position: Position,
+    pub diameter: Length,               // 0 for the default of the schematic
+    pub color:    (f32, f32, f32, f32), // RGBA, all 0 for the default of the schematic
+    pub uuid:     Uuid,
+}
+
+impl Junction {
+    pub fn new((x, y): (Coord, Coord)) -> Self {
+        Self {
+            position: Position { x, y, rotation: None },
+            diameter: Length::ZERO,
+            color:    (0., 0., 0., 0.),
+            uuid:     Uuid::new_v4(),
+        }
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("junction")?;
+        parser::from_sexpr(node)
+    }
+}
 
 impl Display for Junction {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result { todo!() }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
-#[derive(Debug)]
-pub struct NoConnect {}
+/// The cross on a pin left unconnected on purpose.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "no_connect")]
+pub struct NoConnect {
+    #[serde(rename = "at")]
+    pub position: Position,
+    pub uuid:     Uuid,
+}
+
+impl NoConnect {
+    pub fn new((x, y): (Coord, Coord)) -> Self {
+        Self { position: Position { x, y, rotation: None }, uuid: Uuid::new_v4() }
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("no_connect")?;
+        parser::from_sexpr(node)
+    }
+}
 
 impl Display for NoConnect {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result { todo!() }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
-#[derive(Debug)]
-pub struct BusEntry {}
+/// A straight `(wire ...)` or `(bus ...)` segment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WireOrBus {
+    pub kind:   WireKind,
+    pub start:  (Coord, Coord),
+    pub end:    (Coord, Coord),
+    pub stroke: Stroke,
+    pub uuid:   Uuid,
+}
 
-impl Display for BusEntry {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result { todo!() }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireKind {
+    Wire,
+    Bus,
 }
 
-#[derive(Debug)]
-pub struct WireOrBus {}
+impl WireOrBus {
+    pub fn wire(start: (Coord, Coord), end: (Coord, Coord)) -> Self {
+        Self { kind: WireKind::Wire, start, end, stroke: Stroke::default(), uuid: Uuid::new_v4() }
+    }
 
-impl Display for WireOrBus {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result { todo!() }
-}
+    pub fn bus(start: (Coord, Coord), end: (Coord, Coord)) -> Self {
+        Self { kind: WireKind::Bus, ..Self::wire(start, end) }
+    }
 
-#[derive(Debug)]
-pub struct Image {}
+    /// Whether `point` is on the segment, ends included.
+    pub fn contains(&self, (x, y): (Coord, Coord)) -> bool {
+        let ((sx, sy), (ex, ey)) = (self.start, self.end);
+        let cross = (ex - sx).nm() as i128 * (y - sy).nm() as i128
+            - (ey - sy).nm() as i128 * (x - sx).nm() as i128;
+        cross == 0 && BoundingBox::new(self.start, self.end).contains((x, y))
+    }
+
+    /// Whether an end of one of the segments is on the other, which connects them.
+    pub fn touches(&self, other: &Self) -> bool {
+        self.contains(other.start)
+            || self.contains(other.end)
+            || other.contains(self.start)
+            || other.contains(self.end)
+    }
 
-impl Display for Image {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result { todo!() }
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        let kind = match node.name() {
+            Some("wire") => WireKind::Wire,
+            Some("bus") => WireKind::Bus,
+            _ => return Err(node.unexpected("a wire or a bus".to_string())),
+        };
+        let [start, end] = graphic::extract_points(node)?[..] else {
+            return Err(node.expect_child("pts")?.unexpected("two points".to_string()));
+        };
+        Ok(Self {
+            kind,
+            start,
+            end,
+            stroke: Stroke::extract_from(node.expect_child("stroke")?)?,
+            uuid: extract_uuid(node)?,
+        })
+    }
 }
 
-#[derive(Debug)]
-pub struct Text {}
+impl Display for WireOrBus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let ((sx, sy), (ex, ey)) = (self.start, self.end);
+        f.write_fmt(format_args!(
+            "({kind}\n(pts (xy {sx} {sy}) (xy {ex} {ey}))\n{stroke}\n(uuid \"{uuid}\"))",
+            kind = match self.kind {
+                WireKind::Wire => "wire",
+                WireKind::Bus => "bus",
+            },
+            stroke = self.stroke,
+            uuid = self.uuid
+        ))
+    }
+}
 
-impl Display for Text {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result { todo!() }
+/// A `(global_label ...)`, connecting the wire it is on to the wires with the same label on every
+/// sheet.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "global_label text")]
+pub struct Label {
+    pub text: String,
+    pub shape: LabelShape,
+    #[serde(rename = "at")]
+    pub position: Position,
+    #[serde(default, skip_serializing_if = "parser::is_false")]
+    pub fields_autoplaced: bool,
+    pub effects: TextEffect,
+    pub uuid: Uuid,
+    unknown: Vec<SExpr>,
 }
 
-#[derive(Debug)]
-pub struct Label {}
+impl Label {
+    /// A label at `(x, y)`, its text going towards `rotation`.
+    pub fn new(text: &str, shape: LabelShape, (x, y): (Coord, Coord), rotation: Rotation) -> Self {
+        Self {
+            text: text.to_string(),
+            shape,
+            position: Position { x, y, rotation: Some(rotation.degrees()) },
+            fields_autoplaced: false,
+            effects: TextEffect::default().with_justify(vec![label_justify(rotation)]),
+            uuid: Uuid::new_v4(),
+            unknown: vec![],
+        }
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("global_label")?;
+        parser::from_sexpr(node)
+    }
+}
 
 impl Display for Label {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result { todo!() }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
-#[derive(Debug)]
-pub struct LocalLabel {}
+/// A `(label ...)`, connecting the wire it is on to the wires with the same label on its sheet.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "label text")]
+pub struct LocalLabel {
+    pub text: String,
+    #[serde(rename = "at")]
+    pub position: Position,
+    #[serde(default, skip_serializing_if = "parser::is_false")]
+    pub fields_autoplaced: bool,
+    pub effects: TextEffect,
+    pub uuid: Uuid,
+    unknown: Vec<SExpr>,
+}
+
+impl LocalLabel {
+    /// A label at `(x, y)`, its text going towards `rotation`.
+    pub fn new(text: &str, (x, y): (Coord, Coord), rotation: Rotation) -> Self {
+        let justify = vec![label_justify(rotation), Justify::Bottom];
+        Self {
+            text: text.to_string(),
+            position: Position { x, y, rotation: Some(rotation.degrees()) },
+            fields_autoplaced: false,
+            effects: TextEffect::default().with_justify(justify),
+            uuid: Uuid::new_v4(),
+            unknown: vec![],
+        }
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("label")?;
+        parser::from_sexpr(node)
+    }
+}
 
 impl Display for LocalLabel {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result { todo!() }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
-#[derive(Debug)]
-pub struct HierarchicalLabel {}
+/// A `(hierarchical_label ...)`, connecting the wire it is on to the pin with the same name on the
+/// sheet symbol of its parent sheet.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "hierarchical_label text")]
+pub struct HierarchicalLabel {
+    pub text: String,
+    pub shape: LabelShape,
+    #[serde(rename = "at")]
+    pub position: Position,
+    #[serde(default, skip_serializing_if = "parser::is_false")]
+    pub fields_autoplaced: bool,
+    pub effects: TextEffect,
+    pub uuid: Uuid,
+    unknown: Vec<SExpr>,
+}
+
+impl HierarchicalLabel {
+    /// A label at `(x, y)`, its text going towards `rotation`.
+    pub fn new(text: &str, shape: LabelShape, (x, y): (Coord, Coord), rotation: Rotation) -> Self {
+        let Label { text, shape, position, fields_autoplaced, effects, uuid, unknown } =
+            Label::new(text, shape, (x, y), rotation);
+        Self { text, shape, position, fields_autoplaced, effects, uuid, unknown }
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("hierarchical_label")?;
+        parser::from_sexpr(node)
+    }
+}
 
 impl Display for HierarchicalLabel {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result { todo!() }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
-#[derive(Debug)]
-pub struct HierarchicalSheet {}
+/// The direction of the signal through a global or hierarchical label, drawn as its outline.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelShape {
+    Input,
+    Output,
+    Bidirectional,
+    TriState,
+    Passive,
+}
 
-impl Display for HierarchicalSheet {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result { todo!() }
+/// Which label [`KicadSch::label_pin`] attaches.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LabelKind {
+    Local,
+    Global(LabelShape),
+    Hierarchical(LabelShape),
 }
 
-#[derive(Debug)]
-pub struct HierarchicalPin {}
+/// The number of `pin`, one of the pins of a symbol.
+fn pin_number(pin: &Graphic) -> PinNumber {
+    let Graphic::Pin { number, .. } = pin else { unreachable!("Should always be a pin") };
+    number.clone()
+}
 
-impl Display for HierarchicalPin {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result { todo!() }
+/// The rotation of a label whose text reads towards `(dx, dy)`, one of `(±1, 0)` or `(0, ±1)`.
+fn label_rotation((dx, dy): (i64, i64)) -> Rotation {
+    // The sheet's Y axis grows downwards, against the rotations
+    match (dx, dy) {
+        (1, _) => Rotation::Deg0,
+        (-1, _) => Rotation::Deg180,
+        (_, -1) => Rotation::Deg90,
+        _ => Rotation::Deg270,
+    }
+}
+
+/// Labels are anchored where they connect, their text reading away from that point.
+fn label_justify(rotation: Rotation) -> Justify {
+    match rotation {
+        Rotation::Deg0 | Rotation::Deg90 => Justify::Left,
+        Rotation::Deg180 | Rotation::Deg270 => Justify::Right,
+    }
 }
 
+/// The `(sheet_instances ...)` of the root schematic.
 #[derive(Debug)]
-pub struct Page {
+pub struct SheetInstance {
     path: String,
-    page_number: usize,
+    page: String,
+}
+
+impl SheetInstance {
+    fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("sheet_instances")?;
+        let path = node.expect_child("path")?;
+        Ok(Self {
+            path: path.expect_arg(0)?.expect_string()?.to_string(),
+            page: path.expect_child("page")?.expect_arg(0)?.expect_string()?.to_string(),
+        })
+    }
+}
+
+/// Whether `node` is the `(embedded_fonts no)` KiCad 9 ends schematics and library symbols
+/// with, which is written again rather than kept with the unknown nodes.
+pub(crate) fn is_default_embedded_fonts(node: &SExpr) -> bool {
+    let no = |arg: &SExpr| arg.as_atom() == Some("no");
+    node.is_list("embedded_fonts") && node.expect_arg(0).is_ok_and(no)
+}
+
+/// Writes the unknown nodes, then `(embedded_fonts no)` unless they include embedded fonts.
+pub(crate) fn write_unknown_and_embedded_fonts(
+    f: &mut Formatter<'_>,
+    unknown: &[SExpr],
+) -> std::fmt::Result {
+    parser::write_unknown(f, unknown)?;
+    if !unknown.iter().any(|node| node.is_list("embedded_fonts")) {
+        f.write_str("\n(embedded_fonts no)")?;
+    }
+    Ok(())
 }
 
+/// Reads the `(uuid UUID)` child of `node`.
+pub(crate) fn extract_uuid(node: &SExpr) -> Result<Uuid, ParseError> {
+    let uuid = node.expect_child("uuid")?.expect_arg(0)?;
+    uuid.expect_string()?.parse().map_err(|_| uuid.unexpected("a UUID".to_string()))
+}
+
+/// `(at X Y [ROTATION])`
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "at x y rotation")]
 pub struct Position {
-    pub x: f32,
-    pub y: f32,
+    pub x:        Coord,
+    pub y:        Coord,
     pub rotation: Option<f32>,
 }
 
 impl Position {
-    pub fn extract_from(content: &str) -> Result<(Self, &str), String> {
-        let (position, content) =
-            parser::expect_regex(content, r#"\(at -?\d+(\.\d+)? -?\d+(\.\d+)? -?\d+(\.\d+)?\)"#)?;
-        let position = position.replace("(at ", "").replace(")", "");
-        let position = position
-            .split(' ')
-            .map(|value| f32::from_str(value).expect("Failed to parse float"))
-            .collect::<Vec<_>>();
-        Ok((Self { x: position[0], y: position[1], rotation: position.get(2).copied() }, content))
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("at")?;
+        parser::from_sexpr(node)
     }
+
+    /// The rotation, rounded to a quarter turn.
+    pub fn angle(&self) -> Rotation { Rotation::from_degrees(self.rotation.unwrap_or(0.)) }
 }
 
 impl Display for Position {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "(at {} {} {})", self.x, self.y, self.rotation.unwrap_or(0.0))
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use proptest::{option, prelude::*, sample::select};
+
+    use super::*;
+    use crate::schematic::{coord::tests::{coord, length},
+                           geometry::Mirror,
+                           symbol::tests::timer};
+
+    pub fn position() -> impl Strategy<Value = Position> {
+        (coord(), coord(), option::of(-360.0..360.0f32))
+            .prop_map(|(x, y, rotation)| Position { x, y, rotation })
+    }
+
+    #[test]
+    fn junctions_and_no_connects_are_added_where_needed() {
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let mut sheet = KicadSch::default();
+        sheet.place_oriented(&timer(), mm(101.6, 50.8), Orientation::default()).unwrap();
+        sheet.place_oriented(&timer(), mm(152.4, 50.8), Orientation::default()).unwrap();
+        sheet.connect(("U1", "TR"), ("U2", "TR")).unwrap();
+        sheet.connect(("U2", "VCC"), ("U1", "TR")).unwrap();
+
+        sheet.add_junctions();
+        sheet.add_junctions();
+        assert_eq!(sheet.junctions.len(), 1);
+        let junction = (sheet.junctions[0].position.x, sheet.junctions[0].position.y);
+        let wires = &sheet.wires_and_buses;
+        assert!(wires.iter().any(|wire| wire.start == junction || wire.end == junction));
+
+        sheet.add_no_connects();
+        sheet.add_no_connects();
+        let flags: Vec<_> =
+            sheet.no_connects.iter().map(|flag| (flag.position.x, flag.position.y)).collect();
+        assert_eq!(flags, [sheet.symbols[0].pin_position("VCC").unwrap()]);
+    }
+
+    #[test]
+    fn pins_under_buses_are_flagged_and_pins_under_labels_are_not() {
+        let mut sheet = KicadSch::default();
+        let at = (Coord::from_mm(101.6), Coord::from_mm(50.8));
+        sheet.place_oriented(&timer(), at, Orientation::default()).unwrap();
+        let pin = |name| sheet.symbols[0].pin_position(name).unwrap();
+        let (trigger, supply) = (pin("TR"), pin("VCC"));
+        let ((x, y), step) = (trigger, Coord::from_mm(5.08));
+        sheet.add_wire(WireOrBus::bus((x - step, y), (x + step, y)));
+        sheet.add_local_label(LocalLabel::new("VCC", supply, Rotation::Deg90));
+
+        sheet.add_no_connects();
+        let flags: Vec<_> =
+            sheet.no_connects.iter().map(|flag| (flag.position.x, flag.position.y)).collect();
+        assert_eq!(flags, [trigger]);
+    }
+
+    #[test]
+    fn labels_are_attached_away_from_their_pin() {
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let mut sheet = KicadSch::default();
+        sheet.place_oriented(&timer(), mm(101.6, 50.8), Orientation::default()).unwrap();
+        sheet.label_pin(("U1", "TR"), "TRIGGER", LabelKind::Local).unwrap();
+        sheet.label_pin(("U1", "8"), "VCC", LabelKind::Global(LabelShape::Input)).unwrap();
+        assert!(sheet.label_pin(("U1", "OUT"), "OUT", LabelKind::Local).is_err());
+
+        // TR points left and VCC up, from 10.16 mm away from the centre
+        assert_eq!(sheet.wires_and_buses.len(), 2);
+        let label = &sheet.local_labels[0];
+        assert_eq!((label.position.x, label.position.y), mm(101.6 - 10.16 - 2.54, 50.8 - 2.54));
+        assert_eq!(label.position.angle(), Rotation::Deg180);
+        let label = &sheet.labels[0];
+        assert_eq!((label.position.x, label.position.y), mm(101.6, 50.8 - 10.16 - 2.54));
+        assert_eq!(label.position.angle(), Rotation::Deg90);
+
+        sheet.add_no_connects();
+        assert!(sheet.no_connects.is_empty());
+    }
+
+    #[test]
+    fn buses_fan_out_to_pins() {
+        // Data pins on the right of a latch, 2.54 mm apart
+        let font = "(effects (font (size 1.27 1.27)))";
+        let pin = |number: usize| {
+            format!(
+                "(pin bidirectional line (at 7.62 {y} 180) (length 2.54) (name \"D{number}\" \
+                 {font}) (number \"{number}\" {font}))",
+                y = 2.54 * number as f64
+            )
+        };
+        let pins: String = (0..4).map(pin).collect();
+        let symbol = format!(
+            "(symbol \"LATCH\" (property \"Reference\" \"U\" (at 0 0 0) {font}) (symbol \
+             \"LATCH_1_1\" {pins}))"
+        );
+        let symbol = Symbol::extract_from(&parser::parse(&symbol).unwrap(), "Logic").unwrap();
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let mut sheet = KicadSch::default();
+        sheet.place_oriented(&symbol, mm(101.6, 50.8), Orientation::default()).unwrap();
+        let kind = LabelKind::Global(LabelShape::Bidirectional);
+        assert!(sheet.fan_out_bus("U1", &["D0", "D1", "D2"], "D[0..3]", kind).is_err());
+        assert!(sheet.fan_out_bus("U1", &["D0", "D1"], "{SDA}", kind).is_err());
+        sheet.fan_out_bus("U1", &["D0", "D1", "D2", "D3"], "D[0..3]", kind).unwrap();
+
+        let nets: Vec<_> = sheet.local_labels.iter().map(|label| label.text.as_str()).collect();
+        assert_eq!(nets, ["D0", "D1", "D2", "D3"]);
+        // Every wire ends on a bus entry, and every bus entry on the bus
+        let (wires, buses) = sheet.wires_and_buses.split_at(4);
+        let [bus] = buses else { panic!("Should be a single bus") };
+        assert_eq!(bus.kind, WireKind::Bus);
+        assert_eq!(sheet.bus_entries.len(), 4);
+        for (wire, entry) in wires.iter().zip(&sheet.bus_entries) {
+            let [start, end] = entry.ends();
+            assert_eq!(wire.end, start);
+            assert!(bus.contains(end));
+        }
+        assert_eq!(bus.start.0, Coord::from_mm(101.6 + 7.62 + 10.16 + 2.54));
+        assert_eq!(sheet.labels[0].text, "D[0..3]");
+        assert!(bus.contains((sheet.labels[0].position.x, sheet.labels[0].position.y)));
+    }
+
+    #[test]
+    fn generated_schematics_read_back() {
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let mut sheet = KicadSch { project_name: "clock".to_string(), ..Default::default() };
+        let orientation = Orientation { rotation: Rotation::Deg90, mirror: Some(Mirror::Y) };
+        sheet.place_oriented(&timer(), mm(50.8, 50.8), orientation).unwrap();
+        sheet.place_oriented(&timer(), mm(101.6, 50.8), Orientation::default()).unwrap();
+        sheet.connect(("U1", "TR"), ("U2", "TR")).unwrap();
+        sheet.label_pin(("U1", "VCC"), "VCC", LabelKind::Global(LabelShape::Input)).unwrap();
+        sheet.frame_symbols(&["U1", "U2"], "Timers").unwrap();
+
+        let written = sheet.to_string();
+        let read = KicadSch::from_string(&written).unwrap();
+        assert_eq!(read.to_string(), written);
+        assert_eq!(read.project_name, "clock");
+        let pin = |sheet: &KicadSch| sheet.symbol("U1").unwrap().pin_position("TR");
+        assert_eq!(pin(&read), pin(&sheet));
+
+        // With the nodes KiCad 9 adds when saving
+        let flags = "(unit 1)\n\t\t(exclude_from_sim no)\n\t\t(in_bom yes)\n\t\t(on_board \
+                     yes)\n\t\t(dnp no)";
+        assert_eq!(written.matches(flags).count(), 2, "{written}");
+        assert!(written.ends_with("\t(embedded_fonts no)\n)\n"), "{written}");
+        assert!(!written.contains("\n\n"), "{written}");
+    }
+
+    #[test]
+    fn symbols_are_found_moved_and_removed() {
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let mut sheet = KicadSch::default();
+        for x in [50.8, 101.6] {
+            sheet.place_oriented(&timer(), mm(x, 50.8), Orientation::default()).unwrap();
+        }
+        let uuid = sheet.symbol("U2").unwrap().uuid();
+        assert_eq!(sheet.symbol_by_uuid(uuid).unwrap().reference(), "U2");
+        assert_eq!(sheet.symbols_by_lib_id("Timer:NE555").count(), 2);
+        assert_eq!(sheet.symbols_by_lib_id("Device:R").count(), 0);
+
+        sheet.move_symbol("U2", mm(101.6, 76.2)).unwrap();
+        assert_eq!(sheet.symbol("U2").unwrap().pin_position("8"), Some(mm(101.6, 66.04)));
+        assert!(sheet.move_symbol("U3", mm(0., 0.)).is_err());
+
+        sheet.remove_symbol("U1").unwrap();
+        assert_eq!(sheet.symbols().map(SymbolInstance::reference).collect::<Vec<_>>(), ["U2"]);
+        assert_eq!(sheet.lib_symbols.len(), 1);
+        sheet.remove_symbol("U2").unwrap();
+        assert!(sheet.lib_symbols.is_empty());
+        assert!(sheet.remove_symbol("U2").is_err());
+    }
+
+    #[test]
+    fn replaced_symbols_keep_their_wires() {
+        // The pins of the NE555, elsewhere
+        let font = "(effects (font (size 1.27 1.27)))";
+        let symbol = format!(
+            "(symbol \"TLC555\" (property \"Reference\" \"IC\" (at 0 0 0) {font}) (symbol \
+             \"TLC555_1_1\" (pin power_in line (at 0 7.62 270) (length 2.54) (name \"VDD\" \
+             {font}) (number \"8\" {font})) (pin input line (at -7.62 0 0) (length 2.54) (name \
+             \"TRIG\" {font}) (number \"2\" {font}))))"
+        );
+        let tlc555 = Symbol::extract_from(&parser::parse(&symbol).unwrap(), "Timer").unwrap();
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let mut sheet = KicadSch::default();
+        for x in [50.8, 101.6] {
+            sheet.place_oriented(&timer(), mm(x, 50.8), Orientation::default()).unwrap();
+        }
+        sheet.connect(("U1", "TR"), ("U2", "TR")).unwrap();
+        sheet.label_pin(("U2", "VCC"), "VCC", LabelKind::Local).unwrap();
+        let pin = |sheet: &KicadSch, name| sheet.symbol("U2").unwrap().pin_position(name).unwrap();
+        sheet.add_local_label(LocalLabel::new("SUPPLY", pin(&sheet, "VCC"), Rotation::Deg90));
+        sheet.junctions.push(Junction::new(pin(&sheet, "TR")));
+        let uuid = sheet.symbol("U2").unwrap().uuid();
+
+        sheet.replace_symbol("U2", &tlc555).unwrap();
+        let replaced = sheet.symbol("U2").unwrap();
+        assert_eq!((replaced.name.as_str(), replaced.uuid()), ("Timer:TLC555", uuid));
+        assert_eq!(replaced.pin("TR"), None);
+        for pin in ["TRIG", "VDD"] {
+            let point = replaced.pin_position(pin).unwrap();
+            let wires = &sheet.wires_and_buses;
+            assert!(wires.iter().any(|wire| wire.start == point || wire.end == point));
+        }
+        // Moved pins get elbows rather than diagonal wires, and take their label and junction
+        let wires = &sheet.wires_and_buses;
+        assert!(wires.iter().all(|wire| wire.start.0 == wire.end.0 || wire.start.1 == wire.end.1));
+        let label = &sheet.local_labels[1].position;
+        assert_eq!((label.x, label.y), pin(&sheet, "VDD"));
+        let junction = &sheet.junctions[0].position;
+        assert_eq!((junction.x, junction.y), pin(&sheet, "TRIG"));
+        // The timer is still placed as U1
+        assert_eq!(sheet.lib_symbols.len(), 2);
+        sheet.replace_symbol("U1", &tlc555).unwrap();
+        assert_eq!(sheet.lib_symbols, [tlc555]);
+        assert!(sheet.to_string().contains("(property \"Reference\" \"U1\""));
+    }
+
+    #[test]
+    fn frames_surround_symbols_on_the_grid() {
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let mut sheet = KicadSch::default();
+        for x in [50.8, 101.6] {
+            sheet.place_oriented(&timer(), mm(x, 50.8), Orientation::default()).unwrap();
+        }
+        assert!(sheet.frame_symbols(&["U1", "U3"], "Timers").is_err());
+        assert!(sheet.frame_symbols(&[], "Timers").is_err());
+        sheet.frame_symbols(&["U1", "U2"], "Timers").unwrap();
+
+        let [Drawing { graphic: Graphic::Rectangle { start, end, stroke, .. }, .. }] =
+            sheet.drawings.as_slice()
+        else {
+            panic!("Should be a single rectangle")
+        };
+        assert_eq!(*stroke, Stroke::new(Length::ZERO, StrokeType::Dash));
+        let frame = BoundingBox::new(*start, *end);
+        for symbol in &sheet.symbols {
+            let body = symbol.bounding_box().unwrap().inflate(FRAME_MARGIN);
+            assert_eq!(frame.union(body), frame);
+        }
+        for coord in [start.0, start.1, end.0, end.1] {
+            assert_eq!(coord.nm() % GRID.nm(), 0);
+        }
+        assert_eq!(sheet.texts[0].text, "Timers");
+        assert!(frame.contains((sheet.texts[0].position.x, sheet.texts[0].position.y)));
+        assert!(sheet.draw(Graphic::Text {
+            text:        "Timers".to_string(),
+            private:     false,
+            position:    sheet.texts[0].position.clone(),
+            text_effect: TextEffect::default(),
+            unknown:     vec![],
+        })
+        .is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn junctions_round_trip(x in coord(), y in coord(), diameter in length()) {
+            let junction = Junction { diameter, ..Junction::new((x, y)) };
+            let node = parser::parse(&junction.to_string())?;
+            prop_assert_eq!(Junction::extract_from(&node)?, junction);
+            let no_connect = NoConnect::new((x, y));
+            let node = parser::parse(&no_connect.to_string())?;
+            prop_assert_eq!(NoConnect::extract_from(&node)?, no_connect);
+        }
+
+        #[test]
+        fn wires_round_trip(start in (coord(), coord()), end in (coord(), coord()), bus: bool) {
+            let wire = if bus { WireOrBus::bus(start, end) } else { WireOrBus::wire(start, end) };
+            let node = parser::parse(&wire.to_string())?;
+            prop_assert_eq!(WireOrBus::extract_from(&node)?, wire);
+        }
+
+        #[test]
+        fn labels_round_trip(
+            text in "[A-Za-z0-9_/~{}]{1,10}",
+            at in (coord(), coord()),
+            rotation in select(vec![Rotation::Deg0, Rotation::Deg90, Rotation::Deg180]),
+            shape in select(vec![LabelShape::Input, LabelShape::TriState, LabelShape::Passive]),
+        ) {
+            let label = Label::new(&text, shape, at, rotation);
+            prop_assert_eq!(Label::extract_from(&parser::parse(&label.to_string())?)?, label);
+            let label = LocalLabel::new(&text, at, rotation);
+            prop_assert_eq!(LocalLabel::extract_from(&parser::parse(&label.to_string())?)?, label);
+            let label = HierarchicalLabel::new(&text, shape, at, rotation);
+            let node = parser::parse(&label.to_string())?;
+            prop_assert_eq!(HierarchicalLabel::extract_from(&node)?, label);
+        }
     }
 }
@@ -0,0 +1,285 @@
+use std::fmt::{Display, Formatter};
+
+use crate::{parser::{ParseError, Quoted, SExpr},
+            schematic::coord::{Coord, Length}};
+
+/// The size of the sheet a schematic is drawn on, as `(paper "A4")`. Sizes are given in
+/// landscape, see [`Page`] to turn them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Paper {
+    A5,
+    #[default]
+    A4,
+    A3,
+    A2,
+    A1,
+    A0,
+    UsLetter,
+    UsLegal,
+    UsTabloid,
+    AnsiA,
+    AnsiB,
+    AnsiC,
+    AnsiD,
+    AnsiE,
+    Gerber, // A 32 inch square, for plots
+    Custom { width: Length, height: Length }, // `(paper "User" WIDTH HEIGHT)`
+}
+
+impl Paper {
+    /// Width and height in landscape, like KiCad.
+    pub fn size(self) -> (Length, Length) {
+        let mm = |width, height| (Coord::from_mm(width), Coord::from_mm(height));
+        match self {
+            Self::A5 => mm(210., 148.),
+            Self::A4 => mm(297., 210.),
+            Self::A3 => mm(420., 297.),
+            Self::A2 => mm(594., 420.),
+            Self::A1 => mm(841., 594.),
+            Self::A0 => mm(1189., 841.),
+            Self::UsLetter => mm(279.4, 215.9),
+            Self::UsLegal => mm(355.6, 215.9),
+            Self::UsTabloid => mm(431.8, 279.4),
+            Self::AnsiA => mm(279.4, 215.9),
+            Self::AnsiB => mm(431.8, 279.4),
+            Self::AnsiC => mm(558.8, 431.8),
+            Self::AnsiD => mm(863.6, 558.8),
+            Self::AnsiE => mm(1117.6, 863.6),
+            Self::Gerber => mm(812.8, 812.8),
+            Self::Custom { width, height } => (width, height),
+        }
+    }
+
+    /// The name KiCad gives the size.
+    fn name(self) -> &'static str {
+        match self {
+            Self::A5 => "A5",
+            Self::A4 => "A4",
+            Self::A3 => "A3",
+            Self::A2 => "A2",
+            Self::A1 => "A1",
+            Self::A0 => "A0",
+            Self::UsLetter => "USLetter",
+            Self::UsLegal => "USLegal",
+            Self::UsTabloid => "USLedger",
+            Self::AnsiA => "A",
+            Self::AnsiB => "B",
+            Self::AnsiC => "C",
+            Self::AnsiD => "D",
+            Self::AnsiE => "E",
+            Self::Gerber => "GERBER",
+            Self::Custom { .. } => "User",
+        }
+    }
+}
+
+/// A paper size and whether it is turned upright, written as `(paper "A4" [portrait])`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Page {
+    pub paper:    Paper,
+    pub portrait: bool, // Ignored by custom sizes, which give their own width and height
+}
+
+impl Page {
+    pub fn new(paper: Paper, portrait: bool) -> Self { Self { paper, portrait } }
+
+    /// Width and height of the page as drawn, Y growing downwards from the top-left corner.
+    pub fn size(&self) -> (Length, Length) {
+        let (width, height) = self.paper.size();
+        match self.paper {
+            Paper::Custom { .. } => (width, height),
+            _ if self.portrait => (height, width),
+            _ => (width, height),
+        }
+    }
+
+    pub fn width(&self) -> Length { self.size().0 }
+
+    pub fn height(&self) -> Length { self.size().1 }
+
+    /// The middle of the page.
+    pub fn center(&self) -> (Coord, Coord) {
+        let (width, height) = self.size();
+        (width / 2, height / 2)
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("paper")?;
+        let name = node.expect_arg(0)?;
+        let paper = match name.expect_string()? {
+            "A5" => Paper::A5,
+            "A4" => Paper::A4,
+            "A3" => Paper::A3,
+            "A2" => Paper::A2,
+            "A1" => Paper::A1,
+            "A0" => Paper::A0,
+            "USLetter" => Paper::UsLetter,
+            "USLegal" => Paper::UsLegal,
+            "USLedger" => Paper::UsTabloid,
+            "A" => Paper::AnsiA,
+            "B" => Paper::AnsiB,
+            "C" => Paper::AnsiC,
+            "D" => Paper::AnsiD,
+            "E" => Paper::AnsiE,
+            "GERBER" => Paper::Gerber,
+            "User" => Paper::Custom {
+                width:  node.expect_arg(1)?.expect_number()?,
+                height: node.expect_arg(2)?.expect_number()?,
+            },
+            other => return Err(name.unknown_value("paper", other)),
+        };
+        let portrait = node.args().iter().any(|arg| arg.as_atom() == Some("portrait"));
+        Ok(Self { paper, portrait })
+    }
+}
+
+impl Display for Page {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("(paper {}", Quoted(self.paper.name())))?;
+        match self.paper {
+            Paper::Custom { width, height } => f.write_fmt(format_args!(" {width} {height}"))?,
+            _ if self.portrait => f.write_str(" portrait")?,
+            _ => {},
+        }
+        f.write_str(")")
+    }
+}
+
+/// The `(title_block ...)` filled in the frame of the page. Empty fields are not written.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TitleBlock {
+    pub title:    String,
+    pub date:     String, // Like "2025-01-31"
+    pub revision: String,
+    pub company:  String,
+    pub comments: Vec<String>, // Comment lines 1 to 9
+}
+
+impl TitleBlock {
+    pub fn new(title: &str, date: &str, revision: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            date: date.to_string(),
+            revision: revision.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_company(self, company: &str) -> Self {
+        Self { company: company.to_string(), ..self }
+    }
+
+    pub fn with_comments(self, comments: &[&str]) -> Self {
+        Self { comments: comments.iter().map(|comment| comment.to_string()).collect(), ..self }
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("title_block")?;
+        let field = |name| match node.child(name) {
+            Some(child) => Ok(child.expect_arg(0)?.expect_string()?.to_string()),
+            None => Ok::<_, ParseError>("".to_string()),
+        };
+        let mut title_block = Self {
+            title: field("title")?,
+            date: field("date")?,
+            revision: field("rev")?,
+            company: field("company")?,
+            comments: vec![],
+        };
+        for comment in node.args().iter().filter(|arg| arg.is_list("comment")) {
+            let line: usize = comment.expect_arg(0)?.expect_number()?;
+            if !(1..=9).contains(&line) {
+                return Err(comment.unexpected("a comment line from 1 to 9".to_string()));
+            }
+            if title_block.comments.len() < line {
+                title_block.comments.resize(line, "".to_string());
+            }
+            title_block.comments[line - 1] = comment.expect_arg(1)?.expect_string()?.to_string();
+        }
+        Ok(title_block)
+    }
+}
+
+impl Display for TitleBlock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("(title_block")?;
+        let fields = [
+            ("title", &self.title),
+            ("date", &self.date),
+            ("rev", &self.revision),
+            ("company", &self.company),
+        ];
+        for (name, value) in fields.into_iter().filter(|(_, value)| !value.is_empty()) {
+            f.write_fmt(format_args!("\n({name} {})", Quoted(value)))?;
+        }
+        for (line, comment) in self.comments.iter().enumerate().take(9) {
+            if !comment.is_empty() {
+                f.write_fmt(format_args!("\n(comment {} {})", line + 1, Quoted(comment)))?;
+            }
+        }
+        f.write_str(")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{prelude::*, sample::select};
+
+    use super::*;
+    use crate::{parser, schematic::coord::tests::length};
+
+    #[test]
+    fn pages_turn_upright() {
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        assert_eq!(Page::default().size(), mm(297., 210.));
+        assert_eq!(Page::new(Paper::A3, true).size(), mm(297., 420.));
+        assert_eq!(Page::new(Paper::UsLetter, false).center(), mm(139.7, 107.95));
+        assert_eq!(Page::new(Paper::AnsiD, true).size(), mm(558.8, 863.6));
+        assert_eq!(Page::new(Paper::AnsiE, false).to_string(), "(paper \"E\")");
+        let custom = Paper::Custom { width: Coord::from_mm(100.), height: Coord::from_mm(200.) };
+        assert_eq!(Page::new(custom, true).size(), mm(100., 200.));
+        assert_eq!(Page::new(Paper::A4, true).to_string(), "(paper \"A4\" portrait)");
+        assert_eq!(Page::new(custom, false).to_string(), "(paper \"User\" 100 200)");
+    }
+
+    proptest! {
+        #[test]
+        fn pages_round_trip(
+            paper in select(vec![
+                Paper::A5,
+                Paper::A0,
+                Paper::UsLegal,
+                Paper::UsTabloid,
+                Paper::AnsiA,
+                Paper::AnsiC,
+                Paper::AnsiE,
+                Paper::Gerber,
+            ]),
+            (width, height) in (length(), length()),
+            custom: bool,
+            portrait: bool,
+        ) {
+            let page = match custom {
+                true => Page::new(Paper::Custom { width, height }, false),
+                false => Page::new(paper, portrait),
+            };
+            prop_assert_eq!(Page::extract_from(&parser::parse(&page.to_string())?)?, page);
+        }
+
+        #[test]
+        fn title_blocks_round_trip(
+            fields in prop::array::uniform4(any::<String>()),
+            comments in prop::collection::vec(any::<String>(), 0..9),
+        ) {
+            let [title, date, revision, company] = fields;
+            let title_block = TitleBlock { title, date, revision, company, comments };
+            let node = parser::parse(&title_block.to_string())?;
+            let mut expected = title_block.clone();
+            // Trailing empty comments are not written
+            while expected.comments.last().is_some_and(String::is_empty) {
+                expected.comments.pop();
+            }
+            prop_assert_eq!(TitleBlock::extract_from(&node)?, expected);
+        }
+    }
+}
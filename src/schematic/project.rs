@@ -0,0 +1,276 @@
+use std::{collections::{HashMap, HashSet},
+          path::Path};
+
+use crate::schematic::{coord::Coord,
+                       page::TitleBlock,
+                       sheet::{HierarchicalSheet, SheetPath, SHEET_PIN_PITCH, SHEET_WIDTH},
+                       symbol::InstancePath,
+                       KicadSch,
+                       SheetInstance};
+
+/// A root schematic and the sub-sheets placed in it, each written to its own `.kicad_sch` file.
+/// A sub-sheet may be placed several times, its symbols then get a reference per instance.
+#[derive(Debug)]
+pub struct Project {
+    pub name:   String,
+    schematics: Vec<(String, KicadSch)>, // File names and their schematic, the root first
+}
+
+/// A sheet opened by a sheet of another schematic, in the order of the pages.
+struct Visit {
+    file: usize,
+    path: String, // "/{root_uuid}/{sheet_uuid}..."
+}
+
+impl Project {
+    /// A project with an empty root schematic, written to `{name}.kicad_sch`.
+    pub fn new(name: &str) -> Self {
+        let root = KicadSch { project_name: name.to_string(), ..Default::default() };
+        Self { name: name.to_string(), schematics: vec![(format!("{name}.kicad_sch"), root)] }
+    }
+
+    pub fn root(&mut self) -> &mut KicadSch { &mut self.schematics[0].1 }
+
+    /// The schematic written to `file`, like `"power.kicad_sch"`.
+    pub fn schematic(&mut self, file: &str) -> Option<&mut KicadSch> {
+        self.schematics.iter_mut().find(|(name, _)| name == file).map(|(_, schematic)| schematic)
+    }
+
+    /// Adds a sub-sheet written to `file`, to place with [`Self::add_sheet`]. It gets the title
+    /// block of the root schematic when it has none.
+    pub fn add_schematic(&mut self, file: &str, mut schematic: KicadSch) -> Result<(), String> {
+        if self.schematic(file).is_some() {
+            return Err(format!("There already is a schematic in {file}"));
+        }
+        schematic.project_name = self.name.clone();
+        if schematic.title_block.is_none() {
+            schematic.title_block = self.schematics[0].1.title_block.clone();
+        }
+        self.schematics.push((file.to_string(), schematic));
+        Ok(())
+    }
+
+    /// Sets the title block of every schematic of the project.
+    pub fn set_title_block(&mut self, title_block: &TitleBlock) {
+        for (_, schematic) in &mut self.schematics {
+            schematic.set_title_block(title_block.clone());
+        }
+    }
+
+    /// Places the sub-sheet `file` on the schematic `parent` as a sheet named `name`, its top-left
+    /// corner at `position`, with a pin for each hierarchical label of the sub-sheet.
+    pub fn add_sheet(
+        &mut self,
+        parent: &str,
+        file: &str,
+        name: &str,
+        position: (Coord, Coord),
+    ) -> Result<(), String> {
+        let labels: Vec<_> = self
+            .schematic(file)
+            .ok_or(format!("No schematic in {file}"))?
+            .hierarchical_labels
+            .iter()
+            .map(|label| (label.text.clone(), label.shape))
+            .collect();
+        let mut sheet =
+            HierarchicalSheet::new(name, file, position, (SHEET_WIDTH, SHEET_PIN_PITCH));
+        for (text, shape) in labels {
+            if sheet.pin(&text).is_none() {
+                sheet.add_pin(&text, shape);
+            }
+        }
+        self.schematic(parent).ok_or(format!("No schematic in {parent}"))?.add_sheet(sheet)
+    }
+
+    /// Numbers the pages of every sheet instance, depth first from the root, and writes on each
+    /// sheet and symbol the paths of its instances. References stay as placed in the first
+    /// instance using them, and take the next free number with their prefix in the others.
+    pub fn annotate(&mut self) -> Result<(), String> {
+        let root = format!("/{}", self.schematics[0].1.uuid);
+        let mut visits = vec![];
+        self.visit(0, root, &mut vec![0], &mut visits)?;
+
+        for (_, schematic) in &mut self.schematics {
+            schematic.project_name = self.name.clone();
+            schematic.sheet_instance = None;
+            for sheet in &mut schematic.hierarchical_sheets {
+                sheet.project_name = self.name.clone();
+                sheet.instances.clear();
+            }
+        }
+        let root = SheetInstance { path: "/".to_string(), page: "1".to_string() };
+        self.schematics[0].1.sheet_instance = Some(root);
+        for (page, visit) in visits.iter().enumerate().skip(1) {
+            let (parent, sheet) = visit.path.rsplit_once('/').unwrap();
+            let parent = visits.iter().find(|v| v.path == parent).unwrap();
+            let sheet = self.schematics[parent.file]
+                .1
+                .hierarchical_sheets
+                .iter_mut()
+                .find(|s| s.uuid.to_string() == sheet)
+                .unwrap();
+            let page = (page + 1).to_string();
+            sheet.instances.push(SheetPath { path: parent.path.clone(), page });
+        }
+
+        let mut paths: Vec<Vec<Vec<InstancePath>>> = self
+            .schematics
+            .iter()
+            .map(|(_, schematic)| vec![vec![]; schematic.symbols.len()])
+            .collect();
+        let mut used = HashSet::new();
+        for visit in &visits {
+            // Units of a symbol share its reference
+            let mut references: HashMap<&str, String> = HashMap::new();
+            for (index, symbol) in self.schematics[visit.file].1.symbols.iter().enumerate() {
+                let placed = symbol.placed_reference();
+                let reference = references
+                    .entry(placed)
+                    .or_insert_with(|| next_free_reference(placed, &mut used))
+                    .clone();
+                let unit = symbol.unit();
+                paths[visit.file][index].push(InstancePath {
+                    path: visit.path.clone(),
+                    reference,
+                    unit,
+                });
+            }
+        }
+        for ((_, schematic), paths) in self.schematics.iter_mut().zip(paths) {
+            for (symbol, paths) in schematic.symbols.iter_mut().zip(paths) {
+                // Symbols of sheets placed nowhere keep their own instance
+                if !paths.is_empty() {
+                    symbol.set_instances(&self.name, paths);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks the sheets opened from the schematic `file` placed at `path`, `stack` holding the
+    /// schematics it is in to reject sheets containing themselves.
+    fn visit(
+        &self,
+        file: usize,
+        path: String,
+        stack: &mut Vec<usize>,
+        visits: &mut Vec<Visit>,
+    ) -> Result<(), String> {
+        visits.push(Visit { file, path: path.clone() });
+        for sheet in &self.schematics[file].1.hierarchical_sheets {
+            let child =
+                self.schematics.iter().position(|(name, _)| name == sheet.file()).ok_or(
+                    format!("No schematic in {} for sheet {}", sheet.file(), sheet.name()),
+                )?;
+            if stack.contains(&child) {
+                return Err(format!("Sheet {} contains itself", sheet.name()));
+            }
+            stack.push(child);
+            self.visit(child, format!("{path}/{}", sheet.uuid), stack, visits)?;
+            stack.pop();
+        }
+        Ok(())
+    }
+
+    /// The name and content of each file of the project, once annotated.
+    pub fn files(&mut self) -> Result<Vec<(String, String)>, String> {
+        self.annotate()?;
+        Ok(self
+            .schematics
+            .iter()
+            .map(|(file, schematic)| (file.clone(), schematic.to_string()))
+            .collect())
+    }
+
+    /// Writes every file of the project into the directory `dir`.
+    pub fn write_to(&mut self, dir: &Path) -> Result<(), String> {
+        for (file, content) in self.files()? {
+            let path = dir.join(&file);
+            std::fs::write(&path, content)
+                .map_err(|e| format!("Unable to write {}: {e}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// `reference` if it is not used yet, or else the first free one with its prefix, like `U3` for
+/// `U1` when `U1` and `U2` are used.
+fn next_free_reference(reference: &str, used: &mut HashSet<String>) -> String {
+    let prefix = reference.trim_end_matches(|c: char| c.is_ascii_digit());
+    let reference = std::iter::once(reference.to_string())
+        .chain((1..).map(|number| format!("{prefix}{number}")))
+        .find(|reference| !used.contains(reference))
+        .unwrap();
+    used.insert(reference.clone());
+    reference
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schematic::{geometry::{Orientation, Rotation},
+                           symbol::tests::timer,
+                           HierarchicalLabel,
+                           LabelShape};
+
+    #[test]
+    fn sub_sheets_are_instantiated_with_their_own_references() {
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let mut project = Project::new("clock");
+        project.root().place_oriented(&timer(), mm(50.8, 50.8), Orientation::default()).unwrap();
+
+        let mut blinker = KicadSch::default();
+        blinker.place_oriented(&timer(), mm(101.6, 50.8), Orientation::default()).unwrap();
+        let label =
+            HierarchicalLabel::new("TRIG", LabelShape::Input, mm(76.2, 50.8), Rotation::Deg180);
+        blinker.add_hierarchical_label(label);
+        project.add_schematic("blinker.kicad_sch", blinker).unwrap();
+        for (name, y) in [("Left", 101.6), ("Right", 152.4)] {
+            project.add_sheet("clock.kicad_sch", "blinker.kicad_sch", name, mm(50.8, y)).unwrap();
+        }
+        assert!(
+            project.add_sheet("clock.kicad_sch", "blinker.kicad_sch", "Left", mm(0., 0.)).is_err()
+        );
+        project.root().connect(("Left", "TRIG"), ("Right", "TRIG")).unwrap();
+
+        project.set_title_block(&TitleBlock::new("Clock", "2025-01-31", "B"));
+        let files = project.files().unwrap();
+        assert_eq!(files.iter().map(|(file, _)| file.as_str()).collect::<Vec<_>>(), [
+            "clock.kicad_sch",
+            "blinker.kicad_sch"
+        ]);
+        assert!(files[0].1.contains("(sheet_instances"));
+        assert!(!files[1].1.contains("(sheet_instances"));
+        assert!(files.iter().all(|(_, file)| file.contains("(rev \"B\")")));
+
+        let root = project.schematics[0].1.uuid;
+        let sheets = &project.schematics[0].1.hierarchical_sheets;
+        let pages: Vec<_> = sheets.iter().map(|sheet| sheet.instances[0].page.as_str()).collect();
+        assert_eq!(pages, ["2", "3"]);
+        assert_eq!(sheets[0].instances[0].path, format!("/{root}"));
+        let expected = |sheet: &HierarchicalSheet, reference: &str| InstancePath {
+            path:      format!("/{root}/{}", sheet.uuid),
+            reference: reference.to_string(),
+            unit:      1,
+        };
+        let symbol = &project.schematics[1].1.symbols[0];
+        assert_eq!(symbol.instances(), [expected(&sheets[0], "U2"), expected(&sheets[1], "U3")]);
+        assert_eq!(project.schematics[0].1.symbols[0].reference(), "U1");
+
+        // Annotating again keeps the references
+        project.annotate().unwrap();
+        assert_eq!(project.schematics[1].1.symbols[0].reference(), "U2");
+    }
+
+    #[test]
+    fn sheets_cannot_contain_themselves() {
+        let mut project = Project::new("loop");
+        project.add_schematic("a.kicad_sch", KicadSch::default()).unwrap();
+        project
+            .add_sheet("loop.kicad_sch", "a.kicad_sch", "A", (Coord::ZERO, Coord::ZERO))
+            .unwrap();
+        project.add_sheet("a.kicad_sch", "a.kicad_sch", "A", (Coord::ZERO, Coord::ZERO)).unwrap();
+        assert!(project.annotate().is_err());
+    }
+}
@@ -0,0 +1,377 @@
+use std::{cmp::Reverse,
+          collections::{BinaryHeap, HashMap, HashSet}};
+
+use crate::schematic::{bus::BusEntry,
+                       coord::{Coord, Length},
+                       geometry::BoundingBox,
+                       KicadSch,
+                       WireKind,
+                       WireOrBus};
+
+/// Wires are routed on KiCad's default 50 mil grid.
+pub const GRID: Length = Coord::from_nm(1_270_000);
+/// Grid steps searched around the symbols and wires of the sheet.
+const MARGIN: i64 = 20;
+/// Cost of a bend, in grid steps.
+const BEND_COST: u64 = 4;
+/// Cost of crossing a wire of another net, in grid steps.
+const CROSSING_COST: u64 = 12;
+
+type Point = (Coord, Coord);
+type Cell = (i64, i64);
+
+const DIRECTIONS: [Cell; 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+/// Direction of a search state which has not moved yet.
+const START: usize = 4;
+
+/// A pin to route from or to, and the direction a wire leaves it in, away from its symbol.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PinEnd {
+    pub point:     Point,
+    pub direction: Cell,
+}
+
+/// Finds a path of horizontal and vertical wires between two pins of `sheet`, as the points
+/// where it bends. The path leaves and enters the pins away from their symbols, goes around the
+/// placed symbols and sheets and the pins, labels, junctions and bus entries of other nets, and
+/// never runs along nor bends on buses and the wires of other nets. It costs a step per grid
+/// step, and more per bend and per crossed wire or bus.
+///
+/// Wires already connected to a pin are part of its net, and the path may start or end anywhere
+/// on them. Returns no points when the pins are already connected, and `None` when no path
+/// exists.
+pub(crate) fn route(sheet: &KicadSch, from: PinEnd, to: PinEnd) -> Option<Vec<Point>> {
+    let wires = &sheet.wires_and_buses;
+    let from_net = net(wires, from.point);
+    let to_net = net(wires, to.point);
+    if from.point == to.point || from_net.iter().any(|wire| wire.contains(to.point)) {
+        return Some(vec![]);
+    }
+    let others: Vec<&WireOrBus> =
+        wires.iter().filter(|wire| !from_net.contains(wire) && !to_net.contains(wire)).collect();
+
+    let pins = sheet.pin_positions().into_iter();
+    let junctions = sheet.junctions.iter().map(|j| (j.position.x, j.position.y));
+    let bus_entries = sheet.bus_entries.iter().flat_map(BusEntry::ends);
+    let on_nets = |point: &Point| from_net.iter().chain(&to_net).any(|wire| wire.contains(*point));
+    let markers = sheet.label_positions().into_iter().chain(junctions).chain(bus_entries);
+    let wire_ends = others.iter().flat_map(|wire| [wire.start, wire.end]);
+    let forbidden = pins
+        .chain(markers.filter(|point| !on_nets(point)))
+        .filter(|point| *point != to.point)
+        .chain(wire_ends)
+        .filter_map(cell);
+    let obstacles = sheet.bodies().collect();
+
+    let (from_cell, from_stub) = snap(from);
+    let (to_cell, to_stub) = snap(to);
+    let sources: Vec<Cell> = from_net.iter().flat_map(|wire| cells(wire)).collect();
+    let targets: HashSet<Cell> = to_net.iter().flat_map(|wire| cells(wire)).collect();
+    let arrival = DIRECTIONS.iter().position(|&d| d == (-to.direction.0, -to.direction.1))?;
+
+    let points = sources.iter().chain(&targets).copied().chain([from_cell, to_cell]).map(point);
+    let region = sheet
+        .bodies()
+        .chain(others.iter().map(|wire| BoundingBox::new(wire.start, wire.end)))
+        .chain(BoundingBox::from_points(points))
+        .reduce(BoundingBox::union)?
+        .inflate(GRID * MARGIN);
+
+    let grid = Grid { obstacles, forbidden: forbidden.collect(), others, region };
+    let is_goal = |(cell, direction): (Cell, usize)| {
+        (cell == to_cell && direction == arrival) || targets.contains(&cell)
+    };
+    let heuristic = |(x, y): Cell| match targets.is_empty() {
+        true => (x - to_cell.0).unsigned_abs() + (y - to_cell.1).unsigned_abs(),
+        false => 0, // Any point of the net may be the closest
+    };
+
+    // A* over cells and the direction they were entered in
+    let mut costs: HashMap<(Cell, usize), u64> = HashMap::new();
+    let mut previous: HashMap<(Cell, usize), (Cell, usize)> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    for &source in &sources {
+        costs.insert((source, START), 0);
+        queue.push(Reverse((heuristic(source), 0, source, START)));
+    }
+    // A pin is left straight away from its symbol
+    let outward = DIRECTIONS.iter().position(|&d| d == from.direction)?;
+    let first = step(from_cell, outward);
+    if grid.is_free(from_cell, first) || first == to_cell {
+        costs.insert((first, outward), 1);
+        previous.insert((first, outward), (from_cell, START));
+        queue.push(Reverse((1 + heuristic(first), 1, first, outward)));
+    }
+
+    while let Some(Reverse((_, cost, cell, direction))) = queue.pop() {
+        if costs.get(&(cell, direction)).is_some_and(|&best| best < cost) {
+            continue;
+        }
+        if is_goal((cell, direction)) {
+            let mut cells = vec![cell];
+            let mut state = (cell, direction);
+            while let Some(&before) = previous.get(&state) {
+                cells.push(before.0);
+                state = before;
+            }
+            cells.reverse();
+            // The stubs only join the pins the path starts or ends on, not their nets
+            let from_stub = if cells[0] == from_cell { from_stub } else { vec![] };
+            let to_stub = if cell == to_cell { to_stub } else { vec![] };
+            let path = from_stub.into_iter().chain(cells.into_iter().map(point));
+            return Some(corners(path.chain(to_stub.into_iter().rev()).collect()));
+        }
+
+        for next_direction in 0..4 {
+            let turns = direction != START && next_direction != direction;
+            if turns && (next_direction == (direction + 2) % 4 || grid.crosses(cell)) {
+                continue; // No U-turns, and no bends on other wires
+            }
+            let next = step(cell, next_direction);
+            let arrives = next == to_cell && next_direction == arrival;
+            if !grid.region.contains(point(next))
+                || (next == to_cell && !arrives)
+                || (!arrives && !targets.contains(&next) && !grid.is_free(cell, next))
+            {
+                continue;
+            }
+            let cost = cost
+                + 1
+                + if turns { BEND_COST } else { 0 }
+                + if grid.crosses(next) { CROSSING_COST } else { 0 };
+            let state = (next, next_direction);
+            if costs.get(&state).is_none_or(|&best| cost < best) {
+                costs.insert(state, cost);
+                previous.insert(state, (cell, direction));
+                queue.push(Reverse((cost + heuristic(next), cost, next, next_direction)));
+            }
+        }
+    }
+    None
+}
+
+/// What routes must avoid.
+struct Grid<'a> {
+    obstacles: Vec<BoundingBox>,
+    forbidden: HashSet<Cell>,      // Pins, labels, junctions and ends of wires of other nets
+    others:    Vec<&'a WireOrBus>, // Buses and the wires of other nets
+    region:    BoundingBox,
+}
+
+impl Grid<'_> {
+    /// Whether a route may go from `cell` to the next cell `next`.
+    fn is_free(&self, cell: Cell, next: Cell) -> bool {
+        let (x, y) = point(next);
+        let inside = |b: &BoundingBox| b.min.0 < x && x < b.max.0 && b.min.1 < y && y < b.max.1;
+        !self.forbidden.contains(&next)
+            && !self.obstacles.iter().any(inside)
+            && !self.others.iter().any(|wire| wire.contains(point(cell)) && wire.contains((x, y)))
+    }
+
+    /// Whether a bus or a wire of another net goes through `cell`.
+    fn crosses(&self, cell: Cell) -> bool {
+        self.others.iter().any(|wire| wire.contains(point(cell)))
+    }
+}
+
+/// The wires connected to `point`, directly or through other wires.
+pub(crate) fn net(wires: &[WireOrBus], point: Point) -> Vec<&WireOrBus> {
+    let mut net: Vec<&WireOrBus> = vec![];
+    let mut remaining: Vec<&WireOrBus> =
+        wires.iter().filter(|wire| wire.kind == WireKind::Wire).collect();
+    loop {
+        let (connected, rest): (Vec<_>, Vec<_>) = remaining.into_iter().partition(|wire| {
+            wire.contains(point) || net.iter().any(|other| wire.touches(other))
+        });
+        if connected.is_empty() {
+            return net;
+        }
+        net.extend(connected);
+        remaining = rest;
+    }
+}
+
+fn cell((x, y): Point) -> Option<Cell> {
+    let on_grid = |c: Coord| (c.nm() % GRID.nm() == 0).then_some(c.nm() / GRID.nm());
+    Some((on_grid(x)?, on_grid(y)?))
+}
+
+fn point((x, y): Cell) -> Point { (GRID * x, GRID * y) }
+
+fn step((x, y): Cell, direction: usize) -> Cell {
+    let (dx, dy) = DIRECTIONS[direction];
+    (x + dx, y + dy)
+}
+
+/// The grid points along a horizontal or vertical wire, or the ends of other wires.
+fn cells(wire: &WireOrBus) -> Vec<Cell> {
+    match (cell(wire.start), cell(wire.end)) {
+        (Some(start), Some(end)) if start.0 == end.0 || start.1 == end.1 => {
+            let length = (end.0 - start.0).abs().max((end.1 - start.1).abs());
+            let direction = ((end.0 - start.0).signum(), (end.1 - start.1).signum());
+            (0..=length).map(|i| (start.0 + direction.0 * i, start.1 + direction.1 * i)).collect()
+        },
+        (start, end) => start.into_iter().chain(end).collect(),
+    }
+}
+
+/// The grid point where the route of a pin off the grid starts, away from its symbol, and the
+/// points of the stub joining it to the pin.
+fn snap(pin: PinEnd) -> (Cell, Vec<Point>) {
+    if let Some(cell) = cell(pin.point) {
+        return (cell, vec![]);
+    }
+    let (x, y) = pin.point;
+    let nearest = |c: Coord| (c.nm() as f64 / GRID.nm() as f64).round() as i64;
+    // Further away from the symbol on the axis of the pin, the nearest on the other one
+    let outward = |c: Coord, direction: i64| {
+        let grid = c.nm() as f64 / GRID.nm() as f64;
+        if direction > 0 { grid.floor() as i64 + 1 } else { grid.ceil() as i64 - 1 }
+    };
+    let snapped = match pin.direction {
+        (dx, 0) => (outward(x, dx), nearest(y)),
+        (_, dy) => (nearest(x), outward(y, dy)),
+    };
+    let (gx, gy) = point(snapped);
+    let elbow = if pin.direction.1 == 0 { (gx, y) } else { (x, gy) };
+    (snapped, vec![pin.point, elbow])
+}
+
+/// Keeps the points where the path bends, and its ends.
+fn corners(points: Vec<Point>) -> Vec<Point> {
+    let mut corners: Vec<Point> = vec![];
+    for point in points {
+        if corners.last() == Some(&point) {
+            continue;
+        }
+        if let [.., a, b] = corners[..] {
+            let collinear = (b.0 - a.0).nm() as i128 * (point.1 - b.1).nm() as i128
+                == (b.1 - a.1).nm() as i128 * (point.0 - b.0).nm() as i128;
+            if collinear {
+                corners.pop();
+            }
+        }
+        corners.push(point);
+    }
+    corners
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schematic::{geometry::{Orientation, Rotation},
+                           symbol::tests::timer,
+                           LocalLabel};
+
+    /// Two timers facing away from each other, so that wiring their trigger pins goes around.
+    fn sheet(at: f64) -> KicadSch {
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let mut sheet = KicadSch::default();
+        sheet.place_oriented(&timer(), mm(at, 50.8), Orientation::default()).unwrap();
+        let turned = Orientation { rotation: Rotation::Deg180, mirror: None };
+        sheet.place_oriented(&timer(), mm(at + 50.8, 50.8), turned).unwrap();
+        sheet
+    }
+
+    fn check_route(sheet: &KicadSch, from: Point, to: Point) {
+        let wires = &sheet.wires_and_buses;
+        assert_eq!(wires.first().map(|wire| wire.start), Some(from));
+        assert_eq!(wires.last().map(|wire| wire.end), Some(to));
+        for (wire, next) in wires.iter().zip(&wires[1..]) {
+            assert_eq!(wire.end, next.start);
+        }
+        let boxes: Vec<_> = sheet.symbols.iter().filter_map(|s| s.bounding_box()).collect();
+        for wire in wires {
+            assert!(wire.start.0 == wire.end.0 || wire.start.1 == wire.end.1, "{wire:?}");
+            let inside = |(x, y): Point, b: &BoundingBox| {
+                b.min.0 < x && x < b.max.0 && b.min.1 < y && y < b.max.1
+            };
+            for cell in cells(wire) {
+                assert!(!boxes.iter().any(|b| inside(point(cell), b)), "{wire:?} crosses a symbol");
+            }
+        }
+    }
+
+    #[test]
+    fn routes_go_around_symbols() {
+        let mut sheet = sheet(101.6);
+        let from = sheet.symbols[0].pin_position("TR").unwrap();
+        let to = sheet.symbols[1].pin_position("TR").unwrap();
+        sheet.connect(("U1", "TR"), ("U2", "TR")).unwrap();
+        check_route(&sheet, from, to);
+        // Both pins point away from each other: out, around and back in
+        assert_eq!(sheet.wires_and_buses.len(), 5);
+
+        sheet.connect(("U2", "2"), ("U1", "2")).unwrap();
+        assert_eq!(sheet.wires_and_buses.len(), 5, "the pins are already connected");
+    }
+
+    #[test]
+    fn pins_off_the_grid_are_joined_to_it() {
+        let mut sheet = sheet(100.);
+        let from = sheet.symbols[0].pin_position("TR").unwrap();
+        let to = sheet.symbols[1].pin_position("TR").unwrap();
+        sheet.connect(("U1", "TR"), ("U2", "TR")).unwrap();
+        check_route(&sheet, from, to);
+    }
+
+    #[test]
+    fn routes_joining_nets_off_the_grid_stay_orthogonal() {
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let turned = Orientation { rotation: Rotation::Deg180, mirror: None };
+        let mut sheet = KicadSch::default();
+        sheet.place_oriented(&timer(), mm(100., 50.8), turned).unwrap();
+        sheet.place_oriented(&timer(), mm(150.8, 50.8), turned).unwrap();
+        sheet.connect(("U1", "TR"), ("U2", "TR")).unwrap();
+        let wires = sheet.wires_and_buses.len();
+        sheet.connect(("U2", "VCC"), ("U1", "TR")).unwrap();
+        for wire in &sheet.wires_and_buses {
+            assert!(wire.start.0 == wire.end.0 || wire.start.1 == wire.end.1, "{wire:?}");
+        }
+        // The new route ends on the wires of the trigger pins, not on the pin itself
+        let end = sheet.wires_and_buses.last().unwrap().end;
+        assert!(sheet.wires_and_buses[..wires].iter().any(|wire| wire.contains(end)));
+        // Without drawing the stub of the trigger pin again
+        let ends: HashSet<_> =
+            sheet.wires_and_buses.iter().map(|wire| [wire.start, wire.end]).collect();
+        assert_eq!(ends.len(), sheet.wires_and_buses.len());
+    }
+
+    #[test]
+    fn routes_end_on_wires_of_the_same_net() {
+        let mut sheet = sheet(101.6);
+        sheet.connect(("U1", "TR"), ("U2", "TR")).unwrap();
+        let wires = sheet.wires_and_buses.clone();
+        sheet.connect(("U2", "VCC"), ("U1", "TR")).unwrap();
+        let end = sheet.wires_and_buses.last().unwrap().end;
+        assert!(wires.iter().any(|wire| wire.contains(end)));
+        assert!(sheet.wires_and_buses.len() - wires.len() <= 3);
+    }
+
+    #[test]
+    fn routes_go_around_labels_and_buses_of_other_nets() {
+        let from = PinEnd { point: (Coord::ZERO, Coord::ZERO), direction: (1, 0) };
+        let to = PinEnd { point: (GRID * 10, Coord::ZERO), direction: (-1, 0) };
+        let mut sheet = KicadSch::default();
+        assert_eq!(route(&sheet, from, to), Some(vec![from.point, to.point]));
+
+        let label = (GRID * 5, Coord::ZERO);
+        sheet.add_local_label(LocalLabel::new("OTHER", label, Rotation::Deg0));
+        let path = route(&sheet, from, to).unwrap();
+        assert_eq!((path.first(), path.last()), (Some(&from.point), Some(&to.point)));
+        let wires: Vec<_> = path.windows(2).map(|ends| WireOrBus::wire(ends[0], ends[1])).collect();
+        assert!(!wires.iter().any(|wire| wire.contains(label)), "{path:?}");
+
+        // Nor along a bus
+        let across = wires.iter().max_by_key(|wire| (wire.end.0 - wire.start.0).nm().abs());
+        let y = across.unwrap().start.1;
+        sheet.add_wire(WireOrBus::bus((GRID * -5, y), (GRID * 15, y)));
+        let detour = route(&sheet, from, to).unwrap();
+        for wire in detour.windows(2).map(|ends| WireOrBus::wire(ends[0], ends[1])) {
+            let along = sheet.wires_and_buses.iter().any(|bus| {
+                cells(&wire).iter().filter(|cell| bus.contains(point(**cell))).count() > 1
+            });
+            assert!(!along && !wire.contains(label), "{detour:?}");
+        }
+    }
+}
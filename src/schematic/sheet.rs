@@ -0,0 +1,328 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{parser::{self, ParseError, Quoted, SExpr},
+            schematic::{coord::{Coord, Length},
+                        extract_uuid,
+                        geometry::{BoundingBox, Rotation},
+                        graphic::{Justify, Stroke, TextEffect},
+                        router::GRID,
+                        symbol::Property,
+                        LabelShape,
+                        Position}};
+
+/// Width of the sheets placed by [`super::project::Project::add_sheet`].
+pub const SHEET_WIDTH: Length = Coord::from_nm(20 * GRID.nm());
+/// Distance between two pins on the same side of a sheet, and between the pins and the corners.
+pub const SHEET_PIN_PITCH: Length = Coord::from_nm(2 * GRID.nm());
+
+/// A `(sheet ...)`, the box standing for an instance of a sub-sheet, stored in its own file. Its
+/// pins connect to the hierarchical labels with the same name in the sub-sheet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HierarchicalSheet {
+    pub position: (Coord, Coord), // Top-left corner
+    pub size: (Length, Length),
+    pub exclude_from_sim: bool,
+    pub in_bom: bool,
+    pub on_board: bool,
+    pub dnp: bool,
+    pub fields_autoplaced: bool,
+    pub stroke: Stroke,
+    pub fill: (f32, f32, f32, f32), // RGBA, all 0 for the default of the schematic
+    pub uuid: Uuid,
+    properties: Vec<Property>, // "Sheetname" and "Sheetfile", then user fields
+    pub pins: Vec<HierarchicalPin>,
+    pub project_name: String,
+    pub instances: Vec<SheetPath>, // Set when annotating the project
+    unknown: Vec<SExpr>,           // Written back as-is
+}
+
+/// Where an instance of a sheet is, as the path of the sheet it is placed on, and the page
+/// number of the sub-sheet it opens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SheetPath {
+    pub path: String,
+    pub page: String,
+}
+
+impl HierarchicalSheet {
+    /// A sheet named `name` opening the file `file`, its name written above it and its file below.
+    pub fn new(name: &str, file: &str, (x, y): (Coord, Coord), size: (Length, Length)) -> Self {
+        let field = |name, value, y, justify| {
+            let position = Position { x, y, rotation: Some(0.) };
+            let effects = TextEffect::default().with_justify(vec![Justify::Left, justify]);
+            Property::new(name, value, position, effects)
+        };
+        Self {
+            position: (x, y),
+            size,
+            exclude_from_sim: false,
+            in_bom: true,
+            on_board: true,
+            dnp: false,
+            fields_autoplaced: false,
+            stroke: Stroke::default(),
+            fill: (0., 0., 0., 0.),
+            uuid: Uuid::new_v4(),
+            properties: vec![
+                field("Sheetname", name, y - Coord::from_mm(0.7116), Justify::Bottom),
+                field("Sheetfile", file, y + size.1 + Coord::from_mm(0.5846), Justify::Top),
+            ],
+            pins: vec![],
+            project_name: "".to_string(),
+            instances: vec![],
+            unknown: vec![],
+        }
+    }
+
+    /// Like `"Power supply"`, unique among the sheets of a schematic.
+    pub fn name(&self) -> &str { self.property("Sheetname").unwrap_or_default() }
+
+    /// The file of the sub-sheet, relative to the root schematic, like `"power.kicad_sch"`.
+    pub fn file(&self) -> &str { self.property("Sheetfile").unwrap_or_default() }
+
+    fn property(&self, name: &str) -> Option<&str> {
+        self.properties.iter().find(|p| p.name == name).map(|p| p.value.as_str())
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox {
+        let (x, y) = self.position;
+        BoundingBox::new((x, y), (x + self.size.0, y + self.size.1))
+    }
+
+    pub fn pin(&self, name: &str) -> Option<&HierarchicalPin> {
+        self.pins.iter().find(|pin| pin.name == name)
+    }
+
+    /// Adds a pin for the hierarchical label `name` of the sub-sheet, inputs on the left side and
+    /// other pins on the right side, below the pins already there. The sheet grows to fit it.
+    pub fn add_pin(&mut self, name: &str, shape: LabelShape) {
+        let (x, y) = self.position;
+        let (side, rotation) = match shape {
+            LabelShape::Input => (x, Rotation::Deg180),
+            _ => (x + self.size.0, Rotation::Deg0),
+        };
+        let on_side = self.pins.iter().filter(|pin| pin.position.x == side).count() as i64;
+        let pin_y = y + SHEET_PIN_PITCH * (on_side + 1);
+        self.size.1 = self.size.1.max(pin_y + SHEET_PIN_PITCH - y);
+        // The field below the sheet follows its bottom edge
+        let bottom = y + self.size.1 + Coord::from_mm(0.5846);
+        for property in self.properties.iter_mut().filter(|p| p.name == "Sheetfile") {
+            property.position.y = bottom;
+        }
+        self.pins.push(HierarchicalPin::new(name, shape, (side, pin_y), rotation));
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("sheet")?;
+        let pair = |name| -> Result<(Coord, Coord), ParseError> {
+            let child = node.expect_child(name)?;
+            Ok((child.expect_arg(0)?.expect_number()?, child.expect_arg(1)?.expect_number()?))
+        };
+        let flag = |name| {
+            match node.child(name) {
+                Some(child) => child.expect_arg(0)?.expect_bool(),
+                None => Ok(false),
+            }
+        };
+        let fill = match node.child("fill").and_then(|fill| fill.child("color")) {
+            Some(color) => parser::from_sexpr(color)?,
+            None => (0., 0., 0., 0.),
+        };
+        let known = [
+            "at",
+            "size",
+            "exclude_from_sim",
+            "in_bom",
+            "on_board",
+            "dnp",
+            "fields_autoplaced",
+            "stroke",
+            "fill",
+            "uuid",
+            "property",
+            "pin",
+            "instances",
+        ];
+        let mut sheet = Self {
+            position: pair("at")?,
+            size: pair("size")?,
+            exclude_from_sim: flag("exclude_from_sim")?,
+            in_bom: node.child("in_bom").is_none() || flag("in_bom")?,
+            on_board: node.child("on_board").is_none() || flag("on_board")?,
+            dnp: flag("dnp")?,
+            fields_autoplaced: flag("fields_autoplaced")?,
+            stroke: Stroke::extract_from(node.expect_child("stroke")?)?,
+            fill,
+            uuid: extract_uuid(node)?,
+            properties: vec![],
+            pins: vec![],
+            project_name: "".to_string(),
+            instances: vec![],
+            unknown: node.unknown_children(0, &known),
+        };
+        for arg in node.args() {
+            match arg.name() {
+                Some("property") => sheet.properties.push(Property::extract_from(arg)?),
+                Some("pin") => sheet.pins.push(HierarchicalPin::extract_from(arg)?),
+                _ => {},
+            }
+        }
+        if let Some(project) = node.child("instances").and_then(|i| i.child("project")) {
+            sheet.project_name = project.expect_arg(0)?.expect_string()?.to_string();
+            for path in project.args().iter().filter(|arg| arg.is_list("path")) {
+                sheet.instances.push(SheetPath {
+                    path: path.expect_arg(0)?.expect_string()?.to_string(),
+                    page: path.expect_child("page")?.expect_arg(0)?.expect_string()?.to_string(),
+                });
+            }
+        }
+        Ok(sheet)
+    }
+}
+
+impl Display for HierarchicalSheet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let yes_no = |flag| if flag { "yes" } else { "no" };
+        let ((x, y), (width, height)) = (self.position, self.size);
+        let (r, g, b, a) = self.fill;
+        f.write_fmt(format_args!(
+            "(sheet\n(at {x} {y})\n(size {width} {height})\n(exclude_from_sim {})\n(in_bom \
+             {})\n(on_board {})\n(dnp {})",
+            yes_no(self.exclude_from_sim),
+            yes_no(self.in_bom),
+            yes_no(self.on_board),
+            yes_no(self.dnp),
+        ))?;
+        if self.fields_autoplaced {
+            f.write_str("\n(fields_autoplaced yes)")?;
+        }
+        // KiCad writes the opacity of sheets with four decimals
+        f.write_fmt(format_args!(
+            "\n{}\n(fill (color {r} {g} {b} {a:.4}))\n(uuid \"{}\")",
+            self.stroke, self.uuid
+        ))?;
+        for property in &self.properties {
+            f.write_fmt(format_args!("\n{}", property))?;
+        }
+        for pin in &self.pins {
+            f.write_fmt(format_args!("\n{}", pin))?;
+        }
+        parser::write_unknown(f, &self.unknown)?;
+        if !self.instances.is_empty() {
+            f.write_fmt(format_args!("\n(instances\n(project {}", Quoted(&self.project_name)))?;
+            for SheetPath { path, page } in &self.instances {
+                f.write_fmt(format_args!("\n(path {} (page {}))", Quoted(path), Quoted(page)))?;
+            }
+            f.write_str("))")?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// A `(pin ...)` of a sheet, on one of its sides, connecting the wire it is on to the
+/// hierarchical label with the same name in the sub-sheet.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "pin name shape")]
+pub struct HierarchicalPin {
+    pub name:     String,
+    pub shape:    LabelShape,
+    #[serde(rename = "at")]
+    pub position: Position,
+    pub uuid:     Uuid,
+    pub effects:  TextEffect,
+    unknown:      Vec<SExpr>,
+}
+
+impl HierarchicalPin {
+    /// A pin at `(x, y)` on a side of a sheet, pointing out of the sheet towards `rotation`.
+    pub fn new(name: &str, shape: LabelShape, (x, y): (Coord, Coord), rotation: Rotation) -> Self {
+        // The name is written inside the sheet
+        let justify = match rotation {
+            Rotation::Deg0 | Rotation::Deg90 => Justify::Right,
+            Rotation::Deg180 | Rotation::Deg270 => Justify::Left,
+        };
+        Self {
+            name: name.to_string(),
+            shape,
+            position: Position { x, y, rotation: Some(rotation.degrees()) },
+            uuid: Uuid::new_v4(),
+            effects: TextEffect::default().with_justify(vec![justify]),
+            unknown: vec![],
+        }
+    }
+
+    /// The direction a wire leaves the pin in, away from the sheet, as `(±1, 0)` or `(0, ±1)`.
+    pub fn direction(&self) -> (i64, i64) {
+        // The sheet's Y axis grows downwards, against the rotations
+        match self.position.angle() {
+            Rotation::Deg0 => (1, 0),
+            Rotation::Deg90 => (0, -1),
+            Rotation::Deg180 => (-1, 0),
+            Rotation::Deg270 => (0, 1),
+        }
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("pin")?;
+        parser::from_sexpr(node)
+    }
+}
+
+impl Display for HierarchicalPin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{prelude::*, sample::select};
+
+    use super::*;
+    use crate::schematic::coord::tests::{coord, length};
+
+    #[test]
+    fn pins_are_stacked_on_the_sides() {
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let size = (SHEET_WIDTH, Coord::from_mm(5.08));
+        let mut sheet = HierarchicalSheet::new("Power", "power.kicad_sch", mm(50.8, 50.8), size);
+        sheet.add_pin("VIN", LabelShape::Input);
+        sheet.add_pin("EN", LabelShape::Input);
+        sheet.add_pin("VOUT", LabelShape::Output);
+        assert_eq!((sheet.name(), sheet.file()), ("Power", "power.kicad_sch"));
+
+        let at = |name| sheet.pin(name).map(|pin| (pin.position.x, pin.position.y));
+        assert_eq!(at("VIN"), Some(mm(50.8, 53.34)));
+        assert_eq!(at("EN"), Some(mm(50.8, 55.88)));
+        assert_eq!(at("VOUT"), Some(mm(76.2, 53.34)));
+        assert_eq!(sheet.pin("VIN").unwrap().direction(), (-1, 0));
+        assert_eq!(sheet.pin("VOUT").unwrap().direction(), (1, 0));
+        assert_eq!(sheet.size.1, Coord::from_mm(7.62));
+    }
+
+    proptest! {
+        #[test]
+        fn sheets_round_trip(
+            at in (coord(), coord()),
+            size in (length(), length()),
+            name in "[A-Za-z0-9 _]{1,10}",
+            pins in prop::collection::vec(("[A-Z0-9]{1,5}", select(vec![
+                LabelShape::Input, LabelShape::Output, LabelShape::Bidirectional,
+            ])), 0..4),
+            pages in prop::collection::vec("[1-9]{1,2}", 1..3),
+        ) {
+            let mut sheet = HierarchicalSheet::new(&name, "sub.kicad_sch", at, size);
+            for (pin, shape) in pins {
+                sheet.add_pin(&pin, shape);
+            }
+            sheet.project_name = "project".to_string();
+            sheet.instances = pages
+                .into_iter()
+                .map(|page| SheetPath { path: format!("/{}", Uuid::new_v4()), page })
+                .collect();
+            let node = parser::parse(&sheet.to_string())?;
+            prop_assert_eq!(HierarchicalSheet::extract_from(&node)?, sheet);
+        }
+    }
+}
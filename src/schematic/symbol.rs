@@ -1,11 +1,16 @@
 use std::fmt::{Display, Formatter};
 
-use log::{debug, warn};
-use serde::{Deserialize, Serialize};
+use log::debug;
+use serde::{de::IgnoredAny, Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{parser,
-            schematic::{graphic::{Graphic, TextEffect},
+use crate::{parser::{self, ParseError, Quoted, SExpr},
+            schematic::{coord::{Coord, Length},
+                        geometry::{BoundingBox, Orientation, Transform},
+                        graphic::{ElectricalType, Graphic, PinNumber, TextEffect},
+                        extract_uuid,
+                        is_default_embedded_fonts,
+                        write_unknown_and_embedded_fonts,
                         KicadSch,
                         Position}};
 
@@ -13,7 +18,7 @@ use crate::{parser,
 pub struct Symbol {
     pub name: String,
     exclude_from_sim: bool,
-    pin_names: (Option<f32>, bool), //[(pin_names [offset OFFSET] hide)]
+    pin_names: (Option<Length>, bool), //[(pin_names [offset OFFSET] hide)]
     pin_numbers: bool,              /* (pin_numbers hide) => true if it exists (hidden), false
                                      * otherwise */
     in_bom: bool,
@@ -23,135 +28,164 @@ pub struct Symbol {
     pins: Vec<Graphic>, // Graphic::Pin
     units: Vec<Symbol>,
     unit_name: Option<String>,
+    #[serde(default)] // Absent from the bundled libraries dumped before it was added
+    unknown: Vec<SExpr>, // Written back as-is
 }
 
 impl Symbol {
-    pub fn extract_from<'a>(
-        content: &'a str,
-        lib_name: &'a String,
-    ) -> Result<(Self, &'a str), String> {
-        let content = content.trim();
-        let (name, mut content) = parser::expect_regex(content, r#"\(symbol "[^"]+""#)?;
-        let name = name[9..name.len() - 1].to_string();
+    pub fn extract_from(node: &SExpr, lib_name: &str) -> Result<Self, ParseError> {
+        let args = node.expect_list("symbol")?;
+        let name = node.expect_arg(0)?.expect_string()?;
         debug!("Symbol Name: {name}");
         let mut it = Self {
             name: format!("{}:{}", lib_name, name),
-            exclude_from_sim: true,
+            exclude_from_sim: false,
             pin_names: (None, false),
             pin_numbers: false,
-            in_bom: false,
-            on_board: false,
+            in_bom: true,
+            on_board: true,
             properties: vec![],
             graphics: vec![],
             pins: vec![],
             units: vec![],
             unit_name: None,
+            unknown: vec![],
         };
-        loop {
-            if content.starts_with(")") {
-                break;
-            } else if content.starts_with("(in_bom") {
-                let (in_bom, left) = parser::expect_regex(content, r#"\(in_bom (yes|no)\)"#)?;
-                let in_bom = &in_bom[8..in_bom.len() - 1];
-                debug!("In BOM: {in_bom}");
-                it.in_bom = in_bom == "yes";
-                content = left;
-            } else if content.starts_with("(exclude_from_sim no)") {
-                content = parser::expect_str(content, "(exclude_from_sim no)")?;
-                it.exclude_from_sim = false;
-            } else if content.starts_with("(on_board") {
-                let (on_board, left) = parser::expect_regex(content, r#"\(on_board (yes|no)\)"#)?;
-                let on_board = &on_board[10..on_board.len() - 1];
-                debug!("On board: {on_board}");
-                it.on_board = on_board == "yes";
-                content = left;
-            } else if content.starts_with("(property") {
-                let (property, left) = Property::extract_from(content)?;
-                it.properties.push(property);
-                content = left;
-            } else if content.starts_with("(symbol") {
-                let (unit, left) = Self::extract_from(content, lib_name)?;
-                it.units.push(unit);
-                content = left;
-            } else if content.starts_with("(rectangle") {
-                let (rectangle, left) = Graphic::extract_rectangle_from(content)?;
-                it.graphics.push(rectangle);
-                content = left;
-            } else if content.starts_with("(circle") {
-                let (circle, left) = Graphic::extract_circle_from(content)?;
-                it.graphics.push(circle);
-                content = left;
-            } else if content.starts_with("(arc") {
-                let (arc, left) = Graphic::extract_arc_from(content)?;
-                it.graphics.push(arc);
-                content = left;
-            } else if content.starts_with("(pin_names") {
-                content = parser::expect_str(content, "(pin_names")?;
-                let offset_opt;
-                if content.starts_with("(offset") {
-                    let offset;
-                    (offset, content) =
-                        parser::expect_regex(content, r#"\(offset -?\d+(\.\d+)?\)"#)?;
-                    let offset = offset.replace("(offset ", "").replace(")", "");
-                    let offset = offset
-                        .parse::<f32>()
-                        .map_err(|e| format!("Trying to convert `{offset}` to f32: {e}"))?;
-                    offset_opt = Some(offset);
-                } else {
-                    offset_opt = None;
-                }
-                if content.starts_with("hide)") {
-                    content = parser::expect_str(content, "hide)")?;
-                    it.pin_names = (offset_opt, true);
-                } else {
-                    content = parser::expect_str(content, ")")?;
-                    it.pin_names = (offset_opt, false);
-                }
-            } else if content.starts_with("(pin_numbers") {
-                content = parser::expect_str(content, "(pin_numbers hide)")?;
-                it.pin_numbers = true;
-            } else if content.starts_with("(pin") {
-                let (pin, left) = Graphic::extract_pin_from(content)?;
-                it.graphics.push(pin);
-                content = left;
-            } else if content.starts_with("(polyline") {
-                let (polyline, left) = Graphic::extract_polyline_from(content)?;
-                it.graphics.push(polyline);
-                content = left;
-            } else {
-                let (skipped, left) = parser::expect_regex(content, r#"\([^\)]*\)"#)?;
-                warn!("Skipped: {}", skipped);
-                content = left;
+        for arg in &args[1..] {
+            match arg.name() {
+                Some("in_bom") => {
+                    it.in_bom = arg.expect_arg(0)?.expect_bool()?;
+                    debug!("In BOM: {}", it.in_bom);
+                },
+                Some("exclude_from_sim") => {
+                    it.exclude_from_sim = arg.expect_arg(0)?.expect_bool()?;
+                },
+                Some("on_board") => {
+                    it.on_board = arg.expect_arg(0)?.expect_bool()?;
+                    debug!("On board: {}", it.on_board);
+                },
+                Some("property") => it.properties.push(Property::extract_from(arg)?),
+                Some("symbol") => it.units.push(Self::extract_from(arg, lib_name)?),
+                Some(
+                    "rectangle" | "circle" | "arc" | "polyline" | "bezier" | "text" | "text_box",
+                ) => it.graphics.push(Graphic::extract_from(arg)?),
+                Some("pin") => it.pins.push(Graphic::extract_pin_from(arg)?),
+                Some("pin_names") => {
+                    let offset = match arg.child("offset") {
+                        Some(offset) => Some(offset.expect_arg(0)?.expect_number()?),
+                        None => None,
+                    };
+                    it.pin_names = (offset, is_hidden(arg)?);
+                },
+                Some("pin_numbers") => it.pin_numbers = is_hidden(arg)?,
+                Some("unit_name") => {
+                    it.unit_name = Some(arg.expect_arg(0)?.expect_string()?.to_string());
+                },
+                _ if is_default_embedded_fonts(arg) => {},
+                _ => {
+                    debug!("Keeping unknown symbol node: {arg}");
+                    it.unknown.push(arg.clone());
+                },
             }
         }
-        let content = parser::expect_str(content, ")")?;
-        Ok((it, content))
+        Ok(it)
     }
-}
 
-impl Display for Symbol {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    /// Reads a symbol cached in the `(lib_symbols ...)` of a schematic, whose name already starts
+    /// with its library, like `"Device:R"`.
+    pub fn extract_cached_from(node: &SExpr) -> Result<Self, ParseError> {
+        let name = node.expect_arg(0)?.expect_string()?;
+        let lib_name = name.split_once(':').map_or("", |(lib_name, _)| lib_name);
+        Ok(Self { name: name.to_string(), ..Self::extract_from(node, lib_name)? })
+    }
+
+    /// Value of the property called `name`, like `"Reference"` or `"Description"`.
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties.iter().find(|p| p.name == name).map(|p| p.value.as_str())
+    }
+
+    /// Name of the symbol without the library prefix.
+    pub fn short_name(&self) -> &str {
+        self.name.split_once(':').map_or(&self.name, |(_, name)| name)
+    }
+
+    /// Unit and body style of a unit, from its name `PARENT_UNIT_STYLE`. Unit 0 holds what all
+    /// units share.
+    fn unit_and_style(&self) -> Option<(usize, usize)> {
+        let (rest, style) = self.name.rsplit_once('_')?;
+        let (_, unit) = rest.rsplit_once('_')?;
+        Some((unit.parse().ok()?, style.parse().ok()?))
+    }
+
+    /// Own graphics and pins, then those of the units matching `keep`.
+    fn graphics_and_pins(
+        &self,
+        keep: impl Fn((usize, usize)) -> bool,
+    ) -> impl Iterator<Item = &Graphic> {
+        let units = self.units.iter().filter(move |unit| unit.unit_and_style().is_some_and(&keep));
+        let own = self.graphics.iter().chain(&self.pins);
+        own.chain(units.flat_map(|unit| unit.graphics.iter().chain(&unit.pins)))
+    }
+
+    /// The area covered by the graphics and pins of every unit and body style, in symbol
+    /// coordinates, Y growing upwards. Properties are not included.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let graphics = self.graphics_and_pins(|_| true);
+        graphics.filter_map(Graphic::bounding_box).reduce(BoundingBox::union)
+    }
+
+    /// Like [`Self::bounding_box`], for unit `unit` (from 1) in its first body style.
+    pub fn unit_bounding_box(&self, unit: usize) -> Option<BoundingBox> {
+        let graphics = self.graphics_and_pins(|(u, style)| (u == 0 || u == unit) && style <= 1);
+        graphics.filter_map(Graphic::bounding_box).reduce(BoundingBox::union)
+    }
+
+    /// The pins of unit `unit` (from 1) in its first body style, with the pins all units share.
+    pub fn unit_pins(&self, unit: usize) -> impl Iterator<Item = &Graphic> {
+        let is_shown = move |(u, style)| (u == 0 || u == unit) && style <= 1;
+        self.graphics_and_pins(is_shown).filter(|graphic| matches!(graphic, Graphic::Pin { .. }))
+    }
+
+    /// Writes the symbol under `name`. Units only carry their graphics, the flags and properties
+    /// being inherited from their parent.
+    pub(crate) fn fmt_named(
+        &self,
+        f: &mut Formatter<'_>,
+        name: &str,
+        is_unit: bool,
+    ) -> std::fmt::Result {
         f.write_str("(symbol")?;
-        f.write_fmt(format_args!("\n\"{}\"", self.name))?;
-        // f.write_fmt(format_args!(
-        //     "(exclude_from_sim {})",
-        //     if self.exclude_from_sim { "no" } else { "yes" }
-        // ))?;
-        // if self.pin_numbers {
-        //     f.write_str("(pin_numbers hide)")?;
-        // }
-        // if let Some(offset) = self.pin_names.0 {
-        //     f.write_fmt(format_args!(
-        //         "(pin_names (offset {}) {})",
-        //         offset,
-        //         if self.pin_names.1 { "hide" } else { "" }
-        //     ))?;
-        // }
-        // f.write_fmt(format_args!("(in_bom {})", if self.in_bom { "yes" } else { "no" }))?;
-        // f.write_fmt(format_args!("(on_board {})", if self.on_board { "yes" } else { "no" }))?;
-        // for property in &self.properties {
-        //     f.write_fmt(format_args!("\n{}", property))?;
-        // }
+        f.write_fmt(format_args!("\n{}", Quoted(name)))?;
+        if let Some(unit_name) = &self.unit_name {
+            f.write_fmt(format_args!("\n(unit_name {})", Quoted(unit_name)))?;
+        }
+        if self.pin_numbers {
+            f.write_str("\n(pin_numbers (hide yes))")?;
+        }
+        if self.pin_names != (None, false) {
+            f.write_str("\n(pin_names")?;
+            if let Some(offset) = self.pin_names.0 {
+                f.write_fmt(format_args!(" (offset {})", offset))?;
+            }
+            if self.pin_names.1 {
+                f.write_str(" (hide yes)")?;
+            }
+            f.write_str(")")?;
+        }
+        if !is_unit {
+            f.write_fmt(format_args!(
+                "\n(exclude_from_sim {})",
+                if self.exclude_from_sim { "yes" } else { "no" }
+            ))?;
+            f.write_fmt(format_args!("\n(in_bom {})", if self.in_bom { "yes" } else { "no" }))?;
+            f.write_fmt(format_args!(
+                "\n(on_board {})",
+                if self.on_board { "yes" } else { "no" }
+            ))?;
+        }
+        for property in &self.properties {
+            f.write_fmt(format_args!("\n{}", property))?;
+        }
         for graphic in &self.graphics {
             f.write_fmt(format_args!("\n{}", graphic))?;
         }
@@ -159,76 +193,182 @@ impl Display for Symbol {
             f.write_fmt(format_args!("\n{}", pin))?;
         }
         for unit in &self.units {
-            let mut unit = unit.clone();
-            unit.name = unit.name.split_once(':').unwrap().1.to_string();
-            f.write_fmt(format_args!("\n{}", unit))?;
+            f.write_str("\n")?;
+            unit.fmt_named(f, unit.short_name(), true)?;
         }
-        if let Some(unit_name) = &self.unit_name {
-            f.write_fmt(format_args!("(unit_name {})", unit_name))?;
+        match is_unit {
+            true => parser::write_unknown(f, &self.unknown)?,
+            false => write_unknown_and_embedded_fonts(f, &self.unknown)?,
         }
         f.write_str(")")
     }
 }
 
+/// Reads the `hide` flag of `(pin_names ...)` and `(pin_numbers ...)`, either a bare `hide` or
+/// a `(hide yes)`.
+fn is_hidden(node: &SExpr) -> Result<bool, ParseError> {
+    match node.child("hide") {
+        Some(hide) => hide.expect_arg(0)?.expect_bool(),
+        None => Ok(node.args().iter().any(|arg| arg.as_atom() == Some("hide"))),
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_named(f, &self.name, false)
+    }
+}
+
 #[derive(Debug)]
 pub struct SymbolInstance {
     pub name: String,
-    position: Position,
+    lib_name: Option<String>, // The cached symbol, when it differs from the library one
+    position: (Coord, Coord),
+    orientation: Orientation,
     unit: usize,
+    exclude_from_sim: bool,
     in_bom: bool,
     on_board: bool,
+    dnp: bool,
     uuid: Uuid,
     properties: Vec<Property>,
     pins: Vec<Pin>,
-    instance: Instance, // FIXME: Should be Vec<Instance> (Also see fixme of Instance type)
+    lib_symbol: Symbol, // Not written, for the geometry of the unit
+    instance: Instance, // Where the symbol appears in the project
+    unknown: Vec<SExpr>, // Written back as-is, like `(fields_autoplaced yes)`
 }
 
 impl Display for SymbolInstance {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("(symbol")?;
+        if let Some(lib_name) = &self.lib_name {
+            f.write_fmt(format_args!("\n(lib_name {})", Quoted(lib_name)))?;
+        }
         f.write_fmt(format_args!(
-            "(symbol\n(lib_id \n\"{name}\")\n{position}\n(unit {unit})\n(in_bom \
-             {in_bom})\n(on_board {on_board})\n(uuid \"{uuid}\")",
-            name = self.name,
-            position = self.position,
-            unit = self.unit,
-            in_bom = if self.in_bom { "yes" } else { "no" },
-            on_board = if self.on_board { "yes" } else { "no" },
-            uuid = self.uuid
+            "\n(lib_id {name})\n{position}",
+            name = Quoted(&self.name),
+            // KiCad requires the orientation of placed symbols
+            position = Position {
+                x:        self.position.0,
+                y:        self.position.1,
+                rotation: Some(self.orientation.rotation.degrees()),
+            },
         ))?;
+        if let Some(mirror) = self.orientation.mirror {
+            f.write_fmt(format_args!("\n(mirror {mirror})"))?;
+        }
+        let yes_no = |flag| if flag { "yes" } else { "no" };
+        f.write_fmt(format_args!(
+            "\n(unit {})\n(exclude_from_sim {})\n(in_bom {})\n(on_board {})\n(dnp {})",
+            self.unit,
+            yes_no(self.exclude_from_sim),
+            yes_no(self.in_bom),
+            yes_no(self.on_board),
+            yes_no(self.dnp),
+        ))?;
+        parser::write_unknown(f, &self.unknown)?;
+        f.write_fmt(format_args!("\n(uuid \"{}\")", self.uuid))?;
+        // Fields are placed on the sheet, but KiCad turns their text with the symbol when drawing
+        let transform = self.transform();
         for property in &self.properties {
             let mut property = property.clone();
-            property.position = Position {
-                x: self.position.x + property.position.x,
-                y: self.position.y + property.position.y,
-                rotation: Some(
-                    self.position.rotation.unwrap_or(0.0)
-                        + property.position.rotation.unwrap_or(0.0),
-                ),
-            };
+            let (x, y) = transform.apply((property.position.x, property.position.y));
+            property.position = Position { x, y, ..property.position };
             f.write_fmt(format_args!("\n{}", property))?;
         }
         for pin in &self.pins {
             f.write_fmt(format_args!(
-                "\n(pin \"{name}\" (uuid {uuid}))",
-                name = pin.name,
+                "\n(pin {number} (uuid \"{uuid}\")",
+                number = Quoted(pin.number.as_str()),
                 uuid = pin.uuid
             ))?;
+            parser::write_unknown(f, &pin.unknown)?;
+            f.write_str(")")?;
         }
         f.write_fmt(format_args!(
-            "\n(instances\n(project \"{project_name}\" (path \"{path}\" (reference \
-             \"{reference}\") (unit {unit}))))))",
-            project_name = self.instance.project_name,
-            path = self.instance.path.path,
-            reference = self.instance.path.reference,
-            unit = self.instance.path.unit
-        ))
+            "\n(instances\n(project {project_name}",
+            project_name = Quoted(&self.instance.project_name)
+        ))?;
+        for path in &self.instance.paths {
+            f.write_fmt(format_args!(
+                "\n(path {path} (reference {reference}) (unit {unit}))",
+                path = Quoted(&path.path),
+                reference = Quoted(&path.reference),
+                unit = path.unit
+            ))?;
+        }
+        f.write_str(")")?;
+        parser::write_unknown(f, &self.instance.unknown)?;
+        f.write_str("))")
     }
 }
 
 impl SymbolInstance {
+    /// The area covered by the placed unit, in schematic coordinates, Y growing downwards.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let bounding_box = self.lib_symbol.unit_bounding_box(self.unit)?;
+        Some(self.transform().apply_to_box(bounding_box))
+    }
+
+    /// The pins of the placed unit, as drawn in the library symbol.
+    pub fn pins(&self) -> impl Iterator<Item = &Graphic> { self.lib_symbol.unit_pins(self.unit) }
+
+    /// The pin numbered `number_or_name`, or else the first pin named so.
+    pub fn pin(&self, number_or_name: &str) -> Option<&Graphic> {
+        let number = |pin: &&Graphic| {
+            matches!(pin, Graphic::Pin { number, .. } if number.as_str() == number_or_name)
+        };
+        let name =
+            |pin: &&Graphic| matches!(pin, Graphic::Pin { name, .. } if name == number_or_name);
+        self.pins().find(number).or_else(|| self.pins().find(name))
+    }
+
+    pub fn pins_of_type(&self, electrical_type: ElectricalType) -> impl Iterator<Item = &Graphic> {
+        self.pins().filter(move |pin| {
+            matches!(pin, Graphic::Pin { electrical_type: ty, .. } if *ty == electrical_type)
+        })
+    }
+
+    /// Where a wire connects to the pin numbered or named `number_or_name` on the sheet.
+    pub fn pin_position(&self, number_or_name: &str) -> Option<(Coord, Coord)> {
+        self.pin(number_or_name).map(|pin| self.pin_position_of(pin))
+    }
+
+    /// Where a wire connects to `pin`, one of [`Self::pins`], on the sheet. The `(at ...)` of a
+    /// pin is its connection point, its line going from there towards the body of the symbol.
+    pub fn pin_position_of(&self, pin: &Graphic) -> (Coord, Coord) {
+        let Graphic::Pin { position, .. } = pin else { unreachable!("Should always be a pin") };
+        self.transform().apply((position.x, position.y))
+    }
+
+    /// The direction a wire leaves `pin` in, away from the body of the symbol, on the sheet, as
+    /// `(±1, 0)` or `(0, ±1)`.
+    pub fn pin_direction_of(&self, pin: &Graphic) -> (i64, i64) {
+        let Graphic::Pin { position, .. } = pin else { unreachable!("Should always be a pin") };
+        let unit = (Coord::from_nm(1), Coord::ZERO);
+        let toward_body = Transform::rotation(position.angle()).apply(unit);
+        let origin = self.transform().apply((Coord::ZERO, Coord::ZERO));
+        let (dx, dy) = self.transform().apply(toward_body);
+        (-(dx - origin.0).nm(), -(dy - origin.1).nm())
+    }
+
+    /// Moves points of the library symbol to where they land in the schematic.
+    pub fn transform(&self) -> Transform { Transform::placement(self.position, self.orientation) }
+
+    /// Where the origin of the library symbol lands on the sheet.
+    pub fn position(&self) -> (Coord, Coord) { self.position }
+
+    pub(crate) fn set_position(&mut self, position: (Coord, Coord)) { self.position = position }
+
+    pub fn orientation(&self) -> Orientation { self.orientation }
+
+    pub fn set_orientation(&mut self, orientation: Orientation) { self.orientation = orientation }
+
+    /// Places unit `unit` of `symbol`, numbering its reference after those already on `sheet`.
     pub fn from(
         symbol: &Symbol,
-        position: Position,
+        position: (Coord, Coord),
+        orientation: Orientation,
         unit: usize,
         sheet: &KicadSch,
     ) -> Result<Self, String> {
@@ -239,101 +379,274 @@ impl SymbolInstance {
             .ok_or("Symbol doesn't contains a Reference property.")?
             .value
             .clone();
+        // The number after the highest one, like `U3` after `U1` and `U2`
+        let number = sheet
+            .symbols
+            .iter()
+            .filter_map(|s| s.reference().strip_prefix(&base_reference)?.parse::<usize>().ok())
+            .max()
+            .map_or(1, |number| number + 1);
+        let reference = format!("{base_reference}{number}");
 
-        let pins = symbol.pins.iter().map(Pin::from).collect();
+        let mut properties = symbol.properties.clone();
+        for property in properties.iter_mut().filter(|p| p.name == "Reference") {
+            property.value = reference.clone();
+        }
+        let mut pins: Vec<Pin> = symbol.unit_pins(unit).map(Pin::from).collect();
+        pins.sort_by(|a, b| a.number.cmp(&b.number));
 
         Ok(Self {
             name: symbol.name.clone(),
+            lib_name: None,
             position,
+            orientation,
             unit,
-            in_bom: true,
-            on_board: true,
+            exclude_from_sim: symbol.exclude_from_sim,
+            in_bom: symbol.in_bom,
+            on_board: symbol.on_board,
+            dnp: false,
             uuid: Uuid::new_v4(),
-            properties: symbol.properties.clone(),
+            properties,
             pins,
+            lib_symbol: symbol.clone(),
             instance: Instance {
                 project_name: sheet.project_name.clone(),
-                path: InstancePath {
-                    path: format!("/{}", sheet.uuid),
-                    reference: format!("{}{}", base_reference, unit),
-                    unit,
-                },
+                paths: vec![InstancePath { path: format!("/{}", sheet.uuid), reference, unit }],
+                unknown: vec![],
             },
+            unknown: vec![],
         })
     }
+
+    /// Reads a placed symbol, drawn like the symbol of `lib_symbols` named by its `(lib_name ...)`,
+    /// or else by its `(lib_id ...)`.
+    pub fn extract_from(node: &SExpr, lib_symbols: &[Symbol]) -> Result<Self, ParseError> {
+        node.expect_list("symbol")?;
+        let name = node.expect_child("lib_id")?.expect_arg(0)?.expect_string()?;
+        let lib_name = match node.child("lib_name") {
+            Some(lib_name) => Some(lib_name.expect_arg(0)?.expect_string()?),
+            None => None,
+        };
+        let cached = lib_name.unwrap_or(name);
+        let lib_symbol = lib_symbols
+            .iter()
+            .find(|symbol| symbol.name == cached)
+            .ok_or_else(|| node.unexpected(format!("{cached} in the lib_symbols")))?;
+        let position = Position::extract_from(node.expect_child("at")?)?;
+        let mirror = match node.child("mirror") {
+            Some(mirror) => Some(parser::from_sexpr(mirror.expect_arg(0)?)?),
+            None => None,
+        };
+        let flag = |name, default| match node.child(name) {
+            Some(child) => child.expect_arg(0)?.expect_bool(),
+            None => Ok(default),
+        };
+        let mut it = Self {
+            name: name.to_string(),
+            lib_name: lib_name.map(str::to_string),
+            position: (position.x, position.y),
+            orientation: Orientation { rotation: position.angle(), mirror },
+            unit: node.expect_child("unit")?.expect_arg(0)?.expect_number()?,
+            exclude_from_sim: flag("exclude_from_sim", false)?,
+            in_bom: flag("in_bom", true)?,
+            on_board: flag("on_board", true)?,
+            dnp: flag("dnp", false)?,
+            uuid: extract_uuid(node)?,
+            properties: vec![],
+            pins: vec![],
+            lib_symbol: lib_symbol.clone(),
+            instance: Instance::extract_from(node.expect_child("instances")?)?,
+            unknown: node.unknown_children(0, &[
+                "lib_name",
+                "lib_id",
+                "at",
+                "mirror",
+                "unit",
+                "exclude_from_sim",
+                "in_bom",
+                "on_board",
+                "dnp",
+                "uuid",
+                "property",
+                "pin",
+                "instances",
+            ]),
+        };
+        // Fields are written where they are on the sheet, see `Display`
+        let transform = it.transform().inverse();
+        for arg in node.args() {
+            match arg.name() {
+                Some("property") => {
+                    let mut property = Property::extract_from(arg)?;
+                    let (x, y) = transform.apply((property.position.x, property.position.y));
+                    property.position = Position { x, y, ..property.position };
+                    it.properties.push(property);
+                },
+                Some("pin") => it.pins.push(Pin::extract_from(arg)?),
+                _ => {},
+            }
+        }
+        Ok(it)
+    }
+
+    /// Like `"U2"`, in the first instance of the sheet.
+    pub fn reference(&self) -> &str { &self.instance.paths[0].reference }
+
+    /// The reference given when the symbol was placed, before annotating a project.
+    pub(crate) fn placed_reference(&self) -> &str {
+        let reference = self.properties.iter().find(|p| p.name == "Reference");
+        reference.map_or_else(|| self.reference(), |p| p.value.as_str())
+    }
+
+    /// The reference and unit of the symbol in each instance of its sheet, see
+    /// [`super::project::Project`].
+    pub fn instances(&self) -> &[InstancePath] { &self.instance.paths }
+
+    pub(crate) fn set_instances(&mut self, project_name: &str, paths: Vec<InstancePath>) {
+        self.instance.project_name = project_name.to_string();
+        self.instance.paths = paths;
+    }
+
+    pub fn unit(&self) -> usize { self.unit }
+
+    pub fn uuid(&self) -> Uuid { self.uuid }
+
+    /// The symbol of the `(lib_symbols ...)` the placed symbol is drawn like.
+    pub fn lib_symbol(&self) -> &Symbol { &self.lib_symbol }
+
+    /// Draws the placed unit like `symbol` instead, keeping its place, reference and instances.
+    /// Pins keep their UUID when `symbol` has a pin with the same number, other fields are those
+    /// of `symbol`.
+    pub(crate) fn set_lib_symbol(&mut self, symbol: &Symbol) {
+        let reference = self.placed_reference().to_string();
+        self.properties = symbol.properties.clone();
+        for property in self.properties.iter_mut().filter(|p| p.name == "Reference") {
+            property.value = reference.clone();
+        }
+        let mut pins: Vec<Pin> = symbol.unit_pins(self.unit).map(Pin::from).collect();
+        for pin in &mut pins {
+            if let Some(old) = self.pins.iter().find(|old| old.number == pin.number) {
+                pin.uuid = old.uuid;
+            }
+        }
+        pins.sort_by(|a, b| a.number.cmp(&b.number));
+        self.pins = pins;
+        self.name = symbol.name.clone();
+        self.lib_symbol = symbol.clone();
+        self.lib_name = None;
+    }
+
+    /// The project the instances of the symbol are in.
+    pub(crate) fn project_name(&self) -> &str { &self.instance.project_name }
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
-struct Property {
-    name: String,
-    value: String,
-    position: Position,
+#[serde(rename = "property name value")]
+pub(crate) struct Property {
+    pub(crate) name: String,
+    pub(crate) value: String,
+    #[serde(default, skip_serializing)]
+    id: IgnoredAny, // `(id N)`, only written by KiCad 6 and 7
+    #[serde(rename = "at")]
+    pub(crate) position: Position,
+    #[serde(default, skip_serializing_if = "parser::is_false")]
     do_not_autoplace: bool,
+    #[serde(rename = "effects")]
     text_effect: TextEffect,
+    #[serde(default)]
+    unknown: Vec<SExpr>,
 }
 
 impl Display for Property {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!(
-            "(property \"{name}\" \"{value}\" {position}",
-            name = self.name,
-            value = self.value,
-            position = self.position,
-        ))?;
-        if self.do_not_autoplace {
-            f.write_str(" (do_not_autoplace)")?;
-        }
-        f.write_fmt(format_args!(" {})", self.text_effect))
-    }
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
 }
 
 impl Property {
-    fn extract_from(content: &str) -> Result<(Self, &str), String> {
-        let (name_and_value, content) =
-            parser::expect_regex(content, r#"\(property "[^"]+" "[^"]*""#)?;
-        let name_and_value = name_and_value.replace("(property ", "").replace("\"", "");
-        let (name, value) = name_and_value.split_once(' ').unwrap();
-        let (name, value) = (name.to_string(), value.to_string());
-        let (position, content) = Position::extract_from(content)?;
-
-        let do_not_autoplace = content.starts_with("(do_not_autoplace)");
-
-        let content = if do_not_autoplace {
-            parser::expect_str(content, "(do_not_autoplace)")?
-        } else {
-            content
-        };
+    pub(crate) fn new(
+        name: &str,
+        value: &str,
+        position: Position,
+        text_effect: TextEffect,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            id: IgnoredAny,
+            position,
+            do_not_autoplace: false,
+            text_effect,
+            unknown: vec![],
+        }
+    }
 
-        let (text_effect, content) = TextEffect::extract_from(content)?;
-        let content = parser::expect_str(content, ")")?;
-        Ok((Self { name, value, do_not_autoplace, position, text_effect }, content))
+    pub(crate) fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("property")?;
+        parser::from_sexpr(node)
     }
 }
 
 #[derive(Debug)]
 struct Pin {
-    name: String, // Usually the pin number
-    uuid: Uuid,
+    number:  PinNumber,
+    uuid:    Uuid,
+    unknown: Vec<SExpr>, // Like the `(alternate NAME)` picked for the pin
 }
 
 impl Pin {
     pub fn from(pin: &Graphic) -> Self {
         let Graphic::Pin { number, .. } = pin else { unreachable!("Should always be a pin") };
-        Self { name: format!("{number}"), uuid: Uuid::new_v4() }
+        Self { number: number.clone(), uuid: Uuid::new_v4(), unknown: vec![] }
+    }
+
+    fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("pin")?;
+        Ok(Self {
+            number:  node.expect_arg(0)?.expect_string()?.into(),
+            uuid:    extract_uuid(node)?,
+            unknown: node.unknown_children(1, &["uuid"]),
+        })
     }
 }
 
 #[derive(Debug)]
 struct Instance {
     project_name: String,
-    path: InstancePath, // FIXME: Should be Vec<InstancePath>
+    paths: Vec<InstancePath>, // One per instance of the sheet
+    unknown: Vec<SExpr>,      // The instances in other projects, written back as-is
 }
 
-#[derive(Debug)]
+impl Instance {
+    /// Reads the `(instances (project NAME (path PATH (reference REF) (unit UNIT))...)...)` of a
+    /// placed symbol, keeping the first project.
+    fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("instances")?;
+        let project = node.expect_child("project")?;
+        let mut paths = vec![];
+        for path in project.args().iter().filter(|arg| arg.is_list("path")) {
+            let reference = path.expect_child("reference")?.expect_arg(0)?.expect_string()?;
+            paths.push(InstancePath {
+                path:      path.expect_arg(0)?.expect_string()?.to_string(),
+                reference: reference.to_string(),
+                unit:      path.expect_child("unit")?.expect_arg(0)?.expect_number()?,
+            });
+        }
+        if paths.is_empty() {
+            return Err(project.unexpected("a path".to_string()));
+        }
+        let others = node.args().iter().filter(|arg| arg.is_list("project")).skip(1);
+        Ok(Self {
+            project_name: project.expect_arg(0)?.expect_string()?.to_string(),
+            paths,
+            unknown: others.cloned().collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct InstancePath {
-    path:      String, // Usually "/{project_uuid}"
-    reference: String, // Example "U2"
-    unit:      usize,
+    pub path:      String, // "/{root_uuid}" then "/{sheet_uuid}" for each sheet down to the symbol
+    pub reference: String, // Example "U2"
+    pub unit:      usize,
 }
 
 // #[derive(Debug)]
@@ -341,3 +654,180 @@ pub struct InstancePath {
 //     offset: f32,
 //     hide: bool,
 // }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use proptest::{collection::vec, option, prelude::*};
+
+    use super::*;
+    use crate::{parser::tests::unknown_nodes,
+                schematic::{coord::tests::length,
+                            geometry::Rotation,
+                            graphic::tests::{pin, shape, text_effect},
+                            tests::position}};
+
+    fn property() -> impl Strategy<Value = Property> {
+        let text = (position(), any::<bool>(), text_effect());
+        (any::<String>(), any::<String>(), text, unknown_nodes())
+            .prop_map(|(name, value, (position, do_not_autoplace, text_effect), unknown)| Property {
+                name,
+                value,
+                id: IgnoredAny,
+                position,
+                do_not_autoplace,
+                text_effect,
+                unknown,
+            })
+    }
+
+    /// A unit of `parent`, which only carries graphics and pins.
+    fn unit(parent: String) -> impl Strategy<Value = Symbol> {
+        let body = (vec(shape(), 0..3), vec(pin(), 0..3), option::of(any::<String>()));
+        (0..10usize, 0..3usize, body).prop_map(
+            move |(unit, style, (graphics, pins, unit_name))| Symbol {
+                name: format!("{parent}_{unit}_{style}"),
+                exclude_from_sim: false,
+                pin_names: (None, false),
+                pin_numbers: false,
+                in_bom: true,
+                on_board: true,
+                properties: vec![],
+                graphics,
+                pins,
+                units: vec![],
+                unit_name,
+                unknown: vec![],
+            },
+        )
+    }
+
+    fn symbol() -> impl Strategy<Value = Symbol> {
+        let flags = (any::<bool>(), (option::of(length()), any::<bool>()), any::<bool>());
+        let placement = (any::<bool>(), any::<bool>());
+        let body = (vec(property(), 0..4), vec(shape(), 0..3), vec(pin(), 0..3), unknown_nodes());
+        (any::<String>(), flags, placement, body)
+            .prop_flat_map(|(name, flags, placement, body)| {
+                let name = format!("Lib:{name}");
+                let units = vec(unit(name.clone()), 0..3);
+                (Just(name), Just(flags), Just(placement), Just(body), units)
+            })
+            .prop_map(
+                |(
+                    name,
+                    (exclude_from_sim, pin_names, pin_numbers),
+                    (in_bom, on_board),
+                    (properties, graphics, pins, unknown),
+                    units,
+                )| Symbol {
+                    name,
+                    exclude_from_sim,
+                    pin_names,
+                    pin_numbers,
+                    in_bom,
+                    on_board,
+                    properties,
+                    graphics,
+                    pins,
+                    units,
+                    unit_name: None,
+                    unknown,
+                },
+            )
+    }
+
+    /// A timer with its supply pin shared by all units.
+    pub fn timer() -> Symbol {
+        let symbol = r#"(symbol "NE555"
+            (property "Reference" "U" (at 0 0 0) (effects (font (size 1.27 1.27))))
+            (symbol "NE555_0_1" (pin power_in line (at 0 10.16 270) (length 2.54)
+                (name "VCC" (effects (font (size 1.27 1.27))))
+                (number "8" (effects (font (size 1.27 1.27))))))
+            (symbol "NE555_1_1" (pin input line (at -10.16 2.54 0) (length 2.54)
+                (name "TR" (effects (font (size 1.27 1.27))))
+                (number "2" (effects (font (size 1.27 1.27)))))))"#;
+        Symbol::extract_from(&parser::parse(symbol).unwrap(), "Timer").unwrap()
+    }
+
+    #[test]
+    fn pins_are_found_where_they_connect() {
+        let symbol = timer();
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+
+        let mut sheet = KicadSch::default();
+        let orientation = Orientation { rotation: Rotation::Deg90, mirror: None };
+        sheet.place_oriented(&symbol, mm(100., 50.), orientation).unwrap();
+        let instance = &sheet.symbols[0];
+
+        assert_eq!(instance.pin_position("2"), instance.pin_position("TR"));
+        assert_eq!(instance.pin_position("2"), Some(mm(97.46, 60.16)));
+        assert_eq!(instance.pin_position("8"), Some(mm(89.84, 50.)));
+        assert_eq!(instance.pin_position("3"), None);
+        let power: Vec<_> = instance.pins_of_type(ElectricalType::PowerIn).collect();
+        assert_eq!(power, [instance.pin("VCC").unwrap()]);
+    }
+
+    #[test]
+    fn cached_symbols_are_named_before_the_library_symbol() {
+        let mut sheet = KicadSch::default();
+        sheet.place_oriented(&timer(), (Coord::ZERO, Coord::ZERO), Orientation::default()).unwrap();
+        let written = sheet.symbols[0].to_string();
+        let renamed = written.replacen("(symbol", "(symbol (lib_name \"NE555_1\")", 1);
+        let cached = Symbol { name: "NE555_1".to_string(), ..timer() };
+        let node = parser::parse(&renamed).unwrap();
+        let mut instance = SymbolInstance::extract_from(&node, &[cached]).unwrap();
+        assert_eq!(instance.lib_symbol.name, "NE555_1");
+
+        let written = instance.to_string();
+        assert!(written.starts_with("(symbol\n(lib_name \"NE555_1\")\n(lib_id"), "{written}");
+        assert_eq!(written.matches("lib_name").count(), 1);
+        instance.set_lib_symbol(&timer());
+        assert!(!instance.to_string().contains("lib_name"));
+    }
+
+    #[test]
+    fn nodes_of_newer_versions_are_written_back() {
+        let symbol = r#"(symbol "R"
+            (duplicate_pin_numbers_are_jumpers no)
+            (property "Reference" "R" (at 0 0 0)
+                (effects (font (face "Arial") (size 1.27 1.27)) (href "https://kicad.org")))
+            (symbol "R_0_1"
+                (rectangle (start -1.016 -2.54) (end 1.016 2.54) (radius 0.254)
+                    (stroke (width 0.254) (type default)) (fill (type none)))))"#;
+        let symbol = Symbol::extract_from(&parser::parse(symbol).unwrap(), "Device").unwrap();
+        let written = std::fmt::from_fn(|f| symbol.fmt_named(f, "R", false)).to_string();
+        for node in [
+            "(duplicate_pin_numbers_are_jumpers no)",
+            "(face \"Arial\")",
+            "(href \"https://kicad.org\")",
+            "(radius 0.254)",
+        ] {
+            assert!(written.contains(node), "{written}");
+        }
+        let reread = Symbol::extract_from(&parser::parse(&written).unwrap(), "Device").unwrap();
+        assert_eq!(reread, symbol);
+    }
+
+    proptest! {
+        #[test]
+        fn properties_round_trip(property in property()) {
+            let node = parser::parse(&property.to_string())?;
+            prop_assert_eq!(Property::extract_from(&node)?, property);
+        }
+
+        #[test]
+        fn symbols_round_trip(symbol in symbol()) {
+            let written =
+                std::fmt::from_fn(|f| symbol.fmt_named(f, symbol.short_name(), false)).to_string();
+            let node = parser::parse(&written)?;
+            prop_assert_eq!(Symbol::extract_from(&node, "Lib")?, symbol);
+        }
+
+        #[test]
+        fn units_are_within_the_symbol(symbol in symbol(), unit in 1..10usize) {
+            if let Some(unit_box) = symbol.unit_bounding_box(unit) {
+                let symbol_box = symbol.bounding_box();
+                prop_assert!(symbol_box.is_some_and(|b| b.union(unit_box) == b));
+            }
+        }
+    }
+}
@@ -1,19 +1,29 @@
-use std::{fs::read_to_string,
+use std::{fmt::{Display, Formatter},
+          fs::read_to_string,
           path::{Path, PathBuf},
           str::pattern::Pattern,
           vec::IntoIter};
 
-use log::{debug, info};
+use log::{debug, info, warn};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 
-use crate::{parser, schematic::symbol::Symbol};
+use crate::{parser::{self, ParseError, ParseErrorKind, Quoted, SExpr},
+            schematic::symbol::Symbol};
+
+/// `(version ...)` of the libraries saved by KiCad 6, the first version using S-expressions.
+pub const OLDEST_VERSION: u32 = 20211014;
+/// `(version ...)` of the libraries saved by KiCad 9, whose syntax is used when writing.
+pub const VERSION: u32 = 20241209;
 
 pub struct SymbolLibraries(Vec<SymbolLibrary>);
 
 impl SymbolLibraries {
     pub fn iter(&self) -> impl Iterator<Item = &SymbolLibrary> { self.0.iter() }
 
+    /// The libraries bundled in `static/included_libs`, as dumped by the binary with `serde_json`.
+    /// The serde attributes of the symbols also drive the S-expression format, so renaming a
+    /// field changes the dumps too and they must be regenerated.
     pub fn get_statics() -> Self {
         let path = Path::new("static/included_libs");
         let mut libraries = vec![];
@@ -26,7 +36,7 @@ impl SymbolLibraries {
         Self(libraries)
     }
 
-    pub fn add_dir(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+    pub fn add_dir(&mut self, path: impl AsRef<Path>) -> Result<(), ParseError> {
         let libraries_name = self.iter().map(|lib| lib.name.clone()).collect::<Vec<_>>();
 
         let paths = Self::get_all_lib_files(path)?;
@@ -50,7 +60,7 @@ impl SymbolLibraries {
         Ok(())
     }
 
-    pub fn all_from_dir(path: impl AsRef<Path>) -> Result<Self, String> {
+    pub fn all_from_dir(path: impl AsRef<Path>) -> Result<Self, ParseError> {
         let paths = Self::get_all_lib_files(path)?;
         Ok(Self(
             paths
@@ -63,15 +73,15 @@ impl SymbolLibraries {
         ))
     }
 
-    fn get_all_lib_files(path: impl AsRef<Path>) -> Result<Vec<PathBuf>, String> {
+    fn get_all_lib_files(path: impl AsRef<Path>) -> Result<Vec<PathBuf>, ParseError> {
         let path = path.as_ref();
         if !path.is_dir() {
-            Err(format!("{} is not a directory", path.to_string_lossy()))
+            Err(ParseError::io("not a directory", path))
         } else {
             let mut paths = vec![];
 
-            for entry in path.read_dir().map_err(|e| e.to_string())? {
-                let entry = entry.map_err(|e| e.to_string())?;
+            for entry in path.read_dir().map_err(|e| ParseError::io(e, path))? {
+                let entry = entry.map_err(|e| ParseError::io(e, path))?;
                 let path = entry.path();
 
                 if path.is_file() && path.extension().unwrap_or_default() == "kicad_sym" {
@@ -79,10 +89,7 @@ impl SymbolLibraries {
                 } else if path.is_dir() {
                     paths.extend(Self::get_all_lib_files(path)?);
                 } else {
-                    return Err(format!(
-                        "{} is neither a file nor a directory",
-                        path.to_string_lossy()
-                    ));
+                    return Err(ParseError::io("neither a file nor a directory", path));
                 }
             }
             Ok(paths)
@@ -112,16 +119,18 @@ impl IntoIterator for SymbolLibraries {
 #[derive(Deserialize, Serialize)]
 pub struct SymbolLibrary {
     pub name: String,
-    version: String,
+    version: u32, // As read, the library is always written with the syntax of `VERSION`
     generator: String,
-    generator_version: String,
+    generator_version: Option<String>, // Since KiCad 8
     pub symbols: Vec<Symbol>,
+    #[serde(default)]
+    unknown: Vec<SExpr>, // Written back as-is
 }
 
 impl SymbolLibrary {
-    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, String> {
-        let src = read_to_string(&path).map_err(|e| e.to_string())?;
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ParseError> {
         let path = path.as_ref();
+        let src = read_to_string(path).map_err(|e| ParseError::io(e, path))?;
 
         Self::from_string(
             src,
@@ -130,34 +139,80 @@ impl SymbolLibrary {
                 .to_string_lossy()
                 .to_string(),
         )
+        .map_err(|e| e.with_path(path.to_path_buf()))
+    }
+
+    /// Parses a library, errors are located by line and column in `content`.
+    pub fn from_string(content: impl AsRef<str>, name: String) -> Result<Self, ParseError> {
+        let content = content.as_ref();
+        Self::extract_from(&parser::parse(content).map_err(|e| e.locate(content))?, name)
+            .map_err(|e| e.locate(content))
     }
 
-    pub fn from_string(content: impl Into<String>, name: String) -> Result<Self, String> {
-        let content = content.into();
-        let content = content.trim();
-        let content = parser::expect_str(content, "(kicad_symbol_lib")?;
-        let (version, content) = parser::expect_regex(content, r"\(version \d+\)")?;
-        let version = version[9..version.len() - 1].to_string();
+    fn extract_from(root: &SExpr, name: String) -> Result<Self, ParseError> {
+        let args = root.expect_list("kicad_symbol_lib")?;
+
+        let version_node = root.expect_child("version")?.expect_arg(0)?;
+        let version = version_node.expect_number()?;
         debug!("Version: {version}");
-        let (generator, content) = parser::expect_regex(content, r#"\(generator "[^"]+"\)"#)?;
-        let generator = generator[12..generator.len() - 2].to_string();
-        debug!("Generator: {generator}");
-        let (generator_version, mut content) =
-            parser::expect_regex(content, r#"\(generator_version "[^"]+"\)"#)?;
-        let generator_version = generator_version[20..generator_version.len() - 2].to_string();
-        debug!("Generator Version: {generator_version}\n");
-
-        let mut symbols = vec![];
-        while content.starts_with("(symbol") {
-            let (symbol, rest) = Symbol::extract_from(content, &name)?;
-            symbols.push(symbol);
-            content = rest;
+        if version < OLDEST_VERSION {
+            return Err(ParseError::new(
+                ParseErrorKind::UnsupportedVersion(version),
+                version_node.span(),
+            ));
+        }
+        if version > VERSION {
+            warn!("Library {name} has version {version}, newer than {VERSION}, reading it anyway");
         }
+        // KiCad 6 and 7 write the generator as an atom, which `expect_string` also accepts
+        let generator =
+            root.expect_child("generator")?.expect_arg(0)?.expect_string()?.to_string();
+        debug!("Generator: {generator}");
+        let generator_version = match root.child("generator_version") {
+            Some(node) => Some(node.expect_arg(0)?.expect_string()?.to_string()),
+            None => None,
+        };
+        debug!("Generator Version: {generator_version:?}\n");
+
+        let symbols = args
+            .iter()
+            .filter(|arg| arg.is_list("symbol"))
+            .map(|symbol| Symbol::extract_from(symbol, &name))
+            .collect::<Result<Vec<_>, _>>()?;
 
         info!("Found {} symbols for library {name}. symbols names are:", symbols.len());
         for symbol in symbols.iter() {
             info!("\t{}", symbol.name);
         }
-        Ok(Self { name, version, generator, generator_version, symbols })
+        let unknown =
+            root.unknown_children(0, &["version", "generator", "generator_version", "symbol"]);
+        Ok(Self { name, version, generator, generator_version, symbols, unknown })
+    }
+}
+
+impl Display for SymbolLibrary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        parser::write_pretty(
+            f,
+            std::fmt::from_fn(|f| {
+                f.write_fmt(format_args!(
+                    "(kicad_symbol_lib\n(version {})\n(generator {})",
+                    self.version.max(VERSION),
+                    Quoted(&self.generator),
+                ))?;
+                if let Some(generator_version) = &self.generator_version {
+                    f.write_fmt(format_args!(
+                        "\n(generator_version {})",
+                        Quoted(generator_version)
+                    ))?;
+                }
+                for symbol in &self.symbols {
+                    f.write_str("\n")?;
+                    symbol.fmt_named(f, symbol.short_name(), false)?;
+                }
+                parser::write_unknown(f, &self.unknown)?;
+                f.write_str(")")
+            }),
+        )
     }
 }
@@ -0,0 +1,374 @@
+use std::{fmt::{Display, Formatter},
+          path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{parser::{self, ParseError, SExpr},
+            schematic::{coord::{Coord, Length},
+                        graphic::{Fill, Justify, Stroke, TextEffect},
+                        router::GRID,
+                        Position}};
+
+/// Space KiCad leaves between the border of a text box or table cell and its text, on each side.
+pub const TEXT_MARGIN: Length = Coord::from_nm(952_500);
+/// Height of the rows of the tables built by [`Table::new`], fitting a line of default text.
+pub const TABLE_ROW_HEIGHT: Length = Coord::from_nm(4 * GRID.nm());
+/// Length of the base64 strings the data of images is split into, like KiCad.
+const IMAGE_DATA_LINE: usize = 76;
+
+/// A free `(text ...)` on the sheet, like a design note.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "text text")]
+pub struct Text {
+    pub text:             String,
+    #[serde(default)]
+    pub exclude_from_sim: bool,
+    #[serde(rename = "at")]
+    pub position:         Position,
+    pub effects:          TextEffect,
+    pub uuid:             Uuid,
+    unknown:              Vec<SExpr>,
+}
+
+impl Text {
+    /// A text anchored by its bottom-left corner at `(x, y)`, lines separated by `\n`.
+    pub fn new(text: &str, (x, y): (Coord, Coord)) -> Self {
+        Self {
+            text:             text.to_string(),
+            exclude_from_sim: false,
+            position:         Position { x, y, rotation: Some(0.) },
+            effects:          TextEffect::default()
+                .with_justify(vec![Justify::Left, Justify::Bottom]),
+            uuid:             Uuid::new_v4(),
+            unknown:          vec![],
+        }
+    }
+
+    pub fn with_effects(self, effects: TextEffect) -> Self { Self { effects, ..self } }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("text")?;
+        parser::from_sexpr(node)
+    }
+}
+
+impl Display for Text {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
+}
+
+/// A `(text_box ...)`, text wrapped inside a frame.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "text_box text")]
+pub struct TextBox {
+    pub text:             String,
+    #[serde(default)]
+    pub exclude_from_sim: bool,
+    #[serde(rename = "at")]
+    pub position:         Position, // Top-left corner
+    pub size:             (Length, Length),
+    pub margins:          Option<(Length, Length, Length, Length)>, // Left, top, right, bottom
+    pub stroke:           Stroke,
+    pub fill:             Fill,
+    pub effects:          TextEffect,
+    pub uuid:             Uuid,
+    unknown:              Vec<SExpr>,
+}
+
+impl TextBox {
+    /// A box with its top-left corner at `(x, y)`, its text starting at the top left.
+    pub fn new(text: &str, (x, y): (Coord, Coord), size: (Length, Length)) -> Self {
+        Self {
+            text: text.to_string(),
+            exclude_from_sim: false,
+            position: Position { x, y, rotation: Some(0.) },
+            size,
+            margins: Some((TEXT_MARGIN, TEXT_MARGIN, TEXT_MARGIN, TEXT_MARGIN)),
+            stroke: Stroke::default(),
+            fill: Fill::default(),
+            effects: TextEffect::default().with_justify(vec![Justify::Left, Justify::Top]),
+            uuid: Uuid::new_v4(),
+            unknown: vec![],
+        }
+    }
+
+    pub fn with_effects(self, effects: TextEffect) -> Self { Self { effects, ..self } }
+
+    pub fn with_style(self, stroke: Stroke, fill: Fill) -> Self { Self { stroke, fill, ..self } }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("text_box")?;
+        parser::from_sexpr(node)
+    }
+}
+
+impl Display for TextBox {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
+}
+
+/// An `(image ...)` embedded in the schematic, as a base64 encoded PNG.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "image")]
+pub struct Image {
+    #[serde(rename = "at")]
+    pub position: Position, // Centre of the image
+    #[serde(default = "unit_scale")]
+    pub scale:    f64,
+    pub uuid:     Uuid,
+    data:         Vec<String>, // Base64, split in lines
+    unknown:      Vec<SExpr>,
+}
+
+fn unit_scale() -> f64 { 1. }
+
+impl Image {
+    /// The PNG `png` centred on `(x, y)`, drawn `scale` times its size at 300 DPI.
+    pub fn new(png: &[u8], (x, y): (Coord, Coord), scale: f64) -> Result<Self, String> {
+        if !png.starts_with(b"\x89PNG\r\n\x1a\n") {
+            return Err("Images must be PNGs".to_string());
+        }
+        let data = STANDARD.encode(png);
+        let data = data.as_bytes().chunks(IMAGE_DATA_LINE);
+        Ok(Self {
+            position: Position { x, y, rotation: None },
+            scale,
+            uuid: Uuid::new_v4(),
+            data: data.map(|line| String::from_utf8_lossy(line).into_owned()).collect(),
+            unknown: vec![],
+        })
+    }
+
+    /// Reads the PNG at `path`, see [`Self::new`].
+    pub fn from_path(path: &Path, position: (Coord, Coord), scale: f64) -> Result<Self, String> {
+        let png = std::fs::read(path)
+            .map_err(|e| format!("Unable to read image {}: {e}", path.display()))?;
+        Self::new(&png, position, scale)
+    }
+
+    /// The embedded PNG.
+    pub fn png(&self) -> Result<Vec<u8>, String> {
+        STANDARD.decode(self.data.concat()).map_err(|e| format!("Invalid image data: {e}"))
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("image")?;
+        parser::from_sexpr(node)
+    }
+}
+
+impl Display for Image {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
+}
+
+/// A `(table ...)` of text cells, read row by row, KiCad 8 and later.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "table")]
+pub struct Table {
+    pub column_count:  usize,
+    pub border:        TableBorder,
+    pub separators:    TableSeparators,
+    pub column_widths: Vec<Length>,
+    pub row_heights:   Vec<Length>,
+    pub cells:         Vec<TableCell>,
+    unknown:           Vec<SExpr>,
+}
+
+/// The `(border ...)` around a table, and under its first row when it is a header.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "border")]
+pub struct TableBorder {
+    pub external: bool,
+    pub header:   bool,
+    pub stroke:   Stroke,
+}
+
+/// The `(separators ...)` between the rows and columns of a table.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "separators")]
+pub struct TableSeparators {
+    pub rows:   bool,
+    pub cols:   bool,
+    pub stroke: Stroke,
+}
+
+/// A `(table_cell ...)`, a text box in a table.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "table_cell text")]
+pub struct TableCell {
+    pub text:             String,
+    #[serde(default)]
+    pub exclude_from_sim: bool,
+    #[serde(rename = "at")]
+    pub position:         Position, // Top-left corner
+    pub size:             (Length, Length),
+    pub margins:          Option<(Length, Length, Length, Length)>,
+    pub span:             Option<(usize, usize)>, // Columns and rows covered
+    pub fill:             Fill,
+    pub effects:          TextEffect,
+    pub uuid:             Uuid,
+    unknown:              Vec<SExpr>,
+}
+
+impl Table {
+    /// A table with its top-left corner at `(x, y)`, columns `column_widths` wide, and a row of
+    /// [`TABLE_ROW_HEIGHT`] for each of `rows`, the first one being the header.
+    pub fn new(
+        (x, y): (Coord, Coord),
+        column_widths: &[Length],
+        rows: &[&[&str]],
+    ) -> Result<Self, String> {
+        if let Some(row) = rows.iter().find(|row| row.len() != column_widths.len()) {
+            return Err(format!("{row:?} does not have {} columns", column_widths.len()));
+        }
+        let mut cells = vec![];
+        for (row, texts) in rows.iter().enumerate() {
+            let mut cell_x = x;
+            for (text, &width) in texts.iter().zip(column_widths) {
+                let at = (cell_x, y + TABLE_ROW_HEIGHT * row as i64);
+                let TextBox { text, position, size, margins, fill, effects, .. } =
+                    TextBox::new(text, at, (width, TABLE_ROW_HEIGHT));
+                cells.push(TableCell {
+                    text,
+                    exclude_from_sim: false,
+                    position,
+                    size,
+                    margins,
+                    span: Some((1, 1)),
+                    fill,
+                    effects,
+                    uuid: Uuid::new_v4(),
+                    unknown: vec![],
+                });
+                cell_x += width;
+            }
+        }
+        Ok(Self {
+            column_count: column_widths.len(),
+            border: TableBorder { external: true, header: true, stroke: Stroke::default() },
+            separators: TableSeparators { rows: true, cols: true, stroke: Stroke::default() },
+            column_widths: column_widths.to_vec(),
+            row_heights: vec![TABLE_ROW_HEIGHT; rows.len()],
+            cells,
+            unknown: vec![],
+        })
+    }
+
+    /// The cell at `row` and `column`, from 0. KiCad keeps the cells merged into another one,
+    /// empty and spanning nothing, so this is the cell spanning over them instead.
+    pub fn cell(&self, row: usize, column: usize) -> Option<&TableCell> {
+        let at = |r: usize, c: usize| self.cells.get(r * self.column_count + c);
+        if column >= self.column_count || at(row, column).is_none() {
+            return None;
+        }
+        let covering = |(r, c): (usize, usize)| {
+            let cell = at(r, c)?;
+            let (columns, rows) = cell.span.unwrap_or((1, 1));
+            (r + rows > row && c + columns > column).then_some(cell)
+        };
+        (0..=row).rev().flat_map(|r| (0..=column).rev().map(move |c| (r, c))).find_map(covering)
+    }
+
+    pub fn extract_from(node: &SExpr) -> Result<Self, ParseError> {
+        node.expect_list("table")?;
+        parser::from_sexpr(node)
+    }
+}
+
+impl Display for Table {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { parser::write_sexpr(f, self) }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::schematic::{coord::tests::{coord, length},
+                           graphic::tests::{fill, stroke, text_effect}};
+
+    #[test]
+    fn tables_are_laid_out_row_by_row() {
+        let mm = |x, y| (Coord::from_mm(x), Coord::from_mm(y));
+        let widths = [Coord::from_mm(20.32), Coord::from_mm(10.16)];
+        let rows: [&[&str]; 3] = [&["Part", "Value"], &["R1", "10k"], &["C1", "100n"]];
+        let table = Table::new(mm(25.4, 25.4), &widths, &rows).unwrap();
+        let cell = table.cell(2, 1).unwrap();
+        assert_eq!(cell.text, "100n");
+        assert_eq!((cell.position.x, cell.position.y), mm(45.72, 35.56));
+        assert_eq!(table.cell(1, 2), None);
+        assert!(Table::new(mm(0., 0.), &widths, &[&["Part"]]).is_err());
+    }
+
+    #[test]
+    fn merged_cells_are_found_from_the_cells_they_cover() {
+        let cell = |text: &str, x: f64, y: f64, width: f64, span: &str| {
+            format!(
+                "(table_cell \"{text}\" (exclude_from_sim no) (at {x} {y} 0) (size {width} 2.54)
+                    (margins 0.9525 0.9525 0.9525 0.9525) (span {span}) (fill (type none))
+                    (effects (font (size 1.27 1.27)) (justify left top))
+                    (uuid \"{}\"))",
+                Uuid::new_v4()
+            )
+        };
+        // Saved by KiCad 9 after merging the cells of the header
+        let src = format!(
+            "(table (column_count 2)
+                (border (external yes) (header yes) (stroke (width 0.254) (type solid)))
+                (separators (rows yes) (cols yes) (stroke (width 0) (type solid)))
+                (column_widths 20.32 10.16) (row_heights 2.54 2.54)
+                (cells {} {} {} {}))",
+            cell("Parts", 25.4, 25.4, 30.48, "2 1"),
+            cell("", 45.72, 25.4, 10.16, "0 0"),
+            cell("R1", 25.4, 27.94, 20.32, "1 1"),
+            cell("10k", 45.72, 27.94, 10.16, "1 1"),
+        );
+        let table = Table::extract_from(&parser::parse(&src).unwrap()).unwrap();
+        let text = |row, column| table.cell(row, column).map(|cell| cell.text.as_str());
+        assert_eq!((text(0, 0), text(0, 1)), (Some("Parts"), Some("Parts")));
+        assert_eq!((text(1, 0), text(1, 1)), (Some("R1"), Some("10k")));
+        assert_eq!((text(2, 0), text(0, 2)), (None, None));
+
+        let written = table.to_string();
+        assert!(written.contains("(span 0 0)"), "{written}");
+        assert_eq!(Table::extract_from(&parser::parse(&written).unwrap()).unwrap(), table);
+    }
+
+    #[test]
+    fn images_keep_their_png() {
+        let png = b"\x89PNG\r\n\x1a\n".iter().copied().chain(0..=255).collect::<Vec<u8>>();
+        let image = Image::new(&png, (Coord::ZERO, Coord::ZERO), 0.5).unwrap();
+        assert!(image.data.iter().all(|line| line.len() <= IMAGE_DATA_LINE));
+        let node = parser::parse(&image.to_string()).unwrap();
+        assert_eq!(Image::extract_from(&node).unwrap().png().unwrap(), png);
+        assert!(Image::new(b"GIF89a", (Coord::ZERO, Coord::ZERO), 1.).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn texts_round_trip(
+            text in any::<String>(),
+            at in (coord(), coord()),
+            size in (length(), length()),
+            effects in text_effect(),
+            (stroke, fill) in (stroke(), fill()),
+        ) {
+            let item = Text::new(&text, at).with_effects(effects.clone());
+            prop_assert_eq!(Text::extract_from(&parser::parse(&item.to_string())?)?, item);
+            let item = TextBox::new(&text, at, size).with_effects(effects).with_style(stroke, fill);
+            prop_assert_eq!(TextBox::extract_from(&parser::parse(&item.to_string())?)?, item);
+        }
+
+        #[test]
+        fn tables_round_trip(
+            at in (coord(), coord()),
+            widths in prop::collection::vec(length(), 1..4),
+            texts in prop::collection::vec(any::<String>(), 12),
+        ) {
+            let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+            let rows: Vec<&[&str]> = texts.chunks_exact(widths.len()).take(3).collect();
+            let table = Table::new(at, &widths, &rows).unwrap();
+            prop_assert_eq!(Table::extract_from(&parser::parse(&table.to_string())?)?, table);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::fs::{read_dir, read_to_string};
+
+use kicad_generator::schematic::symbol_library::{SymbolLibraries, SymbolLibrary};
+
+#[test]
+fn bundled_libraries_load() {
+    let libraries = SymbolLibraries::get_statics();
+    assert_eq!(libraries.iter().count(), 18);
+    assert!(!libraries.search_by_name("Timer, 555").is_empty());
+    assert!(libraries.iter().all(|library| !library.symbols.is_empty()));
+}
+
+#[test]
+fn bundled_libraries_are_in_the_current_format() {
+    for entry in read_dir("static/included_libs").unwrap() {
+        let path = entry.unwrap().path();
+        let json = read_to_string(&path).unwrap();
+        let library: SymbolLibrary = serde_json::from_str(&json).unwrap();
+        let dumped = serde_json::to_string(&library).unwrap();
+        assert!(dumped == json, "{} must be regenerated", path.display());
+    }
+}
+
+#[test]
+fn bundled_libraries_are_keyed_by_field_names() {
+    let json = read_to_string("static/included_libs/JLCPCB-Analog").unwrap();
+    let library: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let property = &library["symbols"][0]["properties"][0];
+    assert_eq!(property["name"], "Reference");
+    assert_eq!(property["at"]["y"], 2.54);
+    assert!(property["unknown"].is_array());
+    assert!(!json.contains("\"$"));
+}
+
+#[test]
+fn libraries_dumped_without_unknown_nodes_load() {
+    let library = r#"{"name":"Old","version":20231120,"generator":"kicad_symbol_editor",
+        "generator_version":"8.0","symbols":[{"name":"Old:R","exclude_from_sim":false,
+        "pin_names":[null,false],"pin_numbers":false,"in_bom":true,"on_board":true,
+        "properties":[],"graphics":[{"Polyline":{"points":[[0.0,0.0],[1.27,0.0]],
+        "stroke":{"width":0.0,"type":"default","color":null},"fill":{"type":"none",
+        "color":null}}}],"pins":[],"units":[],"unit_name":null}]}"#;
+    let library: SymbolLibrary = serde_json::from_str(library).unwrap();
+    assert_eq!(library.symbols[0].name, "Old:R");
+}
@@ -0,0 +1,116 @@
+use kicad_generator::{parser::ParseErrorKind,
+                      schematic::symbol_library::{SymbolLibrary, OLDEST_VERSION, VERSION}};
+
+// Saved by KiCad 6: atom generator, no generator version, property ids and bare `hide` flags
+const KICAD_6: &str = r#"(kicad_symbol_lib (version 20211014) (generator kicad_symbol_editor)
+  (symbol "AMS1117-3.3" (pin_names (offset 0.254) hide) (in_bom yes) (on_board yes)
+    (property "Reference" "U" (id 0) (at -3.81 3.175 0)
+      (effects (font (size 1.27 1.27)))
+    )
+    (property "Value" "AMS1117-3.3" (id 1) (at 0 3.175 0)
+      (effects (font (size 1.27 1.27)) (justify left))
+    )
+    (property "Footprint" "Package_TO_SOT_SMD:SOT-223-3_TabPin2" (id 2) (at 0 5.08 0)
+      (effects (font (size 1.27 1.27)) hide)
+    )
+    (symbol "AMS1117-3.3_0_1"
+      (rectangle (start -5.08 1.905) (end 5.08 -5.08)
+        (stroke (width 0.254) (type default) (color 0 0 0 0))
+        (fill (type background))
+      )
+    )
+    (symbol "AMS1117-3.3_1_1"
+      (pin power_in line (at 0 -7.62 90) (length 2.54)
+        (name "GND" (effects (font (size 1.27 1.27))))
+        (number "1" (effects (font (size 1.27 1.27))))
+      )
+      (pin power_out line (at 7.62 0 180) (length 2.54) hide
+        (name "VO" (effects (font (size 1.27 1.27)) hide))
+        (number "2" (effects (font (size 1.27 1.27))))
+      )
+    )
+  )
+)"#;
+
+// Saved by KiCad 8: quoted generator with its version, still bare `hide` flags
+const KICAD_8: &str = r#"(kicad_symbol_lib
+	(version 20231120)
+	(generator "kicad_symbol_editor")
+	(generator_version "8.0")
+	(symbol "R"
+		(exclude_from_sim no)
+		(in_bom yes)
+		(on_board yes)
+		(property "Reference" "R"
+			(at 2.032 0 90)
+			(effects
+				(font
+					(size 1.27 1.27)
+				)
+				hide
+			)
+		)
+		(symbol "R_1_1"
+			(pin passive line
+				(at 0 3.81 270)
+				(length 1.27)
+				hide
+				(name "~"
+					(effects
+						(font
+							(size 1.27 1.27)
+						)
+					)
+				)
+				(number "1"
+					(effects
+						(font
+							(size 1.27 1.27)
+						)
+					)
+				)
+			)
+		)
+	)
+)"#;
+
+#[test]
+fn kicad_6_libraries_read() {
+    let library = SymbolLibrary::from_string(KICAD_6, "Regulators".to_string()).unwrap();
+    let symbol = &library.symbols[0];
+    assert_eq!(symbol.name, "Regulators:AMS1117-3.3");
+    assert_eq!(symbol.property("Footprint"), Some("Package_TO_SOT_SMD:SOT-223-3_TabPin2"));
+
+    // Written with the syntax of KiCad 9
+    let written = library.to_string();
+    assert!(written.contains(&format!("(version {VERSION})")), "{written}");
+    assert!(written.contains("(generator \"kicad_symbol_editor\")"), "{written}");
+    assert!(!written.contains("generator_version"), "{written}");
+    assert!(!written.contains("(id "), "{written}");
+    assert_eq!(written.matches("(hide yes)").count(), 4, "{written}");
+    let reread = SymbolLibrary::from_string(&written, "Regulators".to_string()).unwrap();
+    assert!(reread.symbols == library.symbols);
+}
+
+#[test]
+fn kicad_8_libraries_read() {
+    let library = SymbolLibrary::from_string(KICAD_8, "Device".to_string()).unwrap();
+    let written = library.to_string();
+    assert!(written.contains("(generator_version \"8.0\")"), "{written}");
+    assert_eq!(written.matches("(hide yes)").count(), 2, "{written}");
+    let reread = SymbolLibrary::from_string(&written, "Device".to_string()).unwrap();
+    assert!(reread.symbols == library.symbols);
+}
+
+#[test]
+fn libraries_older_than_kicad_6_are_rejected() {
+    let old = KICAD_6.replace("(version 20211014)", "(version 20200829)");
+    let Err(error) = SymbolLibrary::from_string(old, "Regulators".to_string()) else {
+        panic!("Libraries saved before KiCad 6 should be rejected");
+    };
+    assert_eq!(error.kind, ParseErrorKind::UnsupportedVersion(20200829));
+    assert_eq!(error.location.unwrap().line, 1);
+
+    let oldest = KICAD_6.replace("20211014", &OLDEST_VERSION.to_string());
+    assert!(SymbolLibrary::from_string(oldest, "Regulators".to_string()).is_ok());
+}
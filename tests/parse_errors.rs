@@ -0,0 +1,65 @@
+use std::{env::temp_dir,
+          fs::{create_dir_all, write}};
+
+use kicad_generator::{parser::ParseErrorKind,
+                      schematic::symbol_library::{SymbolLibraries, SymbolLibrary}};
+
+// The pin length is not a number
+const BROKEN: &str = "(kicad_symbol_lib
+\t(version 20241209)
+\t(generator \"kicad_symbol_editor\")
+\t(generator_version \"9.0\")
+\t(symbol \"R\"
+\t\t(symbol \"R_1_1\"
+\t\t\t(pin passive line (at 0 3.81 270) (length long)
+\t\t\t\t(name \"~\" (effects (font (size 1.27 1.27))))
+\t\t\t\t(number \"1\" (effects (font (size 1.27 1.27))))
+\t\t\t)
+\t\t)
+\t)
+)";
+
+#[test]
+fn errors_point_at_the_line_and_column() {
+    let Err(error) = SymbolLibrary::from_string(BROKEN, "Device".to_string()) else {
+        panic!("The pin length should be rejected");
+    };
+    assert_eq!(error.kind, ParseErrorKind::InvalidNumber("long".to_string()));
+    let location = error.location.as_deref().unwrap();
+    assert_eq!((location.line, location.column), (7, 46));
+    assert_eq!(location.snippet, "\t\t\t(pin passive line (at 0 3.81 270) (length long)");
+    assert!(error.to_string().starts_with("7:46: `long` is not a valid number\n"), "{error}");
+}
+
+#[test]
+fn errors_of_library_directories_name_the_file() {
+    let dir = temp_dir().join(format!("kicad-generator-parse-errors-{}", std::process::id()));
+    create_dir_all(&dir).unwrap();
+    let path = dir.join("Broken.kicad_sym");
+    write(&path, BROKEN).unwrap();
+
+    let Err(error) = SymbolLibraries::all_from_dir(&dir) else {
+        panic!("The broken library should be reported");
+    };
+    assert_eq!(error.path.as_deref(), Some(path.as_path()));
+    assert_eq!(error.location.as_ref().unwrap().line, 7);
+    assert!(error.to_string().starts_with(&format!("{}:7:46: ", path.display())));
+
+    let missing = dir.join("missing.kicad_sym");
+    let Err(error) = SymbolLibrary::from_path(&missing) else {
+        panic!("Missing libraries should be reported");
+    };
+    assert!(matches!(error.kind, ParseErrorKind::Io(_)));
+    assert_eq!(error.path, Some(missing));
+}
+
+#[test]
+fn truncated_sources_are_rejected() {
+    for src in ["", "(", ")", "\"", "(kicad_symbol_lib", "(kicad_symbol_lib (version", "(version 1)"]
+    {
+        let Err(error) = SymbolLibrary::from_string(src, "Short".to_string()) else {
+            panic!("{src} should be rejected");
+        };
+        assert_eq!(error.location.unwrap().line, 1, "{src}");
+    }
+}
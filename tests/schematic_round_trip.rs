@@ -0,0 +1,378 @@
+use kicad_generator::{parser,
+                      schematic::{coord::Coord, page::Paper, KicadSch}};
+
+// Saved by KiCad 9, trimmed to one element of each kind
+const SCHEMATIC: &str = r#"(kicad_sch
+	(version 20250114)
+	(generator "eeschema")
+	(generator_version "9.0")
+	(uuid "8a1c3f5e-2b7d-4e0a-9c61-3f2e5d7b9a10")
+	(paper "A4")
+	(title_block
+		(title "Blinker")
+		(date "2025-03-14")
+		(rev "A")
+		(comment 1 "Hand drawn")
+	)
+	(lib_symbols
+		(symbol "Device:R"
+			(pin_numbers
+				(hide yes)
+			)
+			(pin_names
+				(offset 0)
+			)
+			(exclude_from_sim no)
+			(in_bom yes)
+			(on_board yes)
+			(property "Reference" "R"
+				(at 2.032 0 90)
+				(effects
+					(font
+						(size 1.27 1.27)
+					)
+				)
+			)
+			(property "Value" "R"
+				(at 0 0 90)
+				(effects
+					(font
+						(size 1.27 1.27)
+					)
+				)
+			)
+			(property "Footprint" ""
+				(at -1.778 0 90)
+				(effects
+					(font
+						(size 1.27 1.27)
+					)
+					(hide yes)
+				)
+			)
+			(property "ki_fp_filters" "R_*"
+				(at 0 0 0)
+				(effects
+					(font
+						(size 1.27 1.27)
+					)
+					(hide yes)
+				)
+			)
+			(symbol "R_0_1"
+				(rectangle
+					(start -1.016 -2.54)
+					(end 1.016 2.54)
+					(stroke
+						(width 0.254)
+						(type default)
+					)
+					(fill
+						(type none)
+					)
+				)
+			)
+			(symbol "R_1_1"
+				(pin passive line
+					(at 0 3.81 270)
+					(length 1.27)
+					(name "~"
+						(effects
+							(font
+								(size 1.27 1.27)
+							)
+						)
+					)
+					(number "1"
+						(effects
+							(font
+								(size 1.27 1.27)
+							)
+						)
+					)
+				)
+				(pin passive line
+					(at 0 -3.81 90)
+					(length 1.27)
+					(name "~"
+						(effects
+							(font
+								(size 1.27 1.27)
+							)
+						)
+					)
+					(number "2"
+						(effects
+							(font
+								(size 1.27 1.27)
+							)
+						)
+					)
+				)
+			)
+			(embedded_fonts no)
+		)
+	)
+	(junction
+		(at 110.49 60.96)
+		(diameter 0)
+		(color 0 0 0 0)
+		(uuid "0f9a8b7c-6d5e-4f3a-8b2c-1d0e9f8a7b6c")
+	)
+	(no_connect
+		(at 127 69.85)
+		(uuid "1a2b3c4d-5e6f-4a7b-8c9d-0e1f2a3b4c5d")
+	)
+	(wire
+		(pts
+			(xy 100.33 60.96) (xy 110.49 60.96)
+		)
+		(stroke
+			(width 0)
+			(type default)
+		)
+		(uuid "2b3c4d5e-6f7a-4b8c-9d0e-1f2a3b4c5d6e")
+	)
+	(polyline
+		(pts
+			(xy 90.17 45.72) (xy 130.81 45.72)
+		)
+		(stroke
+			(width 0)
+			(type dash)
+		)
+		(uuid "3c4d5e6f-7a8b-4c9d-8e1f-2a3b4c5d6e7f")
+	)
+	(text "Pull-up for the LED"
+		(exclude_from_sim no)
+		(at 90.17 43.18 0)
+		(effects
+			(font
+				(size 1.27 1.27)
+			)
+			(justify left bottom)
+		)
+		(uuid "4d5e6f7a-8b9c-4d0e-9f2a-3b4c5d6e7f80")
+	)
+	(label "LED"
+		(at 110.49 60.96 0)
+		(fields_autoplaced yes)
+		(effects
+			(font
+				(size 1.27 1.27)
+			)
+			(justify left bottom)
+		)
+		(uuid "5e6f7a8b-9c0d-4e1f-8a3b-4c5d6e7f8091")
+	)
+	(global_label "VCC"
+		(shape input)
+		(at 100.33 50.8 180)
+		(fields_autoplaced yes)
+		(effects
+			(font
+				(size 1.27 1.27)
+			)
+			(justify right)
+		)
+		(uuid "6f7a8b9c-0d1e-4f2a-9b4c-5d6e7f8091a2")
+		(property "Intersheetrefs" "${INTERSHEET_REFS}"
+			(at 93.3454 50.8 0)
+			(effects
+				(font
+					(size 1.27 1.27)
+				)
+				(justify right)
+				(hide yes)
+			)
+		)
+	)
+	(hierarchical_label "TRIG"
+		(shape output)
+		(at 127 60.96 0)
+		(effects
+			(font
+				(size 1.27 1.27)
+			)
+			(justify left)
+		)
+		(uuid "7a8b9c0d-1e2f-4a3b-8c5d-6e7f8091a2b3")
+	)
+	(symbol
+		(lib_id "Device:R")
+		(at 100.33 55.88 90)
+		(mirror x)
+		(unit 1)
+		(exclude_from_sim no)
+		(in_bom yes)
+		(on_board yes)
+		(dnp no)
+		(fields_autoplaced yes)
+		(uuid "8b9c0d1e-2f3a-4b4c-9d6e-7f8091a2b3c4")
+		(property "Reference" "R1"
+			(at 105.41 55.88 90)
+			(effects
+				(font
+					(size 1.27 1.27)
+				)
+			)
+		)
+		(property "Value" "330"
+			(at 102.87 55.88 90)
+			(effects
+				(font
+					(size 1.27 1.27)
+				)
+			)
+		)
+		(property "Footprint" "Resistor_SMD:R_0603_1608Metric"
+			(at 100.33 57.658 90)
+			(effects
+				(font
+					(size 1.27 1.27)
+				)
+				(hide yes)
+			)
+		)
+		(pin "1"
+			(uuid "9c0d1e2f-3a4b-4c5d-8e7f-8091a2b3c4d5")
+		)
+		(pin "2"
+			(uuid "0d1e2f3a-4b5c-4d6e-9f80-91a2b3c4d5e6")
+		)
+		(instances
+			(project "blinker"
+				(path "/8a1c3f5e-2b7d-4e0a-9c61-3f2e5d7b9a10"
+					(reference "R1")
+					(unit 1)
+				)
+			)
+		)
+	)
+	(sheet
+		(at 140.97 50.8)
+		(size 25.4 12.7)
+		(exclude_from_sim no)
+		(in_bom yes)
+		(on_board yes)
+		(dnp no)
+		(fields_autoplaced yes)
+		(stroke
+			(width 0.1524)
+			(type solid)
+		)
+		(fill
+			(color 0 0 0 0.0000)
+		)
+		(uuid "1e2f3a4b-5c6d-4e7f-8091-a2b3c4d5e6f7")
+		(property "Sheetname" "Timer"
+			(at 140.97 50.0884 0)
+			(effects
+				(font
+					(size 1.27 1.27)
+				)
+				(justify left bottom)
+			)
+		)
+		(property "Sheetfile" "timer.kicad_sch"
+			(at 140.97 64.0846 0)
+			(effects
+				(font
+					(size 1.27 1.27)
+				)
+				(justify left top)
+			)
+		)
+		(pin "TRIG" output
+			(at 166.37 55.88 0)
+			(uuid "2f3a4b5c-6d7e-4f80-91a2-b3c4d5e6f708")
+			(effects
+				(font
+					(size 1.27 1.27)
+				)
+				(justify right)
+			)
+		)
+		(instances
+			(project "blinker"
+				(path "/8a1c3f5e-2b7d-4e0a-9c61-3f2e5d7b9a10"
+					(page "2")
+				)
+			)
+		)
+	)
+	(sheet_instances
+		(path "/"
+			(page "1")
+		)
+	)
+	(embedded_fonts no)
+)
+"#;
+
+#[test]
+fn unmodified_schematics_are_written_back_unchanged() {
+    let written = KicadSch::from_string(SCHEMATIC).unwrap().to_string();
+    assert_eq!(written, SCHEMATIC);
+}
+
+#[test]
+fn items_are_written_in_the_order_of_kicad() {
+    let root = parser::parse(SCHEMATIC).unwrap();
+    let nodes = root.args().iter().map(ToString::to_string).collect::<Vec<_>>();
+    let first = root.args().iter().position(|node| node.is_list("junction")).unwrap();
+    let last = root.args().iter().position(|node| node.is_list("sheet_instances")).unwrap();
+    let reversed = |items: &mut Vec<String>| {
+        items.reverse();
+        let nodes = [&nodes[..first], items, &nodes[last..]].concat();
+        KicadSch::from_string(format!("(kicad_sch {})", nodes.join(" "))).unwrap().to_string()
+    };
+    let mut items = nodes[first..last].to_vec();
+    assert_eq!(reversed(&mut items), SCHEMATIC);
+
+    // Kinds of items KiCad knows but not this crate go in their place too
+    let flag = "(netclass_flag \"\" (length 2.54) (shape round) (at 127 63.5 0)
+        (effects (font (size 1.27 1.27)) (justify left bottom))
+        (uuid \"3b0b7c1e-5d2a-4f8e-9a6c-1e4d7f2a8b35\"))";
+    items.insert(0, flag.to_string());
+    let written = parser::parse(&reversed(&mut items)).unwrap();
+    let kinds = written.args().iter().filter_map(|node| node.name()).collect::<Vec<_>>();
+    assert_eq!(kinds[6..], [
+        "lib_symbols",
+        "junction",
+        "no_connect",
+        "wire",
+        "polyline",
+        "text",
+        "label",
+        "global_label",
+        "hierarchical_label",
+        "netclass_flag",
+        "symbol",
+        "sheet",
+        "sheet_instances",
+        "embedded_fonts",
+    ]);
+}
+
+#[test]
+fn ansi_paper_sizes_are_read() {
+    let schematic = KicadSch::from_string(SCHEMATIC.replace("(paper \"A4\")", "(paper \"D\")"));
+    let page = schematic.unwrap().page();
+    assert_eq!(page.paper, Paper::AnsiD);
+    assert_eq!(page.size(), (Coord::from_mm(863.6), Coord::from_mm(558.8)));
+
+    let gerber = SCHEMATIC.replace("(paper \"A4\")", "(paper \"GERBER\" portrait)");
+    let written = KicadSch::from_string(&gerber).unwrap().to_string();
+    assert!(written.contains("(paper \"GERBER\" portrait)"), "{written}");
+}
+
+#[test]
+fn unreadable_schematics_are_rejected() {
+    // The placed resistor is not cached
+    let renamed = SCHEMATIC.replace("(symbol \"Device:R\"\n", "(symbol \"Device:C\"\n");
+    let error = KicadSch::from_string(renamed).unwrap_err();
+    assert!(error.to_string().contains("Device:R"), "{error}");
+    let old = SCHEMATIC.replace("(version 20250114)", "(version 20211123)");
+    assert!(KicadSch::from_string(old).is_err());
+}
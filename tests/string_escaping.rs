@@ -0,0 +1,81 @@
+use kicad_generator::{parser::{self, Quoted},
+                      schematic::symbol_library::SymbolLibrary};
+
+// Descriptions as they come out of JLCPCB/LCSC part listings
+const DESCRIPTION: &str = "Timers & Support Products \\\"555\\\" 100kHz 4.5V~16V\\nSOIC-8_150mil";
+const DATASHEET: &str =
+    "https://www.lcsc.com/datasheet/lcsc_datasheet_1809261820_Texas-Instruments-NE555DR_C7593.pdf";
+
+fn library() -> String {
+    format!(
+        r#"(kicad_symbol_lib
+	(version 20241209)
+	(generator "easyeda2kicad")
+	(generator_version "0.8.0")
+	(symbol "NE555DR"
+		(in_bom yes)
+		(on_board yes)
+		(property "Reference" "U" (at 0 8.89 0) (effects (font (size 1.27 1.27))))
+		(property "Value" "NE555DR" (at 0 -8.89 0) (effects (font (size 1.27 1.27))))
+		(property "Datasheet" "{DATASHEET}" (at 0 0 0) (effects (font (size 1.27 1.27)) (hide yes)))
+		(property "Description" "{DESCRIPTION}" (at 0 0 0) (effects (font (size 1.27 1.27)) (hide yes)))
+		(property "LCSC Part" "C7593" (at 0 0 0) (effects (font (size 1.27 1.27)) (hide yes)))
+		(property "Footprint" "Package_SO:SOIC-8_3.9x4.9mm_P1.27mm\\Gull wing" (at 0 0 0) (effects (font (size 1.27 1.27)) (hide yes)))
+		(symbol "NE555DR_1_1"
+			(text "\"DIS\" pin \\ open drain" (at 0 0 0) (effects (font (size 1.27 1.27))))
+			(pin input line (at -12.7 5.08 0) (length 3.81)
+				(name "\"TR\"" (effects (font (size 1.27 1.27))))
+				(number "2" (effects (font (size 1.27 1.27))))
+				(alternate "TRIG\n(low)" input inverted)
+			)
+		)
+	)
+)"#
+    )
+}
+
+#[test]
+fn properties_are_unescaped_when_read() {
+    let library = SymbolLibrary::from_string(library(), "JLCPCB".to_string()).unwrap();
+    let symbol = &library.symbols[0];
+
+    assert_eq!(
+        symbol.property("Description"),
+        Some("Timers & Support Products \"555\" 100kHz 4.5V~16V\nSOIC-8_150mil")
+    );
+    assert_eq!(symbol.property("Datasheet"), Some(DATASHEET));
+    assert_eq!(
+        symbol.property("Footprint"),
+        Some("Package_SO:SOIC-8_3.9x4.9mm_P1.27mm\\Gull wing")
+    );
+}
+
+#[test]
+fn escaped_strings_survive_a_round_trip() {
+    let library = SymbolLibrary::from_string(library(), "JLCPCB".to_string()).unwrap();
+    let written = library.to_string();
+
+    assert!(written.contains(&format!("\"{DESCRIPTION}\"")));
+    assert!(written.contains(r#"(name "\"TR\"""#));
+    assert!(written.contains(r#"(alternate "TRIG\n(low)" input inverted)"#));
+    assert!(written.contains(r#"(text "\"DIS\" pin \\ open drain""#));
+    assert!(written.contains(r#""Package_SO:SOIC-8_3.9x4.9mm_P1.27mm\\Gull wing""#));
+
+    let reread = SymbolLibrary::from_string(&written, "JLCPCB".to_string()).unwrap();
+    assert!(reread.symbols == library.symbols);
+    assert_eq!(reread.to_string(), written);
+}
+
+#[test]
+fn quoted_strings_escape_quotes_backslashes_and_newlines() {
+    let value = "3.5\" TFT\\LCD\r\nST7796S";
+    let quoted = Quoted(value).to_string();
+    assert_eq!(quoted, r#""3.5\" TFT\\LCD\r\nST7796S""#);
+    assert_eq!(parser::parse(&quoted).unwrap().expect_string().unwrap(), value);
+}
+
+#[test]
+fn unknown_escape_sequences_are_kept() {
+    let node = parser::parse(r#""C:\Users\kicad \x41\101""#).unwrap();
+    assert_eq!(node.expect_string().unwrap(), "C:\\Users\\kicad AA");
+}
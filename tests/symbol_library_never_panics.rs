@@ -0,0 +1,67 @@
+use kicad_generator::schematic::symbol_library::SymbolLibrary;
+use proptest::{collection::vec, prelude::*, sample::select};
+
+const LIBRARY: &str = r#"(kicad_symbol_lib (version 20211014) (generator kicad_symbol_editor)
+  (symbol "LM358" (pin_names (offset 0.127)) (in_bom yes) (on_board yes)
+    (property "Reference" "U" (id 0) (at 0 5.08 0)
+      (effects (font (size 1.27 1.27) italic) (justify left) hide)
+    )
+    (symbol "LM358_1_1"
+      (arc (start 0 0) (mid 1 1) (end 2 0)
+        (stroke (width 0.254) (type dash_dot) (color 0 0 0 0))
+        (fill (type background))
+      )
+      (polyline (pts (xy -5.08 5.08) (xy 5.08 0)) (stroke (width 0) (type default)) (fill (type none)))
+      (pin output line (at 7.62 0 180) (length 2.54) hide
+        (name "~" (effects (font (size 1.27 1.27))))
+        (number "1" (effects (font (size 1.27 1.27))))
+        (alternate "OUT\"A\"" output inverted)
+      )
+    )
+  )
+)"#;
+
+const TOKENS: &[&str] = &[
+    "(", ")", "\"", "\\", " ", "\n", "hide", "yes", "no", "symbol", "pin", "at", "-1e999", "0.5e3",
+    "é", "18446744073709551616", "(xy 1 2)", "(hide yes)", "(version 1)",
+];
+
+/// The library with some bytes removed and some tokens inserted.
+fn mutated_library() -> impl Strategy<Value = String> {
+    let edit = (0..LIBRARY.len(), 0..4usize, select(TOKENS));
+    vec(edit, 1..8).prop_map(|edits| {
+        let mut src = LIBRARY.to_string();
+        for (at, removed, token) in edits {
+            let at = src.floor_char_boundary(at.min(src.len()));
+            let end = src.floor_char_boundary((at + removed).min(src.len()));
+            src.replace_range(at..end, token);
+        }
+        src
+    })
+}
+
+fn check(src: &str) -> Result<(), TestCaseError> {
+    if let Ok(library) = SymbolLibrary::from_string(src, "Fuzz".to_string()) {
+        let written = library.to_string();
+        let reread = SymbolLibrary::from_string(&written, "Fuzz".to_string());
+        prop_assert!(reread.is_ok(), "written library does not read back: {written}");
+    }
+    Ok(())
+}
+
+#[test]
+fn the_base_library_reads() {
+    assert!(SymbolLibrary::from_string(LIBRARY, "Fuzz".to_string()).is_ok());
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_input_never_panics(src in any::<String>()) {
+        check(&src)?;
+    }
+
+    #[test]
+    fn corrupted_libraries_never_panic(src in mutated_library()) {
+        check(&src)?;
+    }
+}